@@ -0,0 +1,120 @@
+//! Access to the Windows high-resolution performance counter.
+
+use std::fmt;
+
+use serde::Serialize;
+
+#[cfg(windows)]
+pub fn get_qpc_timestamp() -> windows::core::Result<i64> {
+    let mut timestamp: i64 = 0;
+    unsafe {
+        windows::Win32::System::Performance::QueryPerformanceCounter(&mut timestamp)?;
+    }
+    Ok(timestamp)
+}
+
+/// Returns the number of QueryPerformanceCounter ticks per second.
+#[cfg(windows)]
+pub fn get_qpc_frequency() -> windows::core::Result<i64> {
+    let mut frequency: i64 = 0;
+    unsafe {
+        windows::Win32::System::Performance::QueryPerformanceFrequency(&mut frequency)?;
+    }
+    Ok(frequency)
+}
+
+/// Converts a QPC tick delta into nanoseconds.
+///
+/// The multiplication is done in 128 bits so long captures don't overflow.
+pub fn qpc_to_ns(ticks: i64, frequency: i64) -> i64 {
+    (ticks as i128 * 1_000_000_000 / frequency as i128) as i64
+}
+
+/// How far the QPC rate may deviate from the system clock's before the
+/// self-test fails.
+pub const QPC_RATE_TOLERANCE: f64 = 0.005;
+
+/// QPC frequencies of the legacy platform timers (ACPI PM timer, HPET) that
+/// Windows falls back to without an invariant TSC, or under some hypervisors.
+const PLATFORM_TIMER_FREQUENCIES: [i64; 2] = [3_579_545, 14_318_180];
+
+/// Result of [`qpc_self_test`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QpcCheck {
+    /// As reported by `QueryPerformanceFrequency`, in Hz.
+    pub frequency: i64,
+    /// QPC ticks per second of system time over the test.
+    pub measured_frequency: f64,
+    /// Reads back-to-back.
+    pub samples: u64,
+    /// Reads that returned a smaller value than the one before.
+    pub backward_steps: u64,
+    /// Longest gap between two consecutive reads, in ns. Large gaps while
+    /// spinning hint at a preempted (virtual) CPU.
+    pub max_gap_ns: i64,
+}
+
+impl QpcCheck {
+    /// Whether QPC ran monotonically and at its reported rate.
+    pub fn passed(&self) -> bool {
+        let deviation =
+            (self.measured_frequency - self.frequency as f64).abs() / self.frequency as f64;
+        self.backward_steps == 0 && deviation <= QPC_RATE_TOLERANCE
+    }
+
+    /// Whether QPC is backed by a legacy platform timer rather than the TSC,
+    /// making it coarser and slower to read.
+    pub fn is_platform_timer(&self) -> bool {
+        PLATFORM_TIMER_FREQUENCIES.contains(&self.frequency)
+    }
+}
+
+impl fmt::Display for QpcCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "QPC self-test: {:.6} MHz reported, {:.6} MHz measured, {} backward steps in {} reads, longest gap {:.3} ms: {}",
+            self.frequency as f64 / 1e6,
+            self.measured_frequency / 1e6,
+            self.backward_steps,
+            self.samples,
+            self.max_gap_ns as f64 / 1e6,
+            if self.passed() { "OK" } else { "FAILED" }
+        )
+    }
+}
+
+/// Reads QPC back-to-back for `duration`, checking that it never goes
+/// backwards and that it advances at the rate `QueryPerformanceFrequency`
+/// claims, measured against the system clock.
+#[cfg(windows)]
+pub fn qpc_self_test(duration: std::time::Duration) -> windows::core::Result<QpcCheck> {
+    use std::time::SystemTime;
+
+    let frequency = get_qpc_frequency()?;
+    let start_time = SystemTime::now();
+    let start = get_qpc_timestamp()?;
+
+    let mut last = start;
+    let mut samples = 0;
+    let mut backward_steps = 0;
+    let mut max_gap = 0;
+    while start_time.elapsed().unwrap_or_default() < duration {
+        let now = get_qpc_timestamp()?;
+        samples += 1;
+        if now < last {
+            backward_steps += 1;
+        }
+        max_gap = max_gap.max(now - last);
+        last = now;
+    }
+    let elapsed = start_time.elapsed().unwrap_or_default().as_secs_f64();
+
+    Ok(QpcCheck {
+        frequency,
+        measured_frequency: (last - start) as f64 / elapsed,
+        samples,
+        backward_steps,
+        max_gap_ns: qpc_to_ns(max_gap, frequency),
+    })
+}
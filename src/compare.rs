@@ -0,0 +1,311 @@
+//! Side-by-side statistics of two captures, e.g. before and after a driver
+//! update, flagging where the second got worse.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    stats::{self, IntervalSummary},
+    EventType, VBlankRecord,
+};
+
+/// Relative worsening from the first capture to the second that counts as a
+/// regression by default: 10%.
+pub const DEFAULT_REGRESSION_THRESHOLD: f64 = 0.1;
+
+/// Statistics of one side of a [`Comparison`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureStats {
+    pub intervals: Option<IntervalSummary>,
+    /// Frames with a `sync_qpc_time` row.
+    pub frames: usize,
+    /// Refreshes missed, summed over the `dropped_frame` rows.
+    pub dropped: i64,
+}
+
+impl CaptureStats {
+    pub fn from_records(records: &[VBlankRecord]) -> Self {
+        Self {
+            intervals: IntervalSummary::from_records(records),
+            frames: records
+                .iter()
+                .filter(|r| r.event_type == EventType::SyncQpcTime)
+                .count(),
+            dropped: records
+                .iter()
+                .filter(|r| r.event_type == EventType::DroppedFrame)
+                .map(|r| r.count)
+                .sum(),
+        }
+    }
+
+    /// Dropped refreshes per 1000 frames, so captures of different lengths
+    /// compare.
+    pub fn dropped_per_1000(&self) -> f64 {
+        if self.frames == 0 {
+            return 0.0;
+        }
+        self.dropped as f64 * 1000.0 / self.frames as f64
+    }
+
+    /// The compared metrics as `(name, value)`, all lower-is-better; interval
+    /// metrics are in ns.
+    fn metrics(&self) -> Vec<(&'static str, f64)> {
+        let mut metrics = Vec::new();
+        if let Some(summary) = &self.intervals {
+            metrics.extend([
+                ("mean", summary.mean),
+                ("jitter", summary.std_dev),
+                ("p99", summary.p99),
+                ("max", summary.max),
+            ]);
+        }
+        metrics.push(("dropped/1000", self.dropped_per_1000()));
+        metrics
+    }
+}
+
+/// A metric that is worse in the second capture by more than the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub metric: &'static str,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// How capture `b` differs from capture `a`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub a: CaptureStats,
+    pub b: CaptureStats,
+    /// Frames with a vblank interval in both captures, matched by run,
+    /// monitor and frame index.
+    pub matched_frames: usize,
+    /// Median of `b`'s interval minus `a`'s over the matched frames, in ns.
+    pub median_shift: Option<f64>,
+    /// Two-sample Kolmogorov-Smirnov statistic of the two interval
+    /// distributions: the largest gap between their cumulative distributions,
+    /// from 0 (identical) to 1 (no overlap).
+    pub ks_statistic: Option<f64>,
+    pub threshold: f64,
+    pub regressions: Vec<Regression>,
+}
+
+impl Comparison {
+    /// Compares the records of two captures. A metric regresses when `b`'s
+    /// value exceeds `a`'s by more than `threshold`, as a fraction of `a`'s.
+    pub fn new(a: &[VBlankRecord], b: &[VBlankRecord], threshold: f64) -> Self {
+        let stats_a = CaptureStats::from_records(a);
+        let stats_b = CaptureStats::from_records(b);
+
+        let metrics_b: HashMap<_, _> = stats_b.metrics().into_iter().collect();
+        let regressions = stats_a
+            .metrics()
+            .into_iter()
+            .filter_map(|(metric, a)| {
+                let b = *metrics_b.get(metric)?;
+                (b > a * (1.0 + threshold)).then_some(Regression { metric, a, b })
+            })
+            .collect();
+
+        let intervals_a = frame_intervals(a);
+        let mut shifts: Vec<f64> = frame_intervals(b)
+            .iter()
+            .filter_map(|(frame, b)| Some(b - intervals_a.get(frame)?))
+            .collect();
+        shifts.sort_by(f64::total_cmp);
+
+        Self {
+            a: stats_a,
+            b: stats_b,
+            matched_frames: shifts.len(),
+            median_shift: (!shifts.is_empty()).then(|| stats::quantile(&shifts, 0.5)),
+            ks_statistic: ks_statistic(stats::vblank_intervals(a), stats::vblank_intervals(b)),
+            threshold,
+            regressions,
+        }
+    }
+}
+
+/// The vblank interval ending at each frame, keyed by run, monitor and frame
+/// index. Uses the same pairing as [`stats::vblank_intervals`].
+fn frame_intervals(records: &[VBlankRecord]) -> HashMap<(u32, u32, i64), f64> {
+    let mut intervals = HashMap::new();
+    let mut last: HashMap<(u32, u32), &VBlankRecord> = HashMap::new();
+
+    for record in records
+        .iter()
+        .filter(|r| r.event_type == EventType::SyncQpcTime)
+    {
+        let key = (record.run_id, record.monitor_id);
+        if let Some(prev) = last.get(&key) {
+            if record.count == prev.count {
+                continue;
+            }
+            intervals.insert(
+                (record.run_id, record.monitor_id, record.frame_index),
+                (record.timestamp - prev.timestamp) as f64,
+            );
+        }
+        last.insert(key, record);
+    }
+
+    intervals
+}
+
+fn ks_statistic(mut a: Vec<f64>, mut b: Vec<f64>) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    a.sort_by(f64::total_cmp);
+    b.sort_by(f64::total_cmp);
+
+    let (mut i, mut j, mut max_gap) = (0, 0, 0.0f64);
+    while i < a.len() && j < b.len() {
+        let x = a[i].min(b[j]);
+        while i < a.len() && a[i] <= x {
+            i += 1;
+        }
+        while j < b.len() && b[j] <= x {
+            j += 1;
+        }
+        max_gap = max_gap.max((i as f64 / a.len() as f64 - j as f64 / b.len() as f64).abs());
+    }
+    Some(max_gap)
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<14} {:>12} {:>12} {:>9}", "", "a", "b", "change")?;
+        let metrics_b: HashMap<_, _> = self.b.metrics().into_iter().collect();
+        for (metric, a) in self.a.metrics() {
+            let Some(&b) = metrics_b.get(metric) else {
+                continue;
+            };
+            let (a_text, b_text) = if metric == "dropped/1000" {
+                (format!("{:.2}", a), format!("{:.2}", b))
+            } else {
+                (format!("{:.3} ms", a / 1e6), format!("{:.3} ms", b / 1e6))
+            };
+            let change = if a == 0.0 {
+                String::new()
+            } else {
+                format!("{:+.1}%", (b / a - 1.0) * 100.0)
+            };
+            let flag = if self.regressions.iter().any(|r| r.metric == metric) {
+                "  REGRESSION"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "{:<14} {:>12} {:>12} {:>9}{}",
+                metric, a_text, b_text, change, flag
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<14} {:>12} {:>12}",
+            "frames", self.a.frames, self.b.frames
+        )?;
+        writeln!(
+            f,
+            "{:<14} {:>12} {:>12}",
+            "dropped", self.a.dropped, self.b.dropped
+        )?;
+
+        match self.median_shift {
+            Some(shift) => writeln!(
+                f,
+                "Matched frames: {}, median interval shift {:+.3} ms",
+                self.matched_frames,
+                shift / 1e6
+            )?,
+            None => writeln!(f, "Matched frames: none")?,
+        }
+        if let Some(ks) = self.ks_statistic {
+            writeln!(
+                f,
+                "Distribution shift: {:.3} (Kolmogorov-Smirnov, 0 = identical)",
+                ks
+            )?;
+        }
+        match self.regressions.len() {
+            0 => write!(f, "No regressions beyond {:.0}%", self.threshold * 100.0),
+            n => write!(f, "{} regressions beyond {:.0}%", n, self.threshold * 100.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vblanks of one run after the given intervals, in ns.
+    fn capture(intervals: impl IntoIterator<Item = i64>) -> Vec<VBlankRecord> {
+        let vblank = |timestamp, frame_index| VBlankRecord {
+            timestamp,
+            count: frame_index,
+            event_type: EventType::SyncQpcTime,
+            frame_index,
+            lit: true,
+            run_id: 0,
+            monitor_id: 0,
+        };
+        let mut timestamp = 0;
+        let mut records = vec![vblank(0, 0)];
+        for (i, interval) in intervals.into_iter().enumerate() {
+            timestamp += interval;
+            records.push(vblank(timestamp, i as i64 + 1));
+        }
+        records
+    }
+
+    /// Intervals alternating between 16 and 17 ms, plus `offset`.
+    fn alternating(offset: i64) -> impl Iterator<Item = i64> {
+        (0..100).map(move |i| 16_000_000 + (i % 2) * 1_000_000 + offset)
+    }
+
+    #[test]
+    fn identical_captures_do_not_differ() {
+        let records = capture(alternating(0));
+        let comparison = Comparison::new(&records, &records, DEFAULT_REGRESSION_THRESHOLD);
+
+        assert_eq!(comparison.ks_statistic, Some(0.0));
+        assert_eq!(comparison.matched_frames, 100);
+        assert_eq!(comparison.median_shift, Some(0.0));
+        assert!(comparison.regressions.is_empty());
+    }
+
+    #[test]
+    fn longer_intervals_are_flagged() {
+        let a = capture(alternating(0));
+        let b = capture(alternating(2_000_000));
+        let comparison = Comparison::new(&a, &b, DEFAULT_REGRESSION_THRESHOLD);
+
+        // every interval of b is longer than all of a's
+        assert_eq!(comparison.ks_statistic, Some(1.0));
+        assert_eq!(comparison.median_shift, Some(2_000_000.0));
+        let regressed: Vec<_> = comparison.regressions.iter().map(|r| r.metric).collect();
+        assert_eq!(regressed, ["mean", "p99", "max"]);
+
+        // but not past a higher threshold
+        assert!(Comparison::new(&a, &b, 0.2).regressions.is_empty());
+    }
+
+    #[test]
+    fn empty_captures_have_no_distributions() {
+        let comparison = Comparison::new(&[], &[], DEFAULT_REGRESSION_THRESHOLD);
+
+        assert_eq!(comparison.a.intervals, None);
+        assert_eq!(comparison.ks_statistic, None);
+        assert_eq!(comparison.median_shift, None);
+        assert_eq!(comparison.matched_frames, 0);
+        assert!(comparison.regressions.is_empty());
+
+        let records = capture(alternating(0));
+        assert_eq!(
+            Comparison::new(&records, &[], DEFAULT_REGRESSION_THRESHOLD).ks_statistic,
+            None
+        );
+    }
+}
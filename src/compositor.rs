@@ -0,0 +1,111 @@
+//! Keeping the compositor out of the present path.
+//!
+//! With fullscreen optimizations, Windows 10 and 11 run fullscreen and
+//! borderless windows through the compositor while they look like they own
+//! the screen, which adds latency without any sign in the window itself.
+//! They can be turned off per executable with the compatibility layer behind
+//! the "Disable fullscreen optimizations" checkbox in its properties. Whether
+//! presents then flip independently of the compositor shows in the
+//! `present_path` rows. winit already makes the process per-monitor DPI
+//! aware, so DPI scaling doesn't force composition either.
+
+use std::io;
+
+use serde::Serialize;
+
+/// What [`disable_fullscreen_optimizations`] found, as recorded in the
+/// metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FullscreenOptimizations {
+    /// The layer was already set for this executable, so this process
+    /// started with fullscreen optimizations off.
+    Disabled,
+    /// The layer was set just now. Windows applies it when a process starts,
+    /// so it only takes effect from the next launch.
+    DisabledFromNextLaunch,
+}
+
+/// The compatibility layer behind the checkbox.
+#[cfg(windows)]
+const DISABLE_LAYER: &str = "DISABLEDXMAXIMIZEDWINDOWEDMODE";
+
+/// Sets the compatibility layer that turns fullscreen optimizations off for
+/// this executable, for the current user, keeping any other layers it has.
+#[cfg(windows)]
+pub fn disable_fullscreen_optimizations() -> io::Result<FullscreenOptimizations> {
+    use windows::{
+        core::{w, HSTRING, PCWSTR},
+        Win32::System::Registry::{
+            RegCloseKey, RegCreateKeyExW, RegQueryValueExW, RegSetValueExW, HKEY,
+            HKEY_CURRENT_USER, KEY_QUERY_VALUE, KEY_SET_VALUE, REG_OPTION_NON_VOLATILE, REG_SZ,
+        },
+    };
+
+    let exe = HSTRING::from(std::env::current_exe()?.as_os_str());
+    let mut key = HKEY::default();
+    unsafe {
+        RegCreateKeyExW(
+            HKEY_CURRENT_USER,
+            w!("Software\\Microsoft\\Windows NT\\CurrentVersion\\AppCompatFlags\\Layers"),
+            0,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_QUERY_VALUE | KEY_SET_VALUE,
+            None,
+            &mut key,
+            None,
+        )
+        .ok()?;
+    }
+
+    let result = (|| -> io::Result<FullscreenOptimizations> {
+        // a missing value just means no layers yet
+        let mut layers = vec![0u16; 1024];
+        let mut len = (layers.len() * 2) as u32;
+        let read = unsafe {
+            RegQueryValueExW(
+                key,
+                &exe,
+                None,
+                None,
+                Some(layers.as_mut_ptr().cast()),
+                Some(&mut len),
+            )
+        };
+        layers.truncate(if read.is_ok() { len as usize / 2 } else { 0 });
+        let layers = String::from_utf16_lossy(&layers);
+        let layers = layers.trim_end_matches('\0').trim();
+
+        if layers
+            .split_whitespace()
+            .any(|layer| layer == DISABLE_LAYER)
+        {
+            return Ok(FullscreenOptimizations::Disabled);
+        }
+        // the value is `~` followed by the layers, separated by spaces
+        let layers = match layers {
+            "" | "~" => format!("~ {}", DISABLE_LAYER),
+            layers => format!("{} {}", layers, DISABLE_LAYER),
+        };
+        let data: Vec<u8> = layers
+            .encode_utf16()
+            .chain(Some(0))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        unsafe { RegSetValueExW(key, &exe, 0, REG_SZ, Some(&data)).ok()? };
+        Ok(FullscreenOptimizations::DisabledFromNextLaunch)
+    })();
+    unsafe {
+        let _ = RegCloseKey(key);
+    }
+    result
+}
+
+#[cfg(not(windows))]
+pub fn disable_fullscreen_optimizations() -> io::Result<FullscreenOptimizations> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
@@ -0,0 +1,202 @@
+//! The display's EDID, for telling which physical panel a capture came from.
+//!
+//! Monitor names from the OS are often generic ("Generic PnP Monitor"); the
+//! EDID carries the manufacturer, model and serial number the panel reports
+//! about itself. Reading it is Windows only, decoding works anywhere.
+
+use serde::Serialize;
+use winit::monitor::MonitorHandle;
+
+/// What a capture records from the EDID base block.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edid {
+    /// Three-letter PNP vendor ID, e.g. `DEL` or `SAM`.
+    pub manufacturer: String,
+    /// Manufacturer's product code.
+    pub product_code: u16,
+    /// Numeric serial number; 0 if the panel doesn't set one.
+    pub serial_number: u32,
+    /// Model name from the display name descriptor.
+    pub model: Option<String>,
+    /// Serial number from the serial string descriptor, usually what is
+    /// printed on the panel.
+    pub serial: Option<String>,
+    pub manufacture_year: u16,
+    /// Week of manufacture, 1 to 54; 0 if not given.
+    pub manufacture_week: u8,
+}
+
+impl Edid {
+    /// Decodes the 128-byte base block of `bytes`, or `None` if it isn't
+    /// one: wrong header or checksum.
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+        let block = bytes.get(..128)?;
+        if block[..8] != HEADER || block.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+            return None;
+        }
+
+        // five bits per letter, 1 is 'A'
+        let id = u16::from_be_bytes([block[8], block[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|shift| char::from(b'A' - 1 + ((id >> shift) & 0x1f) as u8))
+            .collect();
+
+        let mut model = None;
+        let mut serial = None;
+        // four 18-byte descriptors; display descriptors start with three zeros
+        for descriptor in block[54..126].chunks(18) {
+            if descriptor[..3] != [0, 0, 0] {
+                continue;
+            }
+            let text = || {
+                let text = String::from_utf8_lossy(&descriptor[5..]);
+                Some(text.split('\n').next()?.trim().to_string()).filter(|t| !t.is_empty())
+            };
+            match descriptor[3] {
+                0xfc => model = text(),
+                0xff => serial = text(),
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            manufacturer,
+            product_code: u16::from_le_bytes([block[10], block[11]]),
+            serial_number: u32::from_le_bytes([block[12], block[13], block[14], block[15]]),
+            model,
+            serial,
+            manufacture_year: 1990 + block[17] as u16,
+            manufacture_week: block[16],
+        })
+    }
+}
+
+/// Reads and decodes the EDID of `monitor`, warning if that fails.
+#[cfg(windows)]
+pub fn monitor_edid(monitor: &MonitorHandle) -> Option<Edid> {
+    match read_edid(monitor) {
+        Ok(bytes) => {
+            let edid = Edid::parse(&bytes);
+            if edid.is_none() {
+                log::warn!("The EDID of {:?} is malformed", monitor.name());
+            }
+            edid
+        }
+        Err(e) => {
+            log::warn!("Failed to read the EDID of {:?}: {}", monitor.name(), e);
+            None
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn monitor_edid(_monitor: &MonitorHandle) -> Option<Edid> {
+    None
+}
+
+/// Reads the raw EDID of `monitor` from the registry key of its device.
+///
+/// The display adapter output winit knows the monitor by (`\\.\DISPLAY1`)
+/// names the monitor's device interface, whose instance ID identifies the
+/// monitor among the devices of the monitor class.
+#[cfg(windows)]
+pub fn read_edid(monitor: &MonitorHandle) -> windows::core::Result<Vec<u8>> {
+    use windows::{
+        core::{w, HSTRING, PCWSTR},
+        Win32::{
+            Devices::DeviceAndDriverInstallation::{
+                SetupDiDestroyDeviceInfoList, SetupDiEnumDeviceInfo, SetupDiGetClassDevsW,
+                SetupDiGetDeviceInstanceIdW, SetupDiOpenDevRegKey, DICS_FLAG_GLOBAL, DIGCF_PRESENT,
+                DIREG_DEV, GUID_DEVCLASS_MONITOR, SP_DEVINFO_DATA,
+            },
+            Foundation::{ERROR_NOT_FOUND, HWND},
+            Graphics::Gdi::{EnumDisplayDevicesW, DISPLAY_DEVICEW},
+            System::Registry::{RegCloseKey, RegQueryValueExW, KEY_READ},
+        },
+    };
+    use winit::platform::windows::MonitorHandleExtWindows;
+
+    /// Makes `EnumDisplayDevicesW` report the device interface path.
+    const EDD_GET_DEVICE_INTERFACE_NAME: u32 = 1;
+
+    let utf16 = |wide: &[u16]| {
+        let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+        String::from_utf16_lossy(&wide[..len])
+    };
+
+    let mut device = DISPLAY_DEVICEW {
+        cb: std::mem::size_of::<DISPLAY_DEVICEW>() as u32,
+        ..Default::default()
+    };
+    let output = HSTRING::from(monitor.native_id());
+    if !unsafe { EnumDisplayDevicesW(&output, 0, &mut device, EDD_GET_DEVICE_INTERFACE_NAME) }
+        .as_bool()
+    {
+        return Err(ERROR_NOT_FOUND.into());
+    }
+    // `\\?\DISPLAY#DEL40F3#5&1a2b3c&0&UID4352#{e6f07b5f-...}` is the interface
+    // of instance `DISPLAY\DEL40F3\5&1a2b3c&0&UID4352`
+    let interface = utf16(&device.DeviceID);
+    let instance = interface
+        .trim_start_matches(r"\\?\")
+        .split("#{")
+        .next()
+        .unwrap_or_default()
+        .replace('#', r"\");
+
+    let devices = unsafe {
+        SetupDiGetClassDevsW(
+            Some(&GUID_DEVCLASS_MONITOR),
+            PCWSTR::null(),
+            HWND::default(),
+            DIGCF_PRESENT,
+        )?
+    };
+    let mut result = Err(ERROR_NOT_FOUND.into());
+    let mut info = SP_DEVINFO_DATA {
+        cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
+        ..Default::default()
+    };
+    let mut index = 0;
+    while unsafe { SetupDiEnumDeviceInfo(devices, index, &mut info) }.is_ok() {
+        index += 1;
+        let mut id = [0u16; 256];
+        if unsafe { SetupDiGetDeviceInstanceIdW(devices, &info, Some(&mut id), None) }.is_err()
+            || !utf16(&id).eq_ignore_ascii_case(&instance)
+        {
+            continue;
+        }
+
+        result = unsafe {
+            SetupDiOpenDevRegKey(devices, &info, DICS_FLAG_GLOBAL.0, 0, DIREG_DEV, KEY_READ.0)
+        }
+        .and_then(|key| {
+            // EDIDs with extension blocks run to a few hundred bytes
+            let mut edid = vec![0u8; 1024];
+            let mut len = edid.len() as u32;
+            let read = unsafe {
+                RegQueryValueExW(
+                    key,
+                    w!("EDID"),
+                    None,
+                    None,
+                    Some(edid.as_mut_ptr()),
+                    Some(&mut len),
+                )
+            };
+            unsafe {
+                let _ = RegCloseKey(key);
+            }
+            read.ok()?;
+            edid.truncate(len as usize);
+            Ok(edid)
+        });
+        break;
+    }
+    unsafe {
+        let _ = SetupDiDestroyDeviceInfoList(devices);
+    }
+    result
+}
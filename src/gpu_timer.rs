@@ -0,0 +1,88 @@
+//! GPU-side duration of each frame's render pass via timestamp queries.
+
+/// Begin/end timestamps of the render pass, resolved and read back per frame.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+const QUERY_COUNT: u32 = 2;
+const BUFFER_SIZE: u64 = QUERY_COUNT as u64 * wgpu::QUERY_SIZE as u64;
+
+impl GpuTimer {
+    /// Returns `None` if the device was created without
+    /// `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("render pass timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp resolve"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("timestamp readback"),
+            size: BUFFER_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+        })
+    }
+
+    /// Timestamp writes for the start and end of the render pass.
+    pub fn timestamp_writes(&self) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        }
+    }
+
+    /// Copies the query results somewhere they can be read back from.
+    /// Must be recorded after the render pass has ended.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            BUFFER_SIZE,
+        );
+    }
+
+    /// Blocks until the frame's commands are done and returns the render pass
+    /// duration in nanoseconds.
+    pub fn read(&self, device: &wgpu::Device) -> Option<i64> {
+        let slice = self.readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| ());
+        device.poll(wgpu::PollType::Wait).ok()?;
+
+        let duration = {
+            let data = slice.get_mapped_range();
+            let begin = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            let end = u64::from_le_bytes(data[8..16].try_into().unwrap());
+            (end.wrapping_sub(begin) as f64 * self.period as f64) as i64
+        };
+        self.readback_buffer.unmap();
+
+        Some(duration)
+    }
+}
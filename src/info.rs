@@ -0,0 +1,129 @@
+//! Environment report for `--info`: what a capture could run on.
+
+use std::fmt;
+
+use serde::Serialize;
+use winit::monitor::MonitorHandle;
+
+use crate::metadata::VideoModeInfo;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdapterEntry {
+    pub name: String,
+    pub backend: String,
+    pub device_type: String,
+    pub timestamp_query: bool,
+    pub vulkan_display_timing: bool,
+    /// Present modes supported for a window on this adapter; empty if it
+    /// can't present to the probe surface.
+    pub present_modes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorEntry {
+    /// What `--monitor` accepts to select this one.
+    pub index: usize,
+    pub name: Option<String>,
+    pub refresh_rate_millihertz: Option<u32>,
+    pub video_modes: Vec<VideoModeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub adapters: Vec<AdapterEntry>,
+    pub monitors: Vec<MonitorEntry>,
+}
+
+impl SystemInfo {
+    /// Lists every adapter of `instance` and the given monitors. `surface`,
+    /// if given, is used to query the present modes of each adapter.
+    pub fn collect(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+        monitors: impl IntoIterator<Item = MonitorHandle>,
+    ) -> Self {
+        let adapters = instance
+            .enumerate_adapters(wgpu::Backends::all())
+            .into_iter()
+            .map(|adapter| {
+                let info = adapter.get_info();
+                let features = adapter.features();
+                let present_modes = surface.map_or(Vec::new(), |s| {
+                    s.get_capabilities(&adapter)
+                        .present_modes
+                        .iter()
+                        .map(|mode| format!("{:?}", mode))
+                        .collect()
+                });
+                AdapterEntry {
+                    name: info.name,
+                    backend: format!("{:?}", info.backend),
+                    device_type: format!("{:?}", info.device_type),
+                    timestamp_query: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+                    vulkan_display_timing: features
+                        .contains(wgpu::Features::VULKAN_GOOGLE_DISPLAY_TIMING),
+                    present_modes,
+                }
+            })
+            .collect();
+
+        let monitors = monitors
+            .into_iter()
+            .enumerate()
+            .map(|(index, monitor)| MonitorEntry {
+                index,
+                name: monitor.name(),
+                refresh_rate_millihertz: monitor.refresh_rate_millihertz(),
+                video_modes: monitor
+                    .video_modes()
+                    .map(|m| VideoModeInfo::new(&m))
+                    .collect(),
+            })
+            .collect();
+
+        Self { adapters, monitors }
+    }
+}
+
+impl fmt::Display for SystemInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        writeln!(f, "Adapters:")?;
+        for (i, a) in self.adapters.iter().enumerate() {
+            writeln!(f, "  {}: {} ({}, {})", i, a.name, a.backend, a.device_type)?;
+            writeln!(f, "       timestamp query: {}", yes_no(a.timestamp_query))?;
+            writeln!(
+                f,
+                "       display timing:  {}",
+                yes_no(a.vulkan_display_timing)
+            )?;
+            writeln!(f, "       present modes:   {}", a.present_modes.join(", "))?;
+        }
+
+        write!(f, "Monitors:")?;
+        for m in &self.monitors {
+            let hz = |mhz: u32| mhz as f64 / 1000.0;
+            write!(
+                f,
+                "\n  {}: {}",
+                m.index,
+                m.name.as_deref().unwrap_or("<unnamed>")
+            )?;
+            if let Some(mhz) = m.refresh_rate_millihertz {
+                write!(f, " @ {:.3} Hz", hz(mhz))?;
+            }
+            for mode in &m.video_modes {
+                write!(
+                    f,
+                    "\n       {}x{} {}-bit {:.3} Hz",
+                    mode.width,
+                    mode.height,
+                    mode.bit_depth,
+                    hz(mode.refresh_rate_millihertz)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
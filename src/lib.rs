@@ -0,0 +1,556 @@
+//! Frame and vblank timing capture for display validation.
+//!
+//! The core type is [`TimingSession`], which owns the GPU resources for one
+//! window, renders the flicker stimulus and records when each frame actually
+//! reached the display. The `timings` binary is a thin driver around it;
+//! applications with their own window and event loop can use
+//! [`TimingSession::attach`] instead.
+
+use polars::prelude::*;
+
+pub mod clock;
+pub mod compare;
+pub mod compositor;
+pub mod edid;
+mod gpu_timer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod info;
+#[cfg(feature = "lsl")]
+pub mod lsl;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod output;
+pub mod pattern;
+#[cfg(feature = "nvml")]
+pub mod power;
+pub mod present;
+pub mod priority;
+pub mod progress;
+mod render;
+pub mod serial;
+mod session;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod stats;
+pub mod stop;
+pub mod synthetic;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod udp;
+pub mod wait;
+
+pub use clock::qpc_to_ns;
+#[cfg(windows)]
+pub use clock::{get_qpc_frequency, get_qpc_timestamp};
+#[cfg(windows)]
+pub use present::dx12::get_frame_stats;
+pub use render::shader_hash;
+pub use session::{
+    Backend, FormatBits, FrameError, LimitsPreset, PowerPreference, PresentMode, SessionConfig,
+    TimingSession,
+};
+
+/// What a [`VBlankRecord`] describes.
+///
+/// Kept as a small enum in the capture loop so recording never allocates; it
+/// only becomes a string when the DataFrame is built.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    /// Vblank time reported by the present timing source.
+    SyncQpcTime,
+    /// CPU time after the frame was reported as displayed.
+    CpuTime,
+    /// A present that spanned extra refreshes; `count` is the number missed.
+    DroppedFrame,
+    /// GPU duration of the render pass; `count` is in nanoseconds.
+    GpuRender,
+    /// Timestamp received from the serial photodiode.
+    Photodiode,
+    /// Last vblank seen by the DWM compositor; `count` is its refresh count.
+    DwmVblank,
+    /// Latency from the CPU finishing `present()` to the vblank that showed
+    /// the frame; `count` is in nanoseconds.
+    SubmitToVblank,
+    /// No new present was reported within the vblank timeout; `count` is the
+    /// timeout in ns. Always the last record of a capture.
+    Stall,
+    /// Vsync-off benchmark: CPU time after presenting without waiting for the
+    /// display; `count` is the frame index. Not display timing.
+    VsyncOffCpuTime,
+    /// Vblanks between the last displayed present and the latest vblank
+    /// (`SyncRefreshCount - PresentRefreshCount`, DX12 only); grows when
+    /// frames queue up ahead of the display.
+    QueueDepth,
+    /// When a timed pattern was scheduled to switch on or off; `count` is the
+    /// new state (1 on, 0 off).
+    TransitionIntended,
+    /// Vblank at which that switch reached the display; `count` is how far
+    /// it was from the intended time, in ns.
+    TransitionActual,
+    /// A mouse or keyboard event; `count` is its sequence number.
+    Input,
+    /// Vblank of the first frame responding to an input; `count` is the
+    /// sequence number of that input.
+    InputPresented,
+    /// Color the whole screen was cleared to in full-field mode; `count` is
+    /// `0xRRGGBB`.
+    FrameColor,
+    /// A frame whose interval to the previous vblank deviated from the
+    /// expected refresh interval; `count` is the measured interval in ns.
+    LongFrame,
+    /// The GPU device was lost (e.g. a driver reset) and has been recreated;
+    /// `count` is the number of losses so far. Intervals across it are not
+    /// meaningful.
+    DeviceLost,
+    /// Tearing and sweep patterns: where the top of the bar was drawn in the
+    /// frame shown at this vblank, in thousandths of a scanline. A tear
+    /// appears where scanout met this bar position mid-frame; with a sweep and
+    /// a photodiode, the photodiode fires when the bar reaches its row.
+    TearPhase,
+    /// How the frame shown at this vblank reached the screen (DX12 only), as
+    /// DXGI's `CompositionMode`: 0 composed by DWM, 1 hardware overlay, 2
+    /// flipped directly to scanout, 3 composition failure.
+    PresentPath,
+    /// Start-up milestones, timestamped with the CPU clock like `cpu_time`
+    /// (so usually negative, being before the capture started); `count` is
+    /// the time since the session started being set up, in ns. Written once
+    /// at the top of the capture; `startup_begin` is that reference point.
+    StartupBegin,
+    /// The pipeline for the stimulus was created.
+    StartupPipelineCreated,
+    /// The swapchain was configured.
+    StartupSurfaceConfigured,
+    /// The first swapchain texture was acquired.
+    StartupFirstAcquire,
+    /// The first frame was presented.
+    StartupFirstPresent,
+    /// The first present was reported as displayed.
+    StartupFirstVblank,
+    /// Raw present statistics of the frame shown at this vblank, next to the
+    /// `sync_qpc_time` row whose `count` is the `PresentRefreshCount`:
+    /// the running present count (`PresentCount` on DXGI).
+    PresentCount,
+    /// The vblank count at the sync time (DXGI's `SyncRefreshCount`).
+    SyncRefreshCount,
+    /// The GPU clock at the sync time in raw ticks (DXGI's `SyncGPUTime`),
+    /// where the driver reports it.
+    SyncGpuTime,
+    /// When a `photodiode` row's line arrived on this machine, on the clock
+    /// of the `cpu_time` rows; `count` is the device timestamp it carried.
+    PhotodiodeArrival,
+    /// How many times the present timing source was polled until it
+    /// reported the frame shown at this vblank; high counts mean the wait
+    /// burned CPU time.
+    PollCount,
+    /// Vblank of the first pattern frame after the preroll; `count` is the
+    /// number of preroll frames.
+    PrerollEnd,
+    /// Vblank of the first postroll frame; `count` is the number of postroll
+    /// frames.
+    PostrollStart,
+    /// Board power draw of the GPU (`--gpu-power`, NVIDIA only), on the
+    /// clock of the `cpu_time` rows; `count` is in milliwatts.
+    GpuPowerMw,
+    /// Graphics clock of the GPU, sampled with `gpu_power_mw`; `count` is in
+    /// MHz.
+    GpuClockMhz,
+    /// A present estimated to have torn (`--allow-tearing`), at its
+    /// submission time; `count` is the scanline the tear was at, in
+    /// thousandths, from the submission's offset into the refresh interval.
+    Tear,
+}
+
+impl EventType {
+    /// Name used in the output files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventType::SyncQpcTime => "sync_qpc_time",
+            EventType::CpuTime => "cpu_time",
+            EventType::DroppedFrame => "dropped_frame",
+            EventType::GpuRender => "gpu_render",
+            EventType::Photodiode => "photodiode",
+            EventType::DwmVblank => "dwm_vblank",
+            EventType::SubmitToVblank => "submit_to_vblank_ns",
+            EventType::LongFrame => "long_frame",
+            EventType::FrameColor => "frame_color",
+            EventType::Stall => "stall",
+            EventType::Input => "input",
+            EventType::InputPresented => "input_presented",
+            EventType::TransitionIntended => "transition_intended",
+            EventType::TransitionActual => "transition_actual",
+            EventType::QueueDepth => "queue_depth",
+            EventType::VsyncOffCpuTime => "vsync_off_cpu_time",
+            EventType::DeviceLost => "device_lost",
+            EventType::TearPhase => "tear_phase",
+            EventType::PresentPath => "present_path",
+            EventType::StartupBegin => "startup_begin",
+            EventType::StartupPipelineCreated => "startup_pipeline_created",
+            EventType::StartupSurfaceConfigured => "startup_surface_configured",
+            EventType::StartupFirstAcquire => "startup_first_acquire",
+            EventType::StartupFirstPresent => "startup_first_present",
+            EventType::StartupFirstVblank => "startup_first_vblank",
+            EventType::PresentCount => "present_count",
+            EventType::SyncRefreshCount => "sync_refresh_count",
+            EventType::SyncGpuTime => "sync_gpu_time",
+            EventType::PhotodiodeArrival => "photodiode_arrival",
+            EventType::PollCount => "poll_count",
+            EventType::PrerollEnd => "preroll_end",
+            EventType::PostrollStart => "postroll_start",
+            EventType::GpuPowerMw => "gpu_power_mw",
+            EventType::GpuClockMhz => "gpu_clock_mhz",
+            EventType::Tear => "tear",
+        }
+    }
+}
+
+impl EventType {
+    /// Every event type, in declaration order.
+    pub const ALL: [EventType; 35] = [
+        EventType::SyncQpcTime,
+        EventType::CpuTime,
+        EventType::DroppedFrame,
+        EventType::GpuRender,
+        EventType::Photodiode,
+        EventType::DwmVblank,
+        EventType::SubmitToVblank,
+        EventType::Stall,
+        EventType::VsyncOffCpuTime,
+        EventType::QueueDepth,
+        EventType::TransitionIntended,
+        EventType::TransitionActual,
+        EventType::Input,
+        EventType::InputPresented,
+        EventType::FrameColor,
+        EventType::LongFrame,
+        EventType::DeviceLost,
+        EventType::TearPhase,
+        EventType::PresentPath,
+        EventType::StartupBegin,
+        EventType::StartupPipelineCreated,
+        EventType::StartupSurfaceConfigured,
+        EventType::StartupFirstAcquire,
+        EventType::StartupFirstPresent,
+        EventType::StartupFirstVblank,
+        EventType::PresentCount,
+        EventType::SyncRefreshCount,
+        EventType::SyncGpuTime,
+        EventType::PhotodiodeArrival,
+        EventType::PollCount,
+        EventType::PrerollEnd,
+        EventType::PostrollStart,
+        EventType::GpuPowerMw,
+        EventType::GpuClockMhz,
+        EventType::Tear,
+    ];
+
+    /// Whether this is one of the `startup_*` milestones.
+    pub fn is_startup(self) -> bool {
+        matches!(
+            self,
+            EventType::StartupBegin
+                | EventType::StartupPipelineCreated
+                | EventType::StartupSurfaceConfigured
+                | EventType::StartupFirstAcquire
+                | EventType::StartupFirstPresent
+                | EventType::StartupFirstVblank
+        )
+    }
+}
+
+impl serde::Serialize for EventType {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Reads the name used in the output files, as written by `Serialize`.
+impl<'de> serde::Deserialize<'de> for EventType {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        name.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl std::str::FromStr for EventType {
+    type Err = String;
+
+    /// Parses the name used in the output files.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventType::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == s)
+            .ok_or_else(|| format!("unknown event type {:?}", s))
+    }
+}
+
+impl std::fmt::Display for EventType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single timestamped event, one row in the output.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct VBlankRecord {
+    /// Nanoseconds since the start of the capture.
+    pub timestamp: i64,
+    /// Vblank count, or an event-specific value (e.g. missed intervals, or a
+    /// duration in nanoseconds).
+    pub count: i64,
+    pub event_type: EventType,
+    /// Index of the frame the record belongs to, counted from 0 in each run;
+    /// all rows produced for one present share it. Inputs carry the frame
+    /// that responds to them.
+    pub frame_index: i64,
+    /// Whether the frame this record belongs to was supposed to be lit.
+    pub lit: bool,
+    /// Repetition of the capture this record belongs to.
+    pub run_id: u32,
+    /// Display the record was captured on, when capturing on several at once.
+    pub monitor_id: u32,
+}
+
+macro_rules! struct_to_dataframe {
+    ($input:expr, [$($field:ident),+]) => {
+        {
+            let len = $input.len().to_owned();
+
+            // Extract the field values into separate vectors
+            $(let mut $field = Vec::with_capacity(len);)*
+
+            for e in $input.into_iter() {
+                $($field.push(e.$field);)*
+            }
+            df! {
+                $(stringify!($field) => $field,)*
+            }
+        }
+    };
+}
+
+/// Version of the record columns written by [`records_to_dataframe`] and
+/// recorded as `schema_version` in the metadata. Bump it whenever a column
+/// is added, removed, renamed or changes type.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The columns of [`records_to_dataframe`], in order.
+pub const SCHEMA_COLUMNS: [&str; 7] = [
+    "timestamp",
+    "count",
+    "event_type",
+    "frame_index",
+    "lit",
+    "run_id",
+    "monitor_id",
+];
+
+/// Checks that `df`, such as a loaded capture file, has every column of
+/// [`SCHEMA_COLUMNS`] with a type it can be read back as: integers for the
+/// numeric columns, text or categorical for `event_type`, and booleans for
+/// `lit`. Other columns (`utc_time`, tag columns) are allowed.
+pub fn validate_schema(df: &DataFrame) -> PolarsResult<()> {
+    for name in SCHEMA_COLUMNS {
+        let Ok(column) = df.column(name) else {
+            polars_bail!(SchemaMismatch: "missing column {:?} (expected schema version {})", name, SCHEMA_VERSION);
+        };
+        let dtype = column.dtype();
+        let ok = match name {
+            "event_type" => matches!(dtype, DataType::String | DataType::Categorical(_, _)),
+            "lit" => *dtype == DataType::Boolean,
+            _ => dtype.is_integer(),
+        };
+        if !ok {
+            polars_bail!(
+                SchemaMismatch: "column {:?} has type {} (expected schema version {})",
+                name, dtype, SCHEMA_VERSION
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Assembles records into a DataFrame with one column per field.
+///
+/// `event_type` becomes a categorical column.
+pub fn records_to_dataframe(records: Vec<VBlankRecord>) -> PolarsResult<DataFrame> {
+    let event_types: Vec<&str> = records.iter().map(|r| r.event_type.as_str()).collect();
+    let event_type = Series::new("event_type".into(), event_types)
+        .cast(&DataType::Categorical(None, CategoricalOrdering::Physical))?;
+
+    let mut df = struct_to_dataframe!(
+        records,
+        [timestamp, count, frame_index, lit, run_id, monitor_id]
+    )?;
+    df.insert_column(2, event_type)?;
+    Ok(df)
+}
+
+/// Reads records back from a DataFrame with the columns of
+/// [`records_to_dataframe`], such as a loaded capture file. Other columns
+/// (`utc_time`, tag columns) are ignored.
+pub fn records_from_dataframe(df: &DataFrame) -> PolarsResult<Vec<VBlankRecord>> {
+    let column = |name: &str, dtype: &DataType| -> PolarsResult<Series> {
+        let series = df.column(name)?.as_materialized_series().cast(dtype)?;
+        if series.null_count() > 0 {
+            polars_bail!(ComputeError: "column {:?} has missing values", name);
+        }
+        Ok(series)
+    };
+    let timestamp = column("timestamp", &DataType::Int64)?;
+    let count = column("count", &DataType::Int64)?;
+    let event_type = column("event_type", &DataType::String)?;
+    let frame_index = column("frame_index", &DataType::Int64)?;
+    let lit = column("lit", &DataType::Boolean)?;
+    let run_id = column("run_id", &DataType::UInt32)?;
+    let monitor_id = column("monitor_id", &DataType::UInt32)?;
+
+    let mut event_types = Vec::with_capacity(df.height());
+    for name in event_type.str()?.into_no_null_iter() {
+        event_types.push(
+            name.parse()
+                .map_err(|e: String| polars_err!(ComputeError: "{}", e))?,
+        );
+    }
+
+    Ok(timestamp
+        .i64()?
+        .into_no_null_iter()
+        .zip(count.i64()?.into_no_null_iter())
+        .zip(event_types)
+        .zip(frame_index.i64()?.into_no_null_iter())
+        .zip(lit.bool()?.into_no_null_iter())
+        .zip(run_id.u32()?.into_no_null_iter())
+        .zip(monitor_id.u32()?.into_no_null_iter())
+        .map(
+            |((((((timestamp, count), event_type), frame_index), lit), run_id), monitor_id)| {
+                VBlankRecord {
+                    timestamp,
+                    count,
+                    event_type,
+                    frame_index,
+                    lit,
+                    run_id,
+                    monitor_id,
+                }
+            },
+        )
+        .collect())
+}
+
+/// Like [`records_to_dataframe`], plus a `utc_time` column with the absolute
+/// time of each record in microseconds: `system_time_unix_ns` (the capture's
+/// wall-clock anchor) plus the record's offset. It is null for photodiode
+/// rows, which carry the device's own timestamps.
+pub fn capture_dataframe(
+    records: Vec<VBlankRecord>,
+    system_time_unix_ns: i64,
+) -> PolarsResult<DataFrame> {
+    let utc: Vec<Option<i64>> = records
+        .iter()
+        .map(|r| {
+            (r.event_type != EventType::Photodiode)
+                .then(|| (system_time_unix_ns + r.timestamp) / 1000)
+        })
+        .collect();
+
+    let mut df = records_to_dataframe(records)?;
+    let utc = Series::new("utc_time".into(), utc)
+        .cast(&DataType::Datetime(TimeUnit::Microseconds, None))?;
+    df.with_column(utc)?;
+    Ok(df)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(
+        timestamp: i64,
+        count: i64,
+        event_type: EventType,
+        frame_index: i64,
+        lit: bool,
+    ) -> VBlankRecord {
+        VBlankRecord {
+            timestamp,
+            count,
+            event_type,
+            frame_index,
+            lit,
+            run_id: 1,
+            monitor_id: 2,
+        }
+    }
+
+    fn column_names(df: &DataFrame) -> Vec<&str> {
+        df.get_column_names()
+            .iter()
+            .map(|name| name.as_str())
+            .collect()
+    }
+
+    fn assert_schema(df: &DataFrame) {
+        assert_eq!(column_names(df), SCHEMA_COLUMNS);
+        let dtypes = df.dtypes();
+        assert_eq!(dtypes[0], DataType::Int64);
+        assert_eq!(dtypes[1], DataType::Int64);
+        assert!(matches!(dtypes[2], DataType::Categorical(_, _)));
+        assert_eq!(dtypes[3], DataType::Int64);
+        assert_eq!(dtypes[4], DataType::Boolean);
+        assert_eq!(dtypes[5], DataType::UInt32);
+        assert_eq!(dtypes[6], DataType::UInt32);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = vec![
+            record(16_666_667, 101, EventType::SyncQpcTime, 0, true),
+            record(16_700_000, 101, EventType::CpuTime, 0, true),
+            record(50_000_000, 1, EventType::DroppedFrame, 1, false),
+        ];
+        let df = records_to_dataframe(records).unwrap();
+
+        assert_schema(&df);
+        assert_eq!(df.height(), 3);
+
+        let i64s = |name: &str| -> Vec<i64> {
+            let column = df.column(name).unwrap().as_materialized_series();
+            column.i64().unwrap().into_no_null_iter().collect()
+        };
+        assert_eq!(i64s("timestamp"), [16_666_667, 16_700_000, 50_000_000]);
+        assert_eq!(i64s("count"), [101, 101, 1]);
+        assert_eq!(i64s("frame_index"), [0, 0, 1]);
+
+        let event_types = df
+            .column("event_type")
+            .unwrap()
+            .cast(&DataType::String)
+            .unwrap();
+        let event_types: Vec<&str> = event_types
+            .as_materialized_series()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(event_types, ["sync_qpc_time", "cpu_time", "dropped_frame"]);
+
+        let lit = df.column("lit").unwrap().as_materialized_series();
+        let lit: Vec<bool> = lit.bool().unwrap().into_no_null_iter().collect();
+        assert_eq!(lit, [true, true, false]);
+
+        for name in ["run_id", "monitor_id"] {
+            let column = df.column(name).unwrap().as_materialized_series();
+            let values: Vec<u32> = column.u32().unwrap().into_no_null_iter().collect();
+            assert_eq!(values, if name == "run_id" { [1; 3] } else { [2; 3] });
+        }
+    }
+
+    #[test]
+    fn no_records_keep_the_schema() {
+        let df = records_to_dataframe(Vec::new()).unwrap();
+
+        assert_schema(&df);
+        assert_eq!(df.height(), 0);
+    }
+}
@@ -0,0 +1,49 @@
+//! Lab Streaming Layer outlet publishing a marker for every displayed frame.
+//!
+//! Only built with the `lsl` feature. Markers carry the vblank time as their
+//! LSL timestamp, so they line up with other streams in the same recording
+//! without offline alignment. That holds as long as the present clock is the
+//! one liblsl uses for `local_clock()`: QPC on Windows (DX12 and Vulkan) and
+//! `CLOCK_MONOTONIC` elsewhere.
+
+use lsl::{ExPushable, StreamInfo, StreamOutlet};
+
+use crate::VBlankRecord;
+
+pub struct LslOutlet {
+    outlet: StreamOutlet,
+}
+
+impl LslOutlet {
+    /// Opens an irregular-rate string marker stream called `name`.
+    pub fn new(name: &str) -> Result<Self, lsl::Error> {
+        let info = StreamInfo::new(
+            name,
+            "Markers",
+            1,
+            lsl::IRREGULAR_RATE,
+            lsl::ChannelFormat::String,
+            "timings-frames",
+        )?;
+        Ok(Self {
+            outlet: StreamOutlet::new(&info, 0, 360)?,
+        })
+    }
+
+    /// Pushes a marker like `frame 12 on` for the `sync_qpc_time` record of a
+    /// frame. `present_clock_start_ns` turns its relative timestamp back into
+    /// present clock time.
+    pub fn push_frame(
+        &self,
+        vblank: &VBlankRecord,
+        present_clock_start_ns: i64,
+    ) -> Result<(), lsl::Error> {
+        let marker = format!(
+            "frame {} {}",
+            vblank.frame_index,
+            if vblank.lit { "on" } else { "off" }
+        );
+        let timestamp = (present_clock_start_ns + vblank.timestamp) as f64 / 1e9;
+        self.outlet.push_sample_ex(&vec![marker], timestamp, true)
+    }
+}
@@ -1,291 +1,1804 @@
-use polars::prelude::*;
-use std::{borrow::Cow, collections::HashMap, fs::File, sync::Mutex};
-use wgpu::hal::Adapter;
+use clap::Parser;
+use polars::prelude::{DataFrame, PolarsResult};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 use winit::{
-    event::{Event, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, Event, WindowEvent},
     event_loop::EventLoop,
-    window::Window,
+    monitor::{MonitorHandle, VideoMode},
+    window::{Fullscreen, Window, WindowBuilder},
+};
+
+use timings::{
+    capture_dataframe,
+    compare::{Comparison, DEFAULT_REGRESSION_THRESHOLD},
+    compositor::FullscreenOptimizations,
+    metadata::{parse_tag, sidecar_path, CaptureMetadata, MonitorInfo, WindowMode},
+    output::{
+        add_tag_columns, aggregate, aggregate_path, parse_duration, part_path, read_records,
+        write_df, write_df_csv_append, JsonLinesWriter, OutputFormat,
+    },
+    pattern::{Color, FullField, Image, Patch, Pattern, TimedPattern, Vertices},
+    priority::ThreadSettings,
+    progress::{Progress, Verbosity},
+    serial::PhotodiodeReader,
+    stats,
+    stop::{StopCondition, StopConditions, DEFAULT_FRAMES},
+    synthetic::{Replay, ReplayOrder, SyntheticCapture},
+    wait::WaitMode,
+    Backend, EventType, FormatBits, LimitsPreset, PowerPreference, PresentMode, SessionConfig,
+    TimingSession, VBlankRecord,
 };
 
-use serialport::SerialPort;
+/// How `--info` prints its report.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum InfoFormat {
+    Table,
+    Json,
+}
 
-#[derive(Debug, Clone)]
-struct VBlankRecord {
-    timestamp: i64,
-    count: i64,
-    event_type: String,
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Vsync {
+    On,
+    Off,
 }
 
-macro_rules! struct_to_dataframe {
-    ($input:expr, [$($field:ident),+]) => {
-        {
-            let len = $input.len().to_owned();
+/// Command-line options for a capture run.
+#[derive(Parser, Debug)]
+#[command(version, about = "Measure display refresh and present timing")]
+struct Args {
+    /// Number of frames to collect per run. Without any of --frames,
+    /// --duration and --max-dropped, 1000 frames are collected; with several,
+    /// whichever happens first ends the run
+    #[arg(long, value_parser = clap::value_parser!(i64).range(1..))]
+    frames: Option<i64>,
+
+    /// Collect for this long per run after the warm-up, e.g. `30s` or `10m`
+    #[arg(long, value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// End the run once this many frames have been dropped in it
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(i64).range(1..))]
+    max_dropped: Option<i64>,
+
+    /// Abort if the startup self-test of the performance counter fails
+    /// (Windows), instead of only warning
+    #[arg(long)]
+    strict: bool,
+
+    /// Graphics backend used for rendering and present timing
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// GPU to render on, by index among the adapters of the backend or by (part of) its name
+    #[arg(long)]
+    adapter: Option<String>,
+
+    /// Prefer the low-power or high-performance GPU when no --adapter is given
+    #[arg(long, value_enum)]
+    power_preference: Option<PowerPreference>,
+
+    /// Output file; defaults to a timestamped `timings_<date>T<time>.<ext>`
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+
+    /// Also append each record to this file as a JSON line while capturing
+    #[arg(long)]
+    stream_json: Option<PathBuf>,
+
+    /// Label the capture with `key=value`, written to the metadata; repeatable
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = parse_tag)]
+    tags: Vec<(String, String)>,
+
+    /// Also add each tag as a constant column to the output
+    #[arg(long)]
+    tag_columns: bool,
+
+    /// Also write per-window summaries (frames, mean interval, dropped) of
+    /// this length, e.g. `1s`, next to the output as `<name>.aggregate.<ext>`
+    #[arg(long, value_name = "WINDOW", value_parser = parse_duration)]
+    aggregate: Option<Duration>,
+
+    /// Append to the output file if it exists (CSV only) instead of replacing
+    /// it; its columns must match. Run ids continue after the existing ones
+    #[arg(long, requires = "output", conflicts_with = "ring_flush_secs")]
+    append: bool,
+
+    /// Output file format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// Serial port of a photodiode / trigger box to record alongside the frames (e.g. COM3)
+    #[arg(long)]
+    serial_port: Option<String>,
+
+    /// Baud rate of the serial port
+    #[arg(long, default_value_t = 115200)]
+    baud_rate: u32,
+
+    /// The photodiode reports raw QPC values of this machine; timestamps
+    /// outside the capture are then rejected as corrupt (Windows only)
+    #[arg(long, requires = "serial_port")]
+    photodiode_qpc: bool,
+
+    /// Flash single frames far apart and estimate how long after each onset
+    /// the photodiode's line arrives; the result goes into the metadata
+    #[arg(long, requires = "serial_port", conflicts_with = "pattern")]
+    calibrate_photodiode: bool,
+
+    /// Per-frame draw pattern of 1 (lit) and 0 (blank), cycled over the run,
+    /// `tearing` for a moving bar that makes tearing visible, `sweep:<frames>`
+    /// for a bar stepping down the screen over that many frames, or a static
+    /// `grating:<cycles per degree>` or `checker:<square pixels>`
+    #[arg(long, default_value_t = Pattern::default())]
+    pattern: Pattern,
+
+    /// PNG image to show on lit frames, at its native size in the middle of
+    /// the screen, instead of the pattern's stimulus
+    #[arg(long, value_name = "PATH", value_parser = Image::open)]
+    image: Option<Image>,
+
+    /// Draw the triangles in this file on lit frames instead of the
+    /// pattern's stimulus; one `x,y,color` vertex per line, with the position
+    /// from -1 to 1 (left to right, bottom to top) and the color as hex RGB
+    #[arg(long, value_name = "PATH", value_parser = Vertices::open, conflicts_with = "image")]
+    vertices: Option<Vertices>,
+
+    /// Pixels per degree of visual angle at the viewing distance, for `grating:` patterns
+    #[arg(long, default_value_t = 40.0)]
+    pixels_per_degree: f32,
+
+    /// Switch the stimulus by real time instead of --pattern: on for this many
+    /// milliseconds, then off for --off-ms, snapped to the nearest following vblank
+    #[arg(long, requires = "off_ms", value_parser = clap::value_parser!(u64).range(1..))]
+    on_ms: Option<u64>,
+
+    /// Off phase of the timed pattern, in milliseconds
+    #[arg(long, requires = "on_ms")]
+    off_ms: Option<u64>,
+
+    /// Flash the screen white on every mouse click or key press and record
+    /// `input` / `input_presented` rows, to measure input-to-photon latency
+    #[arg(long)]
+    input_latency: bool,
+
+    /// Fill the whole screen with this hex RGB color on lit frames (e.g.
+    /// ff8000) instead of drawing the stimulus; defaults to white if only
+    /// --off-color is given
+    #[arg(long)]
+    on_color: Option<Color>,
+
+    /// Fill color for blank frames in full-field mode; defaults to black
+    #[arg(long)]
+    off_color: Option<Color>,
+
+    /// Draw the stimulus only inside this rectangle of the screen, given in
+    /// pixels as x,y,width,height (e.g. 0,0,100,100 for the top-left corner
+    /// under a photodiode)
+    #[arg(long, value_name = "X,Y,W,H", conflicts_with_all = ["on_color", "off_color"])]
+    patch: Option<Patch>,
+
+    /// Monitor to capture on, by index or by (part of) its name; defaults to the last one
+    #[arg(long)]
+    monitor: Option<String>,
+
+    /// Also capture on this monitor, by index or name, to compare the vblanks
+    /// of two displays; its records have `monitor_id` 1
+    #[arg(long)]
+    second_monitor: Option<String>,
+
+    /// Fullscreen mode of the capture window
+    #[arg(long, value_enum, default_value_t = WindowMode::Borderless)]
+    fullscreen: WindowMode,
+
+    /// Use a normal window of this size (default 800x600) instead of
+    /// fullscreen, e.g. while developing patterns. Not for validating timing
+    #[arg(
+        long,
+        value_name = "WxH",
+        num_args = 0..=1,
+        default_missing_value = "800x600",
+        value_parser = parse_window_size,
+        conflicts_with = "fullscreen"
+    )]
+    windowed: Option<(u32, u32)>,
+
+    /// Record the GPU render time of each frame (needs timestamp query support)
+    #[arg(long)]
+    gpu_timing: bool,
+
+    /// Draw this many extra full-screen passes of busy work per frame, to
+    /// check frame pacing with the GPU under load
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    gpu_load: u32,
+
+    /// Draw the frame index in binary in the top-left corner, for reading
+    /// back with a camera
+    #[arg(long)]
+    frame_counter: bool,
+
+    /// Show this many frames of `--roll-color` at the start of each run,
+    /// before the pattern, to mark it in external recordings
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    preroll: u32,
+
+    /// Show this many frames of `--roll-color` at the end of each run
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    postroll: u32,
+
+    /// Hex RGB color of the preroll and postroll frames
+    #[arg(long, default_value_t = Color::MAGENTA)]
+    roll_color: Color,
+
+    /// Frames to render before recording starts, excluded from the output
+    #[arg(long, default_value_t = 10)]
+    warmup: u32,
+
+    /// How to wait for each frame to be displayed
+    #[arg(long, value_enum, default_value_t = WaitMode::Hybrid)]
+    wait_mode: WaitMode,
+
+    /// Stop with an error if no vblank is reported for this long, e.g. because
+    /// the monitor went to sleep; the data collected so far is still written
+    #[arg(long)]
+    vblank_timeout_ms: Option<u64>,
+
+    /// Keep only the most recent N records in memory, for unbounded runs
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    ring: Option<u64>,
+
+    /// In ring mode, also write the current records to the output every this many seconds
+    #[arg(long, requires = "ring")]
+    ring_flush_secs: Option<u64>,
+
+    /// Every this long, e.g. `10m`, move the records collected so far to a
+    /// numbered part file next to the output (`<name>.part0001.<ext>`, ...),
+    /// so long captures keep little in memory. The output and the summary
+    /// then only hold what came after the last part
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = parse_duration,
+        conflicts_with_all = ["ring", "append", "second_monitor"]
+    )]
+    flush_interval: Option<Duration>,
+
+    /// Always stop and write the data after this long in total, e.g. `480m`,
+    /// counted from program start and whatever the other stop conditions
+    /// say; a safety net for unattended rigs
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    max_runtime: Option<Duration>,
+
+    /// Number of times to repeat the collection; runs are told apart by `run_id`
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeats: u32,
+
+    /// Swapchain bit depth: 8, 10 or 16f (scRGB, for HDR); defaults to the
+    /// surface's preferred format, which it also falls back to
+    #[arg(long, value_enum)]
+    format_bits: Option<FormatBits>,
+
+    /// Swapchain present mode; falls back to fifo if the surface doesn't support it
+    #[arg(long, value_enum, default_value_t = PresentMode::Fifo)]
+    present_mode: PresentMode,
 
-            // Extract the field values into separate vectors
-            $(let mut $field = Vec::with_capacity(len);)*
+    /// With --present-mode immediate, let presents tear (DXGI_PRESENT_ALLOW_TEARING
+    /// on DX12) and record a `tear` row with the estimated scanline of each tear
+    #[arg(long)]
+    allow_tearing: bool,
 
-            for e in $input.into_iter() {
-                $($field.push(e.$field);)*
+    /// Print the present modes the surface supports and the one selected
+    /// before capturing
+    #[arg(long, conflicts_with_all = ["headless", "synthetic"])]
+    list_present_modes: bool,
+
+    /// `off` turns the run into a throughput benchmark: present immediately,
+    /// don't wait for vblanks and record CPU frame times only
+    #[arg(long, value_enum, default_value_t = Vsync::On)]
+    vsync: Vsync,
+
+    /// Frames the swapchain may queue ahead of the display; higher values trade latency for throughput
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=16))]
+    max_latency: u32,
+
+    /// Device limits to request
+    #[arg(long, value_enum, default_value_t = LimitsPreset::Adapter)]
+    limits: LimitsPreset,
+
+    /// Expected refresh rate in Hz, overriding the one reported by the video mode
+    #[arg(long, value_parser = parse_refresh_rate)]
+    target_hz: Option<f64>,
+
+    /// Allowed deviation of a frame interval from the expected one, as a
+    /// fraction of it, before the frame is flagged as `long_frame`
+    #[arg(long, default_value_t = 0.5)]
+    long_frame_tolerance: f64,
+
+    /// Print a histogram of the frame intervals with this many bins, spanning
+    /// up to 2.5 refresh intervals
+    #[arg(long)]
+    hist_bins: Option<usize>,
+
+    /// Don't print progress while capturing, only the summary at the end
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print a line for every frame instead of a periodic status line
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Also append log messages, with timestamps, to this file
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Publish a marker for every displayed frame on a Lab Streaming Layer
+    /// outlet with this stream name
+    #[cfg(feature = "lsl")]
+    #[arg(long, value_name = "STREAM_NAME", num_args = 0..=1, default_missing_value = "timings")]
+    lsl: Option<String>,
+
+    /// Send a UDP datagram to this address (e.g. 192.168.1.255:5005) at every
+    /// stimulus onset; see the `udp` module docs for the packet layout
+    #[cfg(not(target_arch = "wasm32"))]
+    #[arg(long, value_name = "ADDR:PORT")]
+    udp: Option<std::net::SocketAddr>,
+
+    /// Also add the capture to this SQLite database, creating it if needed;
+    /// records are keyed by a new row in its `sessions` table
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "DB")]
+    sqlite: Option<PathBuf>,
+
+    /// Sample the GPU's power draw and clock this often (default 100ms) as
+    /// `gpu_power_mw` and `gpu_clock_mhz` rows; NVIDIA GPUs only
+    #[cfg(feature = "nvml")]
+    #[arg(long, value_name = "INTERVAL", value_parser = parse_duration, num_args = 0..=1, default_missing_value = "100ms")]
+    gpu_power: Option<Duration>,
+
+    /// Serve live timing stats for Prometheus on this port
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// List adapters, monitors and their capabilities, then exit without
+    /// capturing; `--info json` prints it as JSON
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "table")]
+    info: Option<InfoFormat>,
+
+    /// Compare two captures side by side, then exit without capturing. Reads
+    /// CSV, Parquet and Arrow files, and --stream-json output otherwise.
+    /// Exits with status 2 if B regressed from A
+    #[arg(long, num_args = 2, value_names = ["A", "B"])]
+    compare: Option<Vec<PathBuf>>,
+
+    /// How much worse a metric of --compare's B may be than A's before it is
+    /// a regression, as a fraction of A's
+    #[arg(long, default_value_t = DEFAULT_REGRESSION_THRESHOLD)]
+    regression_threshold: f64,
+
+    /// Pin the capture thread to this CPU core, so the scheduler doesn't
+    /// migrate it
+    #[arg(long, value_name = "CORE")]
+    pin_cpu: Option<usize>,
+
+    /// Raise the priority of the capture process and thread
+    #[arg(long)]
+    high_priority: bool,
+
+    /// Turn off Windows' fullscreen optimizations for this executable, so
+    /// fullscreen presents can flip independently of the compositor, and warn
+    /// if they still went through it. Applies from the next launch if it
+    /// wasn't set before
+    #[arg(long, conflicts_with_all = ["headless", "synthetic"])]
+    disable_fullscreen_optimizations: bool,
+
+    /// Render offscreen with a synthetic clock (60 Hz or `--target-hz`) instead of opening a window (for CI)
+    #[arg(long)]
+    headless: bool,
+
+    /// Don't render at all: generate seeded records (60 Hz or `--target-hz`)
+    /// and run them through the usual summary and output, to test the
+    /// analysis without a GPU
+    #[arg(long, conflicts_with = "headless")]
+    synthetic: bool,
+
+    /// Seed of the `--synthetic` records; the same seed gives the same output
+    #[arg(long, default_value_t = 0, requires = "synthetic")]
+    seed: u64,
+
+    /// Probability of each `--synthetic` frame being shown a refresh late
+    #[arg(long, default_value_t = 0.0, requires = "synthetic")]
+    drop_rate: f64,
+
+    /// Maximum deviation of the `--synthetic` vblank times, in microseconds
+    #[arg(long, default_value_t = 100, requires = "synthetic")]
+    jitter_us: u32,
+
+    /// Replay the vblank intervals of this capture file as the `--synthetic`
+    /// records, with --jitter-us and --drop-rate added on top (set them to 0
+    /// for the capture's own timing). Its median interval is the refresh
+    /// interval unless --target-hz is given
+    #[arg(long, value_name = "CAPTURE", requires = "synthetic")]
+    seed_from_capture: Option<PathBuf>,
+
+    /// Whether --seed-from-capture replays the intervals in their captured
+    /// order, or draws them at random with --seed
+    #[arg(long, value_enum, default_value_t = ReplayOrder::Sequence, requires = "seed_from_capture")]
+    replay_order: ReplayOrder,
+}
+
+/// The progress output chosen by `--quiet` and `--verbose`.
+fn verbosity(args: &Args) -> Verbosity {
+    match (args.quiet, args.verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    }
+}
+
+/// Pattern of `--calibrate-photodiode`: one lit frame in twelve, 200 ms apart
+/// at 60 Hz, so each line can only belong to the flash before it.
+const CALIBRATION_PATTERN: &str = "100000000000";
+
+/// Pins this thread and raises its priority as `--pin-cpu` and
+/// `--high-priority` ask. Returns what was applied, `None` if nothing was
+/// asked for.
+fn apply_thread_settings(args: &Args) -> Option<ThreadSettings> {
+    if args.pin_cpu.is_none() && !args.high_priority {
+        return None;
+    }
+    let mut applied = ThreadSettings::default();
+    if let Some(core) = args.pin_cpu {
+        match timings::priority::pin_to_cpu(core) {
+            Ok(()) => {
+                log::info!("Pinned the capture thread to CPU {}", core);
+                applied.pinned_cpu = Some(core);
             }
-            df! {
-                $(stringify!($field) => $field,)*
+            Err(e) => log::warn!("Failed to pin the capture thread to CPU {}: {}", core, e),
+        }
+    }
+    if args.high_priority {
+        match timings::priority::raise_priority() {
+            Ok(()) => {
+                log::info!("Raised the capture priority");
+                applied.high_priority = true;
             }
+            Err(e) => log::warn!("Failed to raise the capture priority: {}", e),
+        }
+    }
+    Some(applied)
+}
+
+/// Sets the layer behind `--disable-fullscreen-optimizations`, warning if it
+/// can't or if it only applies from the next launch.
+fn disable_fullscreen_optimizations() -> Option<FullscreenOptimizations> {
+    match timings::compositor::disable_fullscreen_optimizations() {
+        Ok(FullscreenOptimizations::Disabled) => {
+            log::info!("Fullscreen optimizations are disabled for this executable");
+            Some(FullscreenOptimizations::Disabled)
+        }
+        Ok(FullscreenOptimizations::DisabledFromNextLaunch) => {
+            log::warn!(
+                "Disabled fullscreen optimizations for this executable; Windows applies this from the next \
+                 launch, so this capture may still go through the compositor"
+            );
+            Some(FullscreenOptimizations::DisabledFromNextLaunch)
+        }
+        Err(e) => {
+            log::warn!("Failed to disable fullscreen optimizations: {}", e);
+            None
+        }
+    }
+}
+
+/// How long the startup self-test reads the performance counter.
+#[cfg(windows)]
+const QPC_SELF_TEST_DURATION: Duration = Duration::from_millis(200);
+
+/// Checks that QPC is monotonic and runs at its reported rate before any
+/// timing depends on it. With `--strict` a failure ends the program.
+#[cfg(windows)]
+fn check_qpc(args: &Args) -> Option<timings::clock::QpcCheck> {
+    let check = match timings::clock::qpc_self_test(QPC_SELF_TEST_DURATION) {
+        Ok(check) => check,
+        Err(e) => {
+            log::error!("QPC self-test could not read the counter: {}", e);
+            if args.strict {
+                std::process::exit(1);
+            }
+            return None;
         }
     };
+
+    println!("{}", check);
+    if check.is_platform_timer() {
+        log::warn!(
+            "QPC runs on a legacy platform timer ({} Hz), likely emulated or without an invariant TSC",
+            check.frequency
+        );
+    }
+    if !check.passed() {
+        log::warn!(
+            "QPC is not monotonic or not running at its reported rate; timings may be garbage"
+        );
+        if args.strict {
+            log::error!("Aborting because of --strict");
+            std::process::exit(1);
+        }
+    }
+    Some(check)
 }
 
-const COLLECT_FRAMES: i64 = 1000;
+/// The stop conditions chosen by `--frames`, `--duration` and `--max-dropped`,
+/// and `--max-runtime` counting from `started`.
+fn stop_conditions(args: &Args, started: Instant) -> StopConditions {
+    let mut conditions = Vec::new();
+    conditions.extend(args.frames.map(StopCondition::Frames));
+    conditions.extend(args.duration.map(StopCondition::Duration));
+    conditions.extend(args.max_dropped.map(StopCondition::DroppedFrames));
+    StopConditions::new(conditions).with_max_runtime(args.max_runtime, started)
+}
 
-use windows::Win32::Graphics::Dxgi::DXGI_FRAME_STATISTICS;
+/// Sets up `log` output on stderr, filtered by `RUST_LOG` (by default warnings,
+/// plus this tool's own info messages), and with `--log-file` also appended
+/// to that file.
+#[cfg(not(target_arch = "wasm32"))]
+fn init_logging(log_file: Option<&Path>) {
+    let env = env_logger::Env::default().default_filter_or("warn,timings=info");
+    let mut builder = env_logger::Builder::from_env(env);
+    builder.format_timestamp_millis();
 
-async fn run(event_loop: EventLoop<()>, window: Window) {
-    let mut size = window.inner_size();
-    size.width = size.width.max(1);
-    size.height = size.height.max(1);
+    if let Some(path) = log_file {
+        match File::options().create(true).append(true).open(path) {
+            Ok(file) => {
+                builder.target(env_logger::Target::Pipe(Box::new(Tee { file })));
+            }
+            Err(e) => {
+                eprintln!("Failed to open log file {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+    builder.init();
+}
 
-    let instance_desc = wgpu::InstanceDescriptor {
-        backends: wgpu::Backends::DX12,
-        ..Default::default()
+/// Log target writing to both stderr and a file.
+#[cfg(not(target_arch = "wasm32"))]
+struct Tee {
+    file: File,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        io::stderr().write_all(buf)?;
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.file.flush()
+    }
+}
+
+/// Builds a filename like `timings_2024-06-01T13-22-05.csv` from the local time.
+fn default_output_path(format: OutputFormat) -> PathBuf {
+    let now = chrono::Local::now();
+    PathBuf::from(format!(
+        "timings_{}.{}",
+        now.format("%Y-%m-%dT%H-%M-%S"),
+        format.extension()
+    ))
+}
+
+/// Finds the monitor matching `selector`, or exits listing the available ones.
+fn select_monitor(event_loop: &EventLoop<()>, selector: Option<&str>) -> MonitorHandle {
+    let monitors: Vec<MonitorHandle> = event_loop.available_monitors().collect();
+
+    let found = match selector {
+        None => monitors.last().cloned(),
+        Some(s) => match s.parse::<usize>() {
+            Ok(index) => monitors.get(index).cloned(),
+            Err(_) => monitors
+                .iter()
+                .find(|m| m.name().is_some_and(|name| name.contains(s)))
+                .cloned(),
+        },
+    };
+
+    found.unwrap_or_else(|| {
+        match selector {
+            Some(s) => eprintln!("No monitor matching {:?}. Available monitors:", s),
+            None => eprintln!("No monitors found"),
+        }
+        for (i, m) in monitors.iter().enumerate() {
+            eprintln!("  {}: {}", i, m.name().as_deref().unwrap_or("<unnamed>"));
+        }
+        std::process::exit(1);
+    })
+}
+
+/// Expected refresh interval in ns, from `--target-hz` or else the video
+/// mode's refresh rate (which is 0 when the platform doesn't report it).
+fn target_interval(args: &Args, refresh_rate_millihertz: Option<u32>) -> Option<i64> {
+    match args.target_hz {
+        Some(hz) => Some((1e9 / hz).round() as i64),
+        None => refresh_rate_millihertz
+            .filter(|&mhz| mhz > 0)
+            .map(|mhz| 1_000_000_000_000 / mhz as i64),
+    }
+}
+
+/// Parses a `--target-hz` refresh rate, which has to be a positive number.
+fn parse_refresh_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(hz) if hz > 0.0 && hz.is_finite() => Ok(hz),
+        _ => Err(format!("invalid refresh rate {:?}, expected Hz above 0", s)),
+    }
+}
+
+/// Parses a `--windowed` size like `800x600`.
+fn parse_window_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid window size {:?}, expected WxH like 800x600", s);
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// The window mode in effect, `--windowed` overriding `--fullscreen`.
+fn window_mode(args: &Args) -> WindowMode {
+    if args.windowed.is_some() {
+        WindowMode::Windowed
+    } else {
+        args.fullscreen
+    }
+}
+
+/// A capture window on `monitor` in the given mode.
+fn window_builder(monitor: MonitorHandle, video_mode: VideoMode, args: &Args) -> WindowBuilder {
+    let builder = WindowBuilder::new();
+    match (window_mode(args), args.windowed) {
+        (WindowMode::Windowed, Some((width, height))) => builder
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_position(monitor.position()),
+        (WindowMode::Exclusive, _) => {
+            builder.with_fullscreen(Some(Fullscreen::Exclusive(video_mode)))
+        }
+        _ => builder.with_fullscreen(Some(Fullscreen::Borderless(Some(monitor)))),
+    }
+}
+
+fn session_config(args: &Args, target_interval: Option<i64>) -> SessionConfig {
+    SessionConfig {
+        backend: args.backend,
+        adapter: args.adapter.clone(),
+        power_preference: args.power_preference,
+        pattern: args.pattern.clone(),
+        pixels_per_degree: args.pixels_per_degree,
+        image: args.image.clone(),
+        vertices: args.vertices.clone(),
+        timed: args.on_ms.zip(args.off_ms).map(|(on, off)| TimedPattern {
+            on_ns: on as i64 * 1_000_000,
+            off_ns: off as i64 * 1_000_000,
+        }),
+        full_field: (args.on_color.is_some() || args.off_color.is_some()).then(|| FullField {
+            on: args.on_color.unwrap_or(Color::WHITE),
+            off: args.off_color.unwrap_or(Color::BLACK),
+        }),
+        gpu_timing: args.gpu_timing,
+        gpu_load: args.gpu_load,
+        frame_counter: args.frame_counter,
+        preroll: args.preroll,
+        postroll: args.postroll,
+        roll_color: args.roll_color,
+        patch: args.patch,
+        warmup_frames: args.warmup,
+        wait_mode: args.wait_mode,
+        vblank_timeout: args.vblank_timeout_ms.map(Duration::from_millis),
+        format_bits: args.format_bits,
+        present_mode: args.present_mode,
+        allow_tearing: args.allow_tearing,
+        vsync: args.vsync == Vsync::On,
+        limits: args.limits,
+        max_frame_latency: args.max_latency,
+        ring: args.ring.map(|n| n as usize),
+        target_interval,
+        long_frame_tolerance: args.long_frame_tolerance,
+    }
+}
+
+/// Opens the `--stream-json` file for the session; the first monitor's
+/// session creates it and the others append to it.
+fn attach_json_stream(session: TimingSession, args: &Args) -> TimingSession {
+    let Some(path) = args.stream_json.as_deref() else {
+        return session;
+    };
+    let stream = if session.monitor_id() == 0 {
+        JsonLinesWriter::create(path)
+    } else {
+        JsonLinesWriter::append(path)
+    };
+    match stream {
+        Ok(stream) => session.with_json_stream(stream),
+        Err(e) => {
+            log::error!("Failed to open {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn attach_photodiode(session: TimingSession, args: &Args) -> TimingSession {
+    // open serial port
+    match args.serial_port.as_deref() {
+        Some(port) => {
+            let reader = PhotodiodeReader::spawn(port, args.baud_rate).unwrap_or_else(|e| {
+                log::error!("Failed to open serial port {}: {}", port, e);
+                std::process::exit(1);
+            });
+            let reader = if args.photodiode_qpc {
+                reader.with_qpc_timestamps()
+            } else {
+                reader
+            };
+            session.with_photodiode(reader)
+        }
+        None => session,
+    }
+}
+
+/// Installs a Ctrl-C handler and returns the flag it sets, so an aborted run
+/// can still write out what it collected.
+fn interrupt_flag() -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    if let Err(e) = ctrlc::set_handler(move || flag.store(true, Ordering::Relaxed)) {
+        log::warn!("Failed to install Ctrl-C handler: {}", e);
+    }
+    interrupted
+}
+
+/// Starts the `--gpu-power` sampler on the session's GPU, if requested. Other
+/// GPUs than NVIDIA ones have no samples, which only warrants a warning.
+#[cfg(feature = "nvml")]
+fn attach_power_sampler(session: TimingSession, args: &Args) -> TimingSession {
+    let Some(interval) = args.gpu_power else {
+        return session;
     };
-    let instance = wgpu::Instance::new(&instance_desc);
-
-    let mut surface = instance.create_surface(&window).unwrap();
-    let adapter = instance
-        .request_adapter(&wgpu::RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::default(),
-            force_fallback_adapter: false,
-            // Request an adapter which can render to our surface
-            compatible_surface: Some(&surface),
+    let adapter = session.adapter().get_info().name;
+    match timings::power::PowerSampler::spawn(&adapter, interval) {
+        Ok(sampler) => {
+            log::info!(
+                "Sampling the power draw of {} every {:?}",
+                sampler.device_name(),
+                interval
+            );
+            session.with_power_sampler(sampler)
+        }
+        Err(e) => {
+            log::warn!(
+                "Can't sample the power draw of {} through NVML ({}); no gpu_power_mw rows",
+                adapter,
+                e
+            );
+            session
+        }
+    }
+}
+
+/// Opens the `--lsl` outlet, if requested.
+#[cfg(feature = "lsl")]
+fn lsl_outlet(args: &Args) -> Option<timings::lsl::LslOutlet> {
+    let name = args.lsl.as_deref()?;
+    match timings::lsl::LslOutlet::new(name) {
+        Ok(outlet) => {
+            log::info!("Publishing frame markers on LSL stream {:?}", name);
+            Some(outlet)
+        }
+        Err(e) => {
+            log::error!("Failed to open LSL outlet: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Pushes the marker for the frame that was just displayed.
+#[cfg(feature = "lsl")]
+fn push_lsl_marker(
+    outlet: Option<&timings::lsl::LslOutlet>,
+    session: &TimingSession,
+    vblank: Option<&timings::VBlankRecord>,
+) {
+    let (Some(outlet), Some(vblank)) = (outlet, vblank) else {
+        return;
+    };
+    let start = session.clock_anchors().present_clock_start_ns;
+    if let Err(e) = outlet.push_frame(vblank, start) {
+        log::warn!("Failed to push LSL marker: {}", e);
+    }
+}
+
+/// Starts the `--udp` onset sender, if requested.
+#[cfg(not(target_arch = "wasm32"))]
+fn udp_sender(args: &Args) -> Option<timings::udp::UdpOnsetSender> {
+    let target = args.udp?;
+    match timings::udp::UdpOnsetSender::new(target) {
+        Ok(sender) => {
+            log::info!("Sending onsets to {}", target);
+            Some(sender)
+        }
+        Err(e) => {
+            log::error!("Failed to open UDP socket for {}: {}", target, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Sends an onset datagram if the frame that was just displayed switched the
+/// stimulus on.
+#[cfg(not(target_arch = "wasm32"))]
+fn send_udp_onset(
+    sender: Option<&mut timings::udp::UdpOnsetSender>,
+    session: &TimingSession,
+    vblank: Option<&timings::VBlankRecord>,
+) {
+    if let (Some(sender), Some(vblank)) = (sender, vblank) {
+        sender.on_vblank(vblank, session.clock_anchors().present_clock_start_ns);
+    }
+}
+
+/// Periodic `--ring-flush-secs` writes of the records held so far.
+struct RingFlush {
+    interval: Duration,
+    last: Instant,
+    // for the `--tag-columns`, so flushes have the final write's columns
+    tags: BTreeMap<String, String>,
+}
+
+impl RingFlush {
+    fn new(args: &Args, tags: &BTreeMap<String, String>) -> Option<Self> {
+        args.ring_flush_secs.map(|secs| Self {
+            interval: Duration::from_secs(secs),
+            last: Instant::now(),
+            tags: tags.clone(),
         })
-        .await
-        .expect("Failed to find an appropriate adapter");
-
-    // Create the logical device and command queue
-    let (device, queue) = adapter
-        .request_device(&wgpu::DeviceDescriptor {
-            label: None,
-            required_features: wgpu::Features::empty(),
-            // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
-            required_limits: wgpu::Limits::downlevel_webgl2_defaults()
-                .using_resolution(adapter.limits()),
-            memory_hints: wgpu::MemoryHints::MemoryUsage,
-            trace: wgpu::Trace::Off,
+    }
+
+    /// Overwrites the output with the current contents of the ring, if due.
+    fn flush_if_due(&mut self, session: &TimingSession, args: &Args, output: &Path) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+
+        let anchor = session.clock_anchors().system_time_unix_ns;
+        let result = output_dataframe(session.records().to_vec(), anchor, args, &self.tags)
+            .and_then(|mut df| write_df(&mut df, output, args.format));
+        if let Err(e) = result {
+            log::error!("Failed to write {}: {}", output.display(), e);
+        }
+    }
+}
+
+/// Periodic `--flush-interval` moves of the records collected so far into
+/// part files. The parts are written on a thread of their own, so a slow
+/// disk never holds up a frame.
+struct PartFlush {
+    interval: Duration,
+    last: Instant,
+    parts: mpsc::Sender<(Vec<VBlankRecord>, i64)>,
+    writer: thread::JoinHandle<u32>,
+}
+
+impl PartFlush {
+    fn new(args: &Args, output: &Path) -> Option<Self> {
+        let interval = args.flush_interval?;
+        let (parts, received) = mpsc::channel::<(Vec<VBlankRecord>, i64)>();
+        let (output, format) = (output.to_path_buf(), args.format);
+        let writer = thread::spawn(move || {
+            let mut written = 0;
+            for (records, anchor) in received {
+                let path = part_path(&output, written + 1, format);
+                let result = capture_dataframe(records, anchor)
+                    .and_then(|mut df| write_df(&mut df, &path, format));
+                match result {
+                    Ok(()) => log::info!("Wrote {}", path.display()),
+                    Err(e) => log::error!("Failed to write {}: {}", path.display(), e),
+                }
+                // numbered either way, so a missing part shows there was a gap
+                written += 1;
+            }
+            written
+        });
+        Some(Self {
+            interval,
+            last: Instant::now(),
+            parts,
+            writer,
         })
-        .await
-        .expect("Failed to create device");
+    }
+
+    /// Hands the session's records to the writer, if due.
+    fn flush_if_due(&mut self, session: &mut TimingSession) {
+        if self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+
+        let anchor = session.clock_anchors().system_time_unix_ns;
+        // the writer only stops once this is dropped
+        let _ = self.parts.send((session.take_records(), anchor));
+    }
+
+    /// Waits for the pending parts and returns how many there are.
+    fn finish(self) -> u32 {
+        drop(self.parts);
+        self.writer.join().unwrap_or(0)
+    }
+}
+
+/// What the end-of-run summary is computed from: a session, or the records
+/// of a `--synthetic` capture.
+struct SummaryInput<'a> {
+    monitor_id: u32,
+    records: &'a [VBlankRecord],
+    /// As `(frame index, missed intervals)`, see [`TimingSession::dropped_frames`].
+    dropped_frames: &'a [(i64, i64)],
+    dropped_frame_count: i64,
+    expected_interval: Option<i64>,
+    benchmark: bool,
+}
+
+impl<'a> SummaryInput<'a> {
+    fn of(session: &'a TimingSession) -> Self {
+        Self {
+            monitor_id: session.monitor_id(),
+            records: session.records(),
+            dropped_frames: session.dropped_frames(),
+            dropped_frame_count: session.dropped_frame_count(),
+            expected_interval: session.expected_interval(),
+            benchmark: session.is_benchmark(),
+        }
+    }
+}
+
+/// Prints the frame rate a vsync-off benchmark achieved.
+fn print_benchmark_summary(records: &[VBlankRecord]) {
+    println!("VSYNC OFF BENCHMARK: CPU frame times, not display timing");
+    match stats::IntervalSummary::from_intervals(&stats::benchmark_intervals(records)) {
+        Some(summary) => {
+            println!("{}", summary);
+            println!("Average: {:.1} fps", 1e9 / summary.mean);
+        }
+        None => println!("No frames recorded"),
+    }
+}
+
+/// Prints the interval statistics and dropped frames of one session.
+fn print_summary(session: &SummaryInput, args: &Args) {
+    if session.benchmark {
+        print_benchmark_summary(session.records);
+        return;
+    }
+
+    let intervals = stats::vblank_intervals(session.records);
+    let summary = stats::IntervalSummary::from_intervals(&intervals);
+    match &summary {
+        Some(summary) => println!("{}", summary),
+        None => println!("No vblank intervals recorded"),
+    }
 
-    // Load the shaders from disk
-    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-        label: None,
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("shader.wgsl"))),
+    if let (Some(bins), Some(summary)) = (args.hist_bins, &summary) {
+        // without a known refresh rate, the typical interval is the next best guess
+        let expected = session
+            .expected_interval
+            .map_or(summary.median, |interval| interval as f64);
+        if let Some(histogram) = stats::Histogram::from_intervals(&intervals, expected, bins) {
+            println!("{}", histogram);
+        }
+    }
+
+    if let Some(polls) = stats::PollHistogram::from_records(session.records) {
+        println!("{}", polls);
+    }
+
+    if let Some(beat) = stats::Beat::detect(&intervals) {
+        println!("{}", beat);
+        log::warn!(
+            "Frame intervals beat with a period of {} frames; frame pacing is off",
+            beat.period_frames
+        );
+    }
+
+    println!("Dropped frames: {}", session.dropped_frame_count);
+    if session.dropped_frame_count > 0 {
+        log::warn!(
+            "Monitor {} dropped {} frames",
+            session.monitor_id,
+            session.dropped_frame_count
+        );
+    }
+    if !session.dropped_frames.is_empty() {
+        let indices: Vec<String> = session
+            .dropped_frames
+            .iter()
+            .map(|(frame, _)| frame.to_string())
+            .collect();
+        println!("  at frames: {}", indices.join(", "));
+    }
+    let long_frames = session
+        .records
+        .iter()
+        .filter(|r| r.event_type == EventType::LongFrame)
+        .count();
+    if let Some(expected) = session.expected_interval {
+        println!(
+            "Long frames: {} (expected interval {:.3} ms)",
+            long_frames,
+            expected as f64 / 1e6
+        );
+    }
+
+    if let Some(diagnosis) = stats::Diagnosis::classify(session.records, session.expected_interval)
+    {
+        println!("{}", diagnosis);
+    }
+}
+
+/// What a capture produced, handed back to `main` to write out.
+struct Capture {
+    /// The records of all monitors.
+    records: Vec<VBlankRecord>,
+    metadata: CaptureMetadata,
+    /// The capture was cut short by an error; `main` exits with an error
+    /// after writing what there is.
+    failed: bool,
+}
+
+/// Prints the end-of-run summary of each session and collects their records
+/// and metadata, with the records of all monitors together.
+fn finish(sessions: Vec<TimingSession>, args: &Args, mut metadata: CaptureMetadata) -> Capture {
+    let several = sessions.len() > 1;
+    let mut sessions = sessions.into_iter();
+    let mut session = sessions.next().expect("no session to finish");
+
+    let adapter_info = session.adapter().get_info();
+    metadata.crate_version = env!("CARGO_PKG_VERSION").to_string();
+    metadata.schema_version = timings::SCHEMA_VERSION;
+    metadata.shader_hash = timings::shader_hash();
+    metadata.pattern = Some(args.pattern.to_string());
+    metadata.patch = args.patch;
+    metadata.gpu_load = args.gpu_load;
+    metadata.frame_counter = args.frame_counter;
+    metadata.gpu_timing = session.gpu_timing();
+    metadata.image = args
+        .image
+        .as_ref()
+        .map(|image| image.path.display().to_string());
+    metadata.vertices = args
+        .vertices
+        .as_ref()
+        .map(|vertices| vertices.path.display().to_string());
+    metadata.backend = Some(format!("{:?}", adapter_info.backend));
+    metadata.clock = Some(session.clock_anchors().clone());
+    metadata.adapter = Some(adapter_info.name);
+    metadata.present_mode = session.present_mode().map(|mode| format!("{:?}", mode));
+    metadata.requested_present_mode = session
+        .requested_present_mode()
+        .map(|mode| format!("{:?}", mode));
+    metadata.supported_present_modes = session
+        .supported_present_modes()
+        .iter()
+        .map(|mode| format!("{:?}", mode))
+        .collect();
+    if let (Some(requested), Some(used)) =
+        (session.requested_present_mode(), session.present_mode())
+    {
+        if requested != used {
+            println!(
+                "Present mode: {:?} (FELL BACK, {:?} was requested)",
+                used, requested
+            );
+        }
+    }
+    metadata.swapchain_format = Some(format!("{:?}", session.target_format()));
+    metadata.expected_interval_ns = session.expected_interval();
+    metadata.vsync_off_benchmark = session.is_benchmark();
+    metadata.max_frame_latency = session.max_frame_latency();
+    metadata.tearing = args.allow_tearing.then(|| session.tearing());
+    metadata.independent_flip = session.independent_flip();
+    if args.disable_fullscreen_optimizations && metadata.independent_flip == Some(false) {
+        log::warn!(
+            "NO INDEPENDENT FLIP: presents still went through the compositor with fullscreen optimizations \
+             disabled; check the present_path rows. Overlays, DPI scaling or a window covering the screen \
+             can force composition"
+        );
+    }
+    metadata.effective_frame_latency = session.effective_frame_latency();
+
+    if several {
+        println!("Monitor {}:", session.monitor_id());
+    }
+    print_summary(&SummaryInput::of(&session), args);
+    check_refresh_rate(session.records(), &mut metadata);
+    check_refresh_regime(session.records(), &mut metadata);
+    if args.calibrate_photodiode {
+        metadata.photodiode_calibration =
+            stats::PhotodiodeCalibration::from_records(session.records());
+        match &metadata.photodiode_calibration {
+            Some(calibration) => println!("{}", calibration),
+            None => {
+                log::warn!("No photodiode lines followed a flash; is the sensor on the screen?")
+            }
+        }
+    }
+    for other in sessions {
+        println!("Monitor {}:", other.monitor_id());
+        print_summary(&SummaryInput::of(&other), args);
+        session.merge(other);
+    }
+
+    Capture {
+        records: session.into_records(),
+        metadata,
+        failed: false,
+    }
+}
+
+/// Compares the measured refresh rate with the one of the monitor's video
+/// mode, and notes the result in `metadata`.
+fn check_refresh_rate(records: &[VBlankRecord], metadata: &mut CaptureMetadata) {
+    let intervals = stats::vblank_intervals(records);
+    if let Some(summary) = stats::IntervalSummary::from_intervals(&intervals) {
+        let reported = metadata
+            .monitor
+            .as_ref()
+            .map(|m| m.video_mode.refresh_rate_millihertz);
+        let check = stats::RefreshRateCheck::new(summary.median, reported);
+        println!("{}", check);
+        if check.matches == Some(false) {
+            log::warn!(
+                "Display ran at {:.3} Hz instead of the {:.3} Hz of its video mode",
+                check.measured_hz,
+                check.reported_hz.unwrap_or_default()
+            );
+        }
+        metadata.refresh_rate = Some(check);
+    }
+}
+
+/// Tells whether the display refreshed at a fixed or a variable rate, and
+/// notes it in `metadata`. Needs the expected interval in `metadata`.
+fn check_refresh_regime(records: &[VBlankRecord], metadata: &mut CaptureMetadata) {
+    if metadata.vsync_off_benchmark {
+        return;
+    }
+    if let Some(regime) = stats::RefreshRegime::detect(records, metadata.expected_interval_ns) {
+        println!("{}", regime);
+        if regime.variable {
+            log::warn!(
+                "The display refreshed at a variable rate (VRR); dropped and long frame counts assume a fixed \
+                 refresh and are meaningless for this capture"
+            );
+        }
+        metadata.refresh_regime = Some(regime);
+    }
+}
+
+/// Adds the capture to the `--sqlite` database, if there is one.
+#[cfg(feature = "sqlite")]
+fn write_sqlite(args: &Args, records: &[VBlankRecord], metadata: &CaptureMetadata) {
+    if let Some(db) = &args.sqlite {
+        match timings::sqlite::write_sqlite(db, records, metadata) {
+            Ok(id) => log::info!("Added session {} to {}", id, db.display()),
+            Err(e) => log::error!("Failed to write {}: {}", db.display(), e),
+        }
+    }
+}
+
+/// Writes the records to `output` (and the `--aggregate` file and `--sqlite`
+/// database), and the metadata sidecar next to it.
+/// The records as written to the output: the [`capture_dataframe`] columns,
+/// plus one per tag with `--tag-columns`.
+fn output_dataframe(
+    records: Vec<VBlankRecord>,
+    system_time_unix_ns: i64,
+    args: &Args,
+    tags: &BTreeMap<String, String>,
+) -> PolarsResult<DataFrame> {
+    let mut df = capture_dataframe(records, system_time_unix_ns)?;
+    if args.tag_columns {
+        if let Err(e) = add_tag_columns(&mut df, tags) {
+            log::error!("Failed to add tag columns: {}", e);
+        }
+    }
+    Ok(df)
+}
+
+fn write_capture(capture: Capture, args: &Args, output: &Path) {
+    let Capture {
+        records, metadata, ..
+    } = capture;
+
+    #[cfg(feature = "sqlite")]
+    write_sqlite(args, &records, &metadata);
+
+    // synthetic captures have no clock, and their `utc_time` starts at the epoch
+    let anchor = metadata
+        .clock
+        .as_ref()
+        .map_or(0, |clock| clock.system_time_unix_ns);
+    let mut df = output_dataframe(records, anchor, args, &metadata.tags).unwrap();
+    if args.append {
+        if let Err(e) = write_df_csv_append(&mut df, output) {
+            // don't lose the capture over a mismatching file
+            let fallback = output.with_file_name(default_output_path(args.format));
+            log::error!(
+                "Failed to append to {}: {}; writing {} instead",
+                output.display(),
+                e,
+                fallback.display()
+            );
+            if let Err(e) = write_df(&mut df, &fallback, args.format) {
+                log::error!("Failed to write {}: {}", fallback.display(), e);
+            }
+        }
+    } else if let Err(e) = write_df(&mut df, output, args.format) {
+        log::error!("Failed to write {}: {}", output.display(), e);
+    }
+
+    if let Some(window) = args.aggregate {
+        let path = aggregate_path(output, args.format);
+        let result =
+            aggregate(&df, window).and_then(|mut agg| write_df(&mut agg, &path, args.format));
+        if let Err(e) = result {
+            log::error!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    let meta_path = sidecar_path(output);
+    if let Err(e) = metadata.write(&meta_path) {
+        log::error!("Failed to write {}: {}", meta_path.display(), e);
+    }
+}
+
+/// Prints the `--info` report. The adapters' present modes are probed on a
+/// hidden window, so nothing shows up on screen.
+#[cfg(not(target_arch = "wasm32"))]
+fn print_info(format: InfoFormat) {
+    let event_loop = EventLoop::new().unwrap();
+    let probe = WindowBuilder::new()
+        .with_visible(false)
+        .build(&event_loop)
+        .ok();
+
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..Default::default()
     });
+    let surface = probe.as_ref().and_then(|w| instance.create_surface(w).ok());
+    let info = timings::info::SystemInfo::collect(
+        &instance,
+        surface.as_ref(),
+        event_loop.available_monitors(),
+    );
+
+    match format {
+        InfoFormat::Table => println!("{}", info),
+        InfoFormat::Json => println!("{}", serde_json::to_string_pretty(&info).unwrap()),
+    }
+}
 
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        label: None,
-        bind_group_layouts: &[],
-        push_constant_ranges: &[],
+/// Prints the `--list-present-modes` report of a session's surface.
+fn print_present_modes(session: &TimingSession) {
+    println!("Monitor {} present modes:", session.monitor_id());
+    for mode in session.supported_present_modes() {
+        let selected = if session.present_mode() == Some(*mode) {
+            "  (selected)"
+        } else {
+            ""
+        };
+        println!("  {:?}{}", mode, selected);
+    }
+    match (session.requested_present_mode(), session.present_mode()) {
+        (Some(requested), Some(used)) if requested != used => {
+            println!(
+                "  {:?} was requested but isn't supported; fell back to {:?}",
+                requested, used
+            )
+        }
+        _ => println!("  matches the request"),
+    }
+}
+
+/// Prints the `--compare` report of two capture files and returns the exit
+/// status: 0 without regressions, 1 if a file can't be read, 2 with
+/// regressions.
+fn compare(a: &Path, b: &Path, threshold: f64) -> i32 {
+    let load = |path: &Path| {
+        read_records(path).map_err(|e| eprintln!("Failed to read {}: {}", path.display(), e))
+    };
+    let (Ok(records_a), Ok(records_b)) = (load(a), load(b)) else {
+        return 1;
+    };
+
+    println!("a: {}", a.display());
+    println!("b: {}", b.display());
+    let comparison = Comparison::new(&records_a, &records_b, threshold);
+    println!("{}", comparison);
+    if comparison.regressions.is_empty() {
+        0
+    } else {
+        2
+    }
+}
+
+/// Generates the `--synthetic` records and summarizes them like a capture's.
+/// Only `--frames` and `--duration` end its runs, or the end of a sequential
+/// `--seed-from-capture` replay. It has no clock anchors, so the `utc_time`
+/// column starts at the Unix epoch and the output is the same on every run.
+fn run_synthetic(args: &Args, mut metadata: CaptureMetadata) -> Capture {
+    let replay = args.seed_from_capture.as_deref().map(|path| {
+        let records = read_records(path).unwrap_or_else(|e| {
+            log::error!("Failed to read {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        let source = path.display().to_string();
+        Replay::from_records(source, &records, args.replay_order).unwrap_or_else(|| {
+            log::error!("{} has no vblank intervals to replay", path.display());
+            std::process::exit(1);
+        })
     });
+    let capture = SyntheticCapture {
+        interval_ns: target_interval(args, None)
+            .or(replay.as_ref().map(Replay::median_interval))
+            .unwrap_or(timings::present::synthetic::DEFAULT_INTERVAL_NS),
+        jitter_ns: args.jitter_us as i64 * 1000,
+        drop_rate: args.drop_rate,
+        seed: args.seed,
+        long_frame_tolerance: args.long_frame_tolerance,
+        replay,
+    };
+    let replay_frames = capture
+        .replay
+        .as_ref()
+        .filter(|replay| replay.order == ReplayOrder::Sequence)
+        .map(|replay| replay.intervals.len() as i64);
+    let frames = args
+        .frames
+        .or_else(|| {
+            args.duration
+                .map(|d| d.as_nanos() as i64 / capture.interval_ns)
+        })
+        .or(replay_frames)
+        .unwrap_or(DEFAULT_FRAMES);
+
+    let records: Vec<VBlankRecord> = (0..args.repeats)
+        .flat_map(|run| capture.generate(frames, &args.pattern, run))
+        .collect();
+    let dropped_frames: Vec<(i64, i64)> = records
+        .iter()
+        .filter(|r| r.event_type == EventType::DroppedFrame)
+        .map(|r| (r.frame_index, r.count))
+        .collect();
 
-    let swapchain_capabilities = surface.get_capabilities(&adapter);
-    let swapchain_format = swapchain_capabilities.formats[0];
-    let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: None,
-        layout: Some(&pipeline_layout),
-        vertex: wgpu::VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            buffers: &[],
-            compilation_options: Default::default(),
+    metadata.crate_version = env!("CARGO_PKG_VERSION").to_string();
+    metadata.schema_version = timings::SCHEMA_VERSION;
+    metadata.pattern = Some(args.pattern.to_string());
+    metadata.expected_interval_ns = Some(capture.interval_ns);
+    metadata.synthetic = Some(capture);
+
+    print_summary(
+        &SummaryInput {
+            monitor_id: 0,
+            records: &records,
+            dropped_frames: &dropped_frames,
+            dropped_frame_count: dropped_frames.iter().map(|(_, missed)| missed).sum(),
+            expected_interval: metadata.expected_interval_ns,
+            benchmark: false,
         },
-        fragment: Some(wgpu::FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            compilation_options: Default::default(),
-            targets: &[Some(swapchain_format.into())],
-        }),
-        primitive: wgpu::PrimitiveState::default(),
-        depth_stencil: None,
-        multisample: wgpu::MultisampleState::default(),
-        multiview: None,
-        cache: None,
-    });
+        args,
+    );
+    check_refresh_rate(&records, &mut metadata);
 
-    let mut config = surface
-        .get_default_config(&adapter, size.width, size.height)
-        .unwrap();
+    Capture {
+        records,
+        metadata,
+        failed: false,
+    }
+}
 
-    config.present_mode = wgpu::PresentMode::Fifo;
-    config.desired_maximum_frame_latency = 1;
-    surface.configure(&device, &config);
+/// Runs the capture without a window; see [`TimingSession::new_headless`].
+#[cfg(not(target_arch = "wasm32"))]
+async fn run_headless(
+    args: &Args,
+    output: &Path,
+    metadata: CaptureMetadata,
+    started: Instant,
+) -> Capture {
+    let config = session_config(args, target_interval(args, None));
+    let session = TimingSession::new_headless(config, 1920, 1080).await;
+    let session = attach_json_stream(session, args);
+    let mut session = attach_photodiode(session, args);
+    #[cfg(feature = "nvml")]
+    {
+        session = attach_power_sampler(session, args);
+    }
+    let interrupted = interrupt_flag();
+    let mut ring_flush = RingFlush::new(args, &metadata.tags);
+    let mut part_flush = PartFlush::new(args, output);
+    let mut stop = stop_conditions(args, started);
+    #[cfg(feature = "lsl")]
+    let lsl = lsl_outlet(args);
+    let mut udp = udp_sender(args);
+    let mut max_runtime_reached = false;
+    let mut failed = false;
 
-    unsafe {
-        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
-            let sc = surface.unwrap().swap_chain().read();
-            let sc = sc.as_ref().unwrap().as_raw();
-            sc.SetMaximumFrameLatency(1).unwrap();
-        });
+    'runs: for run in 0..args.repeats {
+        if interrupted.load(Ordering::Relaxed) {
+            break;
+        }
+        if run > 0 {
+            session.next_run();
+        }
+        let mut progress = Progress::new(verbosity(args));
+        stop.next_run();
+
+        let mut done = None;
+        while done.is_none() && !interrupted.load(Ordering::Relaxed) {
+            let vblank = match session.poll_frame() {
+                Ok(vblank) => vblank,
+                Err(e) => {
+                    // save what we have rather than losing the whole capture
+                    progress.finish();
+                    log::error!("Capture aborted: {}", e);
+                    failed = true;
+                    break 'runs;
+                }
+            };
+            #[cfg(feature = "lsl")]
+            push_lsl_marker(lsl.as_ref(), &session, vblank.as_ref());
+            send_udp_onset(udp.as_mut(), &session, vblank.as_ref());
+            progress.update(vblank.as_ref());
+            progress.print_if_due(
+                session.frame_index(),
+                stop.frames(),
+                session.dropped_frame_count(),
+            );
+            if let Some(flush) = ring_flush.as_mut() {
+                flush.flush_if_due(&session, args, output);
+            }
+            if let Some(flush) = part_flush.as_mut() {
+                flush.flush_if_due(&mut session);
+            }
+            done = stop.check(&mut session);
+        }
+        progress.finish();
+        if let Some(condition) = done {
+            log::info!("Run {} / {} done: {}", run + 1, args.repeats, condition);
+            if condition.ends_capture() {
+                log::warn!("Stopping the capture: {}", condition);
+                max_runtime_reached = true;
+                break;
+            }
+        }
     }
 
-    // open serial port
-    // let mut port = serialport::new("COM3", 115200)
-    //     .timeout(std::time::Duration::from_millis(100))
-    //     .data_bits(serialport::DataBits::Eight)
-    //     .flow_control(serialport::FlowControl::None)
-    //     .open()
-    //     .expect("Failed to open serial port");\
+    let mut capture = finish(vec![session], args, metadata);
+    capture.failed = failed;
+    capture.metadata.max_runtime_reached = max_runtime_reached;
+    capture.metadata.flushed_parts = part_flush.map_or(0, PartFlush::finish);
+    capture
+}
 
-    let records = Arc::new(Mutex::new(Vec::new()));
+/// Drives one session per window. The first window sets the pace: it decides
+/// when a run is complete, and only it records photodiode events.
+///
+/// Returns the capture once the event loop exits, or `None` if the window
+/// was closed before it was complete.
+async fn run(
+    event_loop: EventLoop<()>,
+    windows: Vec<Arc<Window>>,
+    args: &Args,
+    output: &Path,
+    metadata: CaptureMetadata,
+    started: Instant,
+) -> Option<Capture> {
+    let mut stop = stop_conditions(args, started);
 
-    // use QueryPerformanceCounter
-    let win_start = get_qpc_timestamp().unwrap();
+    let monitors = [&metadata.monitor, &metadata.second_monitor];
+    let mut all_sessions = Vec::with_capacity(windows.len());
+    for (i, window) in windows.iter().enumerate() {
+        let refresh_rate = monitors
+            .get(i)
+            .and_then(|m| m.as_ref())
+            .map(|m| m.video_mode.refresh_rate_millihertz);
+        let config = session_config(args, target_interval(args, refresh_rate));
+        let mut session = TimingSession::new(window.clone(), config)
+            .await
+            .with_monitor_id(i as u32);
+        if args.list_present_modes {
+            print_present_modes(&session);
+        }
+        session = attach_json_stream(session, args);
+        if i == 0 {
+            session = attach_photodiode(session, args);
+            #[cfg(feature = "nvml")]
+            {
+                session = attach_power_sampler(session, args);
+            }
+        }
+        all_sessions.push(session);
+    }
 
-    // create Instant from QueryPerformanceCounter
-    let cpu_start = std::time::Instant::now();
+    // `finish` consumes the sessions, so keep them in an Option we can take from
+    let mut sessions = Some(all_sessions);
+    let mut progress = Progress::new(verbosity(args));
+    let interrupted = interrupt_flag();
+    // only the first monitor's records are flushed while running
+    let mut ring_flush = RingFlush::new(args, &metadata.tags);
+    #[cfg(feature = "lsl")]
+    let lsl = lsl_outlet(args);
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut udp = udp_sender(args);
 
-    let mut running_frame = 0;
-    let mut last_frame = 0;
+    #[cfg(feature = "metrics")]
+    let metrics = args.metrics_port.map(|port| {
+        timings::metrics::MetricsServer::start(port).unwrap_or_else(|e| {
+            log::error!("Failed to start metrics server on port {}: {}", port, e);
+            std::process::exit(1);
+        })
+    });
 
-    let window = &window;
+    let mut result = None;
+    let capture = &mut result;
+    let mut parts = PartFlush::new(args, output);
+    let part_flush = &mut parts;
     event_loop
         .run(move |event, target| {
-            // Have the closure take ownership of the resources.
-            // `event_loop.run` never returns, therefore we must do this to ensure
-            // the resources are properly cleaned up.
-            let _ = (&instance, &adapter, &shader, &pipeline_layout);
-
-            if let Event::WindowEvent {
-                window_id: _,
-                event,
-            } = event
-            {
+            let Some(all) = sessions.as_mut() else {
+                return;
+            };
+
+            if let Event::WindowEvent { window_id, event } = event {
+                let Some(index) = windows.iter().position(|w| w.id() == window_id) else {
+                    return;
+                };
+                let s = &mut all[index];
+
                 match event {
                     WindowEvent::Resized(new_size) => {
                         // Reconfigure the surface with the new size
-                        config.width = new_size.width.max(1);
-                        config.height = new_size.height.max(1);
-                        surface.configure(&device, &config);
+                        s.resize(new_size);
                         // On macos the window needs to be redrawn manually after resizing
-                        window.request_redraw();
+                        windows[index].request_redraw();
                     }
                     WindowEvent::RedrawRequested => {
-                        let frame = surface
-                            .get_current_texture()
-                            .expect("Failed to acquire next swap chain texture");
-                        let view = frame
-                            .texture
-                            .create_view(&wgpu::TextureViewDescriptor::default());
-                        let mut encoder =
-                            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                                label: None,
-                            });
-                        {
-                            let mut rpass =
-                                encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                                    label: None,
-                                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                                        view: &view,
-                                        resolve_target: None,
-                                        ops: wgpu::Operations {
-                                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                            store: wgpu::StoreOp::Store,
-                                        },
-                                    })],
-                                    depth_stencil_attachment: None,
-                                    timestamp_writes: None,
-                                    occlusion_query_set: None,
-                                });
-                            rpass.set_pipeline(&render_pipeline);
-                            if running_frame % 2 == 0 {
-                                rpass.draw(0..6, 0..1);
-                            } else {
-                                // do nothing
+                        let vblank = match s.poll_frame() {
+                            Ok(vblank) => vblank,
+                            Err(e) => {
+                                // save what we have rather than losing the whole capture
+                                progress.finish();
+                                log::error!("Capture aborted: {}", e);
+                                let mut aborted =
+                                    finish(sessions.take().unwrap(), args, metadata.clone());
+                                aborted.failed = true;
+                                *capture = Some(aborted);
+                                target.exit();
+                                return;
                             }
+                        };
+                        windows[index].request_redraw();
+                        if index > 0 {
+                            // the other displays just keep presenting alongside
+                            return;
                         }
 
-                        queue.submit(Some(encoder.finish()));
-                        frame.present();
-                        window.request_redraw();
-
-                        let mut present_stats = get_frame_stats(&surface);
-
-                        // busy wait until the flip count changes
-                        while present_stats.PresentCount == last_frame {
-                            present_stats = get_frame_stats(&surface);
-                            // sleep for 1us
-                            // std::thread::sleep(std::time::Duration::from_micros(1));
+                        let running_frame = s.frame_index();
+                        #[cfg(feature = "lsl")]
+                        push_lsl_marker(lsl.as_ref(), s, vblank.as_ref());
+                        #[cfg(not(target_arch = "wasm32"))]
+                        send_udp_onset(udp.as_mut(), s, vblank.as_ref());
+                        progress.update(vblank.as_ref());
+                        progress.print_if_due(
+                            running_frame,
+                            stop.frames(),
+                            s.dropped_frame_count(),
+                        );
+                        #[cfg(feature = "metrics")]
+                        if let Some(metrics) = &metrics {
+                            metrics.update(
+                                progress.mean_interval(),
+                                progress.last_interval(),
+                                s.dropped_frame_count(),
+                            );
                         }
 
-                        last_frame = present_stats.PresentCount;
-
-                        // get frame stats timestamp
-                        let fs_timestamp = present_stats.SyncQPCTime - win_start;
-
-                        // take cpu timestamp
-                        let cpu_timestamp = (cpu_start.elapsed().as_nanos() / 100) as i64;
-
-                        records.lock().unwrap().push(VBlankRecord {
-                            timestamp: fs_timestamp,
-                            count: present_stats.PresentRefreshCount as i64,
-                            event_type: "sync_qpc_time".to_string(),
-                        });
-
-                        records.lock().unwrap().push(VBlankRecord {
-                            timestamp: cpu_timestamp,
-                            count: present_stats.PresentRefreshCount as i64,
-                            event_type: "cpu_time".to_string(),
-                        });
-
-                        running_frame = running_frame + 1;
-                        println!("Collecting frame: {} / {}", running_frame, COLLECT_FRAMES);
-
-                        if running_frame > COLLECT_FRAMES {
-                            // write to csv
-                            let mut df = struct_to_dataframe!(
-                                records.lock().unwrap().clone(),
-                                [timestamp, count, event_type]
-                            )
-                            .unwrap();
-                            write_df_csv(&mut df).unwrap();
+                        if let Some(flush) = ring_flush.as_mut() {
+                            flush.flush_if_due(s, args, output);
+                        }
+                        if let Some(flush) = part_flush.as_mut() {
+                            flush.flush_if_due(s);
+                        }
 
+                        let aborted = interrupted.load(Ordering::Relaxed);
+                        if aborted {
+                            progress.finish();
+                            log::warn!("Interrupted, saving {} frames", running_frame.max(0));
+                            *capture =
+                                Some(finish(sessions.take().unwrap(), args, metadata.clone()));
+                            target.exit();
+                        } else if let Some(condition) = stop.check(s) {
+                            progress.finish();
+                            let run_id = s.run_id();
+                            log::info!("Run {} / {} done: {}", run_id + 1, args.repeats, condition);
+                            if condition.ends_capture() {
+                                log::warn!("Stopping the capture: {}", condition);
+                            } else if run_id + 1 < args.repeats {
+                                all.iter_mut().for_each(TimingSession::next_run);
+                                progress = Progress::new(verbosity(args));
+                                stop.next_run();
+                                return;
+                            }
+                            let mut finished =
+                                finish(sessions.take().unwrap(), args, metadata.clone());
+                            finished.metadata.max_runtime_reached = condition.ends_capture();
+                            *capture = Some(finished);
                             target.exit();
                         }
                     }
-                    WindowEvent::CloseRequested => target.exit(),
+                    WindowEvent::MouseInput {
+                        state: ElementState::Pressed,
+                        ..
+                    } if args.input_latency => s.register_input(),
+                    WindowEvent::KeyboardInput { event, .. }
+                        if args.input_latency
+                            && event.state == ElementState::Pressed
+                            && !event.repeat =>
+                    {
+                        s.register_input()
+                    }
+                    WindowEvent::CloseRequested => {
+                        // dropping the sessions stops the photodiode reader
+                        sessions = None;
+                        target.exit()
+                    }
                     _ => {}
                 };
             }
         })
         .unwrap();
+    if let (Some(capture), Some(flush)) = (result.as_mut(), parts) {
+        capture.metadata.flushed_parts = flush.finish();
+    }
+    result
 }
 
-pub fn get_frame_stats(surface: &wgpu::Surface) -> DXGI_FRAME_STATISTICS {
-    let mut present_stats: DXGI_FRAME_STATISTICS = DXGI_FRAME_STATISTICS::default();
+pub fn main() {
+    // what --max-runtime counts from
+    let started = Instant::now();
+    // parse arguments before anything else so bad input fails before the window opens
+    let mut args = Args::parse();
+    if args.calibrate_photodiode {
+        args.pattern = CALIBRATION_PATTERN.parse().unwrap();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    init_logging(args.log_file.as_deref());
+    #[cfg(target_arch = "wasm32")]
+    {
+        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+        console_log::init().expect("could not initialize logger");
+    }
 
-    unsafe {
-        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
-            let sc = surface.unwrap().swap_chain().read();
-            let sc = sc.as_ref().unwrap().as_raw();
-            sc.GetFrameStatistics(&mut present_stats);
-        })
-    };
+    let mut tags = BTreeMap::new();
+    for (key, value) in &args.tags {
+        if tags.insert(key.clone(), value.clone()).is_some() {
+            eprintln!("Tag {:?} given more than once", key);
+            std::process::exit(1);
+        }
+    }
 
-    present_stats
-}
-pub fn main() {
+    if args.append && args.format != OutputFormat::Csv {
+        eprintln!("--append is only supported for CSV output");
+        std::process::exit(1);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(format) = args.info {
+        print_info(format);
+        return;
+    }
+
+    if let Some(paths) = &args.compare {
+        std::process::exit(compare(&paths[0], &paths[1], args.regression_threshold));
+    }
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output_path(args.format));
+    if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+        if !dir.is_dir() {
+            log::error!("Output directory {} does not exist", dir.display());
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(windows)]
+    let qpc_check = check_qpc(&args);
+    #[cfg(not(windows))]
+    let qpc_check = None;
+    // the capture runs on this thread, whichever mode drives it
+    let thread = apply_thread_settings(&args);
+
+    if args.synthetic {
+        let metadata = CaptureMetadata {
+            tags,
+            ..Default::default()
+        };
+        let capture = run_synthetic(&args, metadata);
+        write_capture(capture, &args, &output);
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.headless {
+        let metadata = CaptureMetadata {
+            qpc_check,
+            thread,
+            tags,
+            ..Default::default()
+        };
+        let capture = pollster::block_on(run_headless(&args, &output, metadata, started));
+        let failed = capture.failed;
+        write_capture(capture, &args, &output);
+        if failed {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let fullscreen_optimizations = args
+        .disable_fullscreen_optimizations
+        .then(disable_fullscreen_optimizations)
+        .flatten();
     let event_loop = EventLoop::new().unwrap();
-    #[allow(unused_mut)]
-    let primary_monitor = event_loop
-        .available_monitors()
-        .last()
-        .expect("Failed to get primary monitor");
+    let primary_monitor = select_monitor(&event_loop, args.monitor.as_deref());
     let video_mode = primary_monitor
         .video_modes()
         .next()
         .expect("Failed to get video mode");
-    println!("Video mode: {:?}", video_mode);
-    let mut builder = winit::window::WindowBuilder::new().with_fullscreen(Some(
-        winit::window::Fullscreen::Borderless(Some(primary_monitor)),
-    ));
+    log::info!("Video mode: {:?}", video_mode);
+    let mut metadata = CaptureMetadata {
+        monitor: Some(MonitorInfo::new(&primary_monitor, &video_mode)),
+        window_mode: Some(window_mode(&args)),
+        fullscreen_optimizations,
+        qpc_check,
+        thread,
+        tags,
+        ..Default::default()
+    };
+    if args.windowed.is_some() {
+        log::warn!(
+            "Capturing in a window; timings go through the compositor and aren't representative"
+        );
+    }
+    #[allow(unused_mut)]
+    let mut builder = window_builder(primary_monitor, video_mode, &args);
     #[cfg(target_arch = "wasm32")]
     {
         use wasm_bindgen::JsCast;
@@ -300,33 +1813,36 @@ pub fn main() {
             .unwrap();
         builder = builder.with_canvas(Some(canvas));
     }
-    let window = builder.build(&event_loop).unwrap();
+    let mut windows = vec![Arc::new(builder.build(&event_loop).unwrap())];
+
+    if let Some(selector) = args.second_monitor.as_deref() {
+        let monitor = select_monitor(&event_loop, Some(selector));
+        let video_mode = monitor
+            .video_modes()
+            .next()
+            .expect("Failed to get video mode");
+        log::info!("Second monitor video mode: {:?}", video_mode);
+        metadata.second_monitor = Some(MonitorInfo::new(&monitor, &video_mode));
+        let window = window_builder(monitor, video_mode, &args)
+            .build(&event_loop)
+            .unwrap();
+        windows.push(Arc::new(window));
+    }
 
     #[cfg(not(target_arch = "wasm32"))]
+    if let Some(capture) =
+        pollster::block_on(run(event_loop, windows, &args, &output, metadata, started))
     {
-        env_logger::init();
-        pollster::block_on(run(event_loop, window));
+        let failed = capture.failed;
+        write_capture(capture, &args, &output);
+        if failed {
+            std::process::exit(1);
+        }
     }
     #[cfg(target_arch = "wasm32")]
-    {
-        std::panic::set_hook(Box::new(console_error_panic_hook::hook));
-        console_log::init().expect("could not initialize logger");
-        wasm_bindgen_futures::spawn_local(run(event_loop, window));
-    }
-}
-
-pub fn get_qpc_timestamp() -> windows::core::Result<i64> {
-    let mut timestamp: i64 = 0;
-    unsafe {
-        windows::Win32::System::Performance::QueryPerformanceCounter(&mut timestamp)?;
-    }
-    Ok(timestamp)
-}
-fn write_df_csv(df: &mut DataFrame) -> PolarsResult<()> {
-    let mut file = File::create("example.csv").expect("could not create file");
-
-    CsvWriter::new(&mut file)
-        .include_header(true)
-        .with_separator(b',')
-        .finish(df)
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Some(capture) = run(event_loop, windows, &args, &output, metadata, started).await {
+            write_capture(capture, &args, &output);
+        }
+    });
 }
@@ -0,0 +1,220 @@
+//! Description of the capture setup, written as a JSON sidecar next to the data.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use winit::monitor::{MonitorHandle, VideoMode};
+
+use crate::{
+    clock::QpcCheck,
+    compositor::FullscreenOptimizations,
+    edid::{self, Edid},
+    pattern::Patch,
+    priority::ThreadSettings,
+    stats::{PhotodiodeCalibration, RefreshRateCheck, RefreshRegime},
+    synthetic::SyntheticCapture,
+};
+
+/// The display a capture was taken on.
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorInfo {
+    pub name: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub position: (i32, i32),
+    pub refresh_rate_millihertz: Option<u32>,
+    /// The video mode that was selected for the capture.
+    pub video_mode: VideoModeInfo,
+    /// Manufacturer, model and serial number the panel reports (Windows
+    /// only).
+    pub edid: Option<Edid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoModeInfo {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u16,
+    pub refresh_rate_millihertz: u32,
+}
+
+impl MonitorInfo {
+    pub fn new(monitor: &MonitorHandle, video_mode: &VideoMode) -> Self {
+        let size = monitor.size();
+        let position = monitor.position();
+        Self {
+            name: monitor.name(),
+            width: size.width,
+            height: size.height,
+            position: (position.x, position.y),
+            refresh_rate_millihertz: monitor.refresh_rate_millihertz(),
+            video_mode: VideoModeInfo::new(video_mode),
+            edid: edid::monitor_edid(monitor),
+        }
+    }
+}
+
+impl VideoModeInfo {
+    pub fn new(video_mode: &VideoMode) -> Self {
+        let size = video_mode.size();
+        Self {
+            width: size.width,
+            height: size.height,
+            bit_depth: video_mode.bit_depth(),
+            refresh_rate_millihertz: video_mode.refresh_rate_millihertz(),
+        }
+    }
+}
+
+/// How the capture window covered the display.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowMode {
+    /// Fullscreen window composed by the OS; may add compositor latency
+    Borderless,
+    /// Takes over the display's video mode, bypassing the compositor
+    Exclusive,
+    /// A normal decorated window (`--windowed`), for development only; its
+    /// timings are not representative of a fullscreen stimulus
+    #[value(skip)]
+    Windowed,
+}
+
+/// The clock values that were subtracted from the recorded timestamps, so they
+/// can be mapped back onto an absolute timeline.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClockAnchors {
+    /// QueryPerformanceFrequency in ticks per second (Windows only).
+    pub qpc_frequency: Option<i64>,
+    /// Raw QueryPerformanceCounter value at the moment the `cpu_time` rows
+    /// count from (Windows only).
+    pub qpc_start: Option<i64>,
+    /// Start of the capture in the present timing clock, in ns. This is what
+    /// was subtracted from the `sync_qpc_time` rows.
+    pub present_clock_start_ns: i64,
+    /// How far apart the zero points of the `sync_qpc_time` and `cpu_time`
+    /// rows can be, in ns; `None` without a present timing source.
+    pub anchor_uncertainty_ns: Option<i64>,
+    /// Wall-clock time at the start of the capture, as nanoseconds since the
+    /// Unix epoch and as RFC 3339. `cpu_time` rows count from this moment, and
+    /// the `utc_time` column is computed from it.
+    pub system_time_unix_ns: i64,
+    pub system_time: String,
+}
+
+/// Everything about a capture that isn't a per-frame record.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CaptureMetadata {
+    /// Version of this tool.
+    pub crate_version: String,
+    /// Version of the record columns, see [`SCHEMA_VERSION`](crate::SCHEMA_VERSION).
+    pub schema_version: u32,
+    /// Graphics backend the capture actually ran on.
+    pub backend: Option<String>,
+    /// The `--pattern` that was drawn.
+    pub pattern: Option<String>,
+    /// The `--image` drawn instead of the pattern's stimulus, if any.
+    pub image: Option<String>,
+    /// The `--vertices` file drawn instead of the pattern's stimulus, if any.
+    pub vertices: Option<String>,
+    /// The rectangle the stimulus was drawn in (`--patch`), if not the whole
+    /// screen.
+    pub patch: Option<Patch>,
+    /// Extra full-screen passes drawn per frame (`--gpu-load`).
+    pub gpu_load: u32,
+    /// Whether `gpu_render` rows were recorded; `--gpu-timing` needs an
+    /// adapter with timestamp queries.
+    pub gpu_timing: bool,
+    /// Whether the frame index was drawn in the corner (`--frame-counter`).
+    pub frame_counter: bool,
+    /// Hash of the shader source, see [`crate::shader_hash`].
+    pub shader_hash: String,
+    /// `None` for headless captures.
+    pub monitor: Option<MonitorInfo>,
+    /// Display recorded as `monitor_id` 1, if capturing on two at once.
+    pub second_monitor: Option<MonitorInfo>,
+    /// `None` for headless captures.
+    pub window_mode: Option<WindowMode>,
+    pub clock: Option<ClockAnchors>,
+    /// Startup check of the performance counter (Windows only).
+    pub qpc_check: Option<QpcCheck>,
+    /// The `--pin-cpu` and `--high-priority` settings that took effect.
+    pub thread: Option<ThreadSettings>,
+    /// Name of the GPU the capture ran on.
+    pub adapter: Option<String>,
+    /// Swapchain present mode actually used; `None` for headless captures.
+    pub present_mode: Option<String>,
+    /// The present mode asked for, which differs from `present_mode` if the
+    /// surface didn't support it.
+    pub requested_present_mode: Option<String>,
+    /// With `--allow-tearing`, whether presents could tear and `tear` rows
+    /// were recorded.
+    pub tearing: Option<bool>,
+    /// With `--disable-fullscreen-optimizations`, whether they were already
+    /// off for this process or only from the next launch.
+    pub fullscreen_optimizations: Option<FullscreenOptimizations>,
+    /// Whether the last frame bypassed the compositor (DX12 only), see the
+    /// `present_path` rows for every frame.
+    pub independent_flip: Option<bool>,
+    /// Every present mode the surface supported.
+    pub supported_present_modes: Vec<String>,
+    /// Texture format rendered to, e.g. `Rgb10a2Unorm`.
+    pub swapchain_format: Option<String>,
+    /// Requested maximum frame latency; `None` for headless captures.
+    pub max_frame_latency: Option<u32>,
+    /// What the swapchain reported using (DX12 only).
+    pub effective_frame_latency: Option<u32>,
+    /// Set for `--vsync off` runs, whose records are CPU frame times only.
+    pub vsync_off_benchmark: bool,
+    /// Part files written by `--flush-interval` before the output, which
+    /// holds the rest.
+    pub flushed_parts: u32,
+    /// Whether `--max-runtime` ended the capture.
+    pub max_runtime_reached: bool,
+    /// Refresh interval long frames were judged against, in ns.
+    pub expected_interval_ns: Option<i64>,
+    /// Measured against reported refresh rate of the (first) monitor.
+    pub refresh_rate: Option<RefreshRateCheck>,
+    /// Whether the (first) monitor refreshed at a fixed or variable rate.
+    pub refresh_regime: Option<RefreshRegime>,
+    /// Photodiode latency from a `--calibrate-photodiode` capture, to
+    /// correct the `photodiode_arrival` rows of later ones.
+    pub photodiode_calibration: Option<PhotodiodeCalibration>,
+    /// Settings of a `--synthetic` capture, whose records weren't measured.
+    pub synthetic: Option<SyntheticCapture>,
+    /// User-supplied labels from `--tag key=value`.
+    pub tags: BTreeMap<String, String>,
+}
+
+impl CaptureMetadata {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+/// Parses a `key=value` tag. Neither side may be empty.
+pub fn parse_tag(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid tag {:?}, expected key=value", s))?;
+    let (key, value) = (key.trim(), value.trim());
+    if key.is_empty() {
+        return Err(format!("tag {:?} has an empty key", s));
+    }
+    if value.is_empty() {
+        return Err(format!("tag {:?} has an empty value", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// `capture.csv` -> `capture.meta.json`
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("meta.json")
+}
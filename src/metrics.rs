@@ -0,0 +1,85 @@
+//! Prometheus endpoint exposing live timing statistics.
+//!
+//! Only built with the `metrics` feature. The values are written from the
+//! render loop into atomics and formatted on request by a server thread, so
+//! scraping never blocks a frame.
+
+use std::{
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[derive(Debug, Default)]
+struct Gauges {
+    // f64 bit patterns, NaN until the first interval is known
+    mean_interval_ms: AtomicU64,
+    last_interval_ms: AtomicU64,
+    dropped_frames: AtomicI64,
+}
+
+/// Handle for updating the gauges served on `/metrics`.
+#[derive(Debug, Clone)]
+pub struct MetricsServer {
+    gauges: Arc<Gauges>,
+}
+
+impl MetricsServer {
+    /// Starts serving on all interfaces at `port`.
+    pub fn start(port: u16) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let server = tiny_http::Server::http(("0.0.0.0", port))?;
+        let gauges = Arc::new(Gauges {
+            mean_interval_ms: AtomicU64::new(f64::NAN.to_bits()),
+            last_interval_ms: AtomicU64::new(f64::NAN.to_bits()),
+            dropped_frames: AtomicI64::new(0),
+        });
+
+        let served = gauges.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let body = render(&served);
+                let header = tiny_http::Header::from_bytes(
+                    &b"Content-Type"[..],
+                    &b"text/plain; version=0.0.4"[..],
+                )
+                .unwrap();
+                let _ = request.respond(tiny_http::Response::from_string(body).with_header(header));
+            }
+        });
+
+        log::info!("Serving Prometheus metrics on port {}", port);
+        Ok(Self { gauges })
+    }
+
+    /// Publishes the latest values. Intervals are in nanoseconds.
+    pub fn update(&self, mean_interval: Option<f64>, last_interval: Option<f64>, dropped: i64) {
+        let ms = |ns: Option<f64>| ns.map_or(f64::NAN, |ns| ns / 1e6).to_bits();
+        self.gauges
+            .mean_interval_ms
+            .store(ms(mean_interval), Ordering::Relaxed);
+        self.gauges
+            .last_interval_ms
+            .store(ms(last_interval), Ordering::Relaxed);
+        self.gauges.dropped_frames.store(dropped, Ordering::Relaxed);
+    }
+}
+
+fn render(gauges: &Gauges) -> String {
+    let load = |v: &AtomicU64| f64::from_bits(v.load(Ordering::Relaxed));
+    format!(
+        "# HELP timings_mean_frame_interval_ms Running mean vblank interval.\n\
+         # TYPE timings_mean_frame_interval_ms gauge\n\
+         timings_mean_frame_interval_ms {}\n\
+         # HELP timings_frame_interval_ms Most recent vblank interval.\n\
+         # TYPE timings_frame_interval_ms gauge\n\
+         timings_frame_interval_ms {}\n\
+         # HELP timings_dropped_frames Refresh intervals missed since the start.\n\
+         # TYPE timings_dropped_frames gauge\n\
+         timings_dropped_frames {}\n",
+        load(&gauges.mean_interval_ms),
+        load(&gauges.last_interval_ms),
+        gauges.dropped_frames.load(Ordering::Relaxed),
+    )
+}
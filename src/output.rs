@@ -0,0 +1,445 @@
+//! Writing the collected DataFrame to disk, and reading it back.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use polars::prelude::*;
+
+use crate::{records_from_dataframe, validate_schema, VBlankRecord};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Parquet,
+    /// Arrow IPC (Feather v2), memory-mappable from pyarrow/pandas
+    Arrow,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+        }
+    }
+
+    /// The format a file with `path`'s extension was written in.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(OutputFormat::Csv),
+            "parquet" => Some(OutputFormat::Parquet),
+            "arrow" | "ipc" | "feather" => Some(OutputFormat::Arrow),
+            _ => None,
+        }
+    }
+}
+
+/// Adds one column per tag, holding its value on every row. Fails if a tag
+/// would replace one of the record columns.
+pub fn add_tag_columns<'a>(
+    df: &mut DataFrame,
+    tags: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> PolarsResult<()> {
+    let height = df.height();
+    for (key, value) in tags {
+        if df.get_column_index(key).is_some() {
+            polars_bail!(Duplicate: "tag {:?} clashes with an existing column", key);
+        }
+        df.with_column(Series::new(
+            key.as_str().into(),
+            vec![value.as_str(); height],
+        ))?;
+    }
+    Ok(())
+}
+
+/// Summarises the records of `df` in consecutive windows of `window`
+/// length: frames displayed, their mean vblank interval and the refresh
+/// intervals dropped, per run and monitor.
+pub fn aggregate(df: &DataFrame, window: Duration) -> PolarsResult<DataFrame> {
+    let window_ns = window.as_nanos() as i64;
+    let event_type = || col("event_type").cast(DataType::String);
+    let is_vblank = || event_type().eq(lit("sync_qpc_time"));
+
+    df.clone()
+        .lazy()
+        .with_columns([
+            col("timestamp").floor_div(lit(window_ns)).alias("window"),
+            // only meaningful on vblank rows, where it's the interval to the previous one
+            col("timestamp")
+                .diff(1, NullBehavior::Ignore)
+                .over([col("run_id"), col("monitor_id"), col("event_type")])
+                .alias("interval"),
+        ])
+        .group_by_stable([col("run_id"), col("monitor_id"), col("window")])
+        .agg([
+            col("timestamp").filter(is_vblank()).count().alias("frames"),
+            col("interval")
+                .filter(is_vblank())
+                .mean()
+                .alias("mean_interval_ns"),
+            col("count")
+                .filter(event_type().eq(lit("dropped_frame")))
+                .sum()
+                .alias("dropped"),
+        ])
+        .with_column((col("window") * lit(window_ns)).alias("window_start_ns"))
+        .drop([col("window")])
+        .collect()
+}
+
+/// `capture.csv` -> `capture.aggregate.csv`
+pub fn aggregate_path(output: &Path, format: OutputFormat) -> PathBuf {
+    output.with_extension(format!("aggregate.{}", format.extension()))
+}
+
+/// `capture.csv` -> `capture.part0001.csv` for `part` 1
+pub fn part_path(output: &Path, part: u32, format: OutputFormat) -> PathBuf {
+    output.with_extension(format!("part{:04}.{}", part, format.extension()))
+}
+
+/// Parses a duration like `500ms`, `10s`, `5m` or `2h`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}, expected e.g. 500ms, 10s or 5m", s))?;
+    let secs = |per_unit: u64| value.checked_mul(per_unit).map(Duration::from_secs);
+    let duration = match unit {
+        "ms" => Some(Duration::from_millis(value)),
+        "s" | "" => Some(Duration::from_secs(value)),
+        "m" => secs(60),
+        "h" => secs(60 * 60),
+        _ => {
+            return Err(format!(
+                "invalid duration unit {:?}, expected ms, s, m or h",
+                unit
+            ))
+        }
+    }
+    .ok_or_else(|| format!("duration {:?} is too long", s))?;
+    if duration.is_zero() {
+        return Err("duration must not be zero".to_string());
+    }
+    Ok(duration)
+}
+
+pub fn write_df(df: &mut DataFrame, path: &Path, format: OutputFormat) -> PolarsResult<()> {
+    match format {
+        OutputFormat::Csv => write_df_csv(df, path),
+        OutputFormat::Parquet => write_df_parquet(df, path),
+        OutputFormat::Arrow => write_df_arrow(df, path),
+    }
+}
+
+pub fn write_df_csv(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let mut file = File::create(path)?;
+
+    CsvWriter::new(&mut file)
+        .include_header(true)
+        .with_separator(b',')
+        .finish(df)
+}
+
+/// Appends `df` to an existing CSV file at `path` without repeating the
+/// header, or creates it if there is none yet.
+///
+/// The file's header must match the columns of `df`. Run ids continue after
+/// the highest one already in the file, so appended runs stay distinct.
+pub fn write_df_csv_append(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let header = match File::open(path) {
+        Ok(file) => {
+            let mut header = String::new();
+            BufReader::new(file).read_line(&mut header)?;
+            header.trim_end().to_string()
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if header.is_empty() {
+        return write_df_csv(df, path);
+    }
+
+    let columns = df
+        .get_column_names()
+        .iter()
+        .map(|name| name.as_str())
+        .collect::<Vec<_>>()
+        .join(",");
+    if header != columns {
+        polars_bail!(
+            SchemaMismatch: "{} has columns {:?}, but this capture has {:?}",
+            path.display(), header, columns
+        );
+    }
+
+    let existing = CsvReadOptions::default()
+        .with_has_header(true)
+        .with_columns(Some(["run_id".into()].into()))
+        .try_into_reader_with_file_path(Some(path.to_path_buf()))?
+        .finish()?;
+    let last_run = existing
+        .column("run_id")?
+        .as_materialized_series()
+        .cast(&DataType::UInt32)?
+        .u32()?
+        .max();
+    if let Some(last_run) = last_run {
+        let run_id = df.column("run_id")?.as_materialized_series() + (last_run + 1);
+        df.with_column(run_id)?;
+    }
+
+    let mut file = File::options().append(true).open(path)?;
+    CsvWriter::new(&mut file)
+        .include_header(false)
+        .with_separator(b',')
+        .finish(df)
+}
+
+pub fn read_df(path: &Path, format: OutputFormat) -> PolarsResult<DataFrame> {
+    match format {
+        OutputFormat::Csv => read_df_csv(path),
+        OutputFormat::Parquet => read_df_parquet(path),
+        OutputFormat::Arrow => read_df_arrow(path),
+    }
+}
+
+/// Reads a CSV capture written by [`write_df_csv`].
+pub fn read_df_csv(path: &Path) -> PolarsResult<DataFrame> {
+    CsvReadOptions::default()
+        .with_has_header(true)
+        .try_into_reader_with_file_path(Some(path.to_path_buf()))?
+        .finish()
+}
+
+pub fn read_df_parquet(path: &Path) -> PolarsResult<DataFrame> {
+    ParquetReader::new(File::open(path)?).finish()
+}
+
+pub fn read_df_arrow(path: &Path) -> PolarsResult<DataFrame> {
+    IpcReader::new(File::open(path)?).finish()
+}
+
+/// Reads the records of a capture file back, in the format its extension
+/// names: CSV, Parquet or Arrow as written by [`write_df`], or JSON Lines as
+/// written by [`JsonLinesWriter`] for any other extension. Columns beyond
+/// the records' own (`utc_time`, tags) are ignored.
+pub fn read_records(path: &Path) -> PolarsResult<Vec<VBlankRecord>> {
+    let Some(format) = OutputFormat::from_path(path) else {
+        return read_json_lines(path).map_err(PolarsError::from);
+    };
+    let df = read_df(path, format)?;
+    validate_schema(&df)?;
+    records_from_dataframe(&df)
+}
+
+/// Reads a file written by [`JsonLinesWriter`]. Fields other than the
+/// records' own are ignored.
+pub fn read_json_lines(path: &Path) -> io::Result<Vec<VBlankRecord>> {
+    let mut records = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            records.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(records)
+}
+
+pub fn write_df_parquet(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let mut file = File::create(path)?;
+
+    ParquetWriter::new(&mut file).finish(df)?;
+    Ok(())
+}
+
+pub fn write_df_arrow(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let mut file = File::create(path)?;
+
+    IpcWriter::new(&mut file).finish(df)
+}
+
+/// Writes records as JSON Lines while the capture is running, so a crash
+/// loses at most the current frame and the file can be followed live.
+pub struct JsonLinesWriter {
+    out: BufWriter<File>,
+}
+
+impl JsonLinesWriter {
+    /// Creates `path`, replacing any previous contents.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Opens `path` for appending, e.g. to share one file between sessions.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            out: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, record: &VBlankRecord) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_each_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for s in ["", "ms", "-5s", "1.5s", "5 s", "5d", "5sec", "0", "0ms"] {
+            assert!(parse_duration(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        let max = u64::MAX.to_string();
+        assert_eq!(parse_duration(&max), Ok(Duration::from_secs(u64::MAX)));
+        assert!(parse_duration(&format!("{}m", max)).is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+        // one digit more than a u64 holds
+        assert!(parse_duration(&format!("{}0s", max)).is_err());
+    }
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("timings-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn run_ids(df: &DataFrame) -> Vec<u32> {
+        df.column("run_id")
+            .unwrap()
+            .as_materialized_series()
+            .cast(&DataType::UInt32)
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect()
+    }
+
+    #[test]
+    fn append_continues_the_run_ids() {
+        let file = TempFile::new("append.csv");
+        let mut first = df!("timestamp" => [1i64, 2, 3], "run_id" => [0u32, 0, 1]).unwrap();
+        let mut second = df!("timestamp" => [4i64, 5], "run_id" => [0u32, 1]).unwrap();
+
+        // the first append creates the file
+        write_df_csv_append(&mut first, &file.0).unwrap();
+        write_df_csv_append(&mut second, &file.0).unwrap();
+
+        let df = read_df_csv(&file.0).unwrap();
+        assert_eq!(df.height(), 5);
+        assert_eq!(run_ids(&df), [0, 0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn append_rejects_other_columns() {
+        let file = TempFile::new("mismatch.csv");
+        let mut first = df!("timestamp" => [1i64], "run_id" => [0u32]).unwrap();
+        let mut other = df!("timestamp" => [2i64], "count" => [1i64], "run_id" => [0u32]).unwrap();
+
+        write_df_csv(&mut first, &file.0).unwrap();
+        assert!(write_df_csv_append(&mut other, &file.0).is_err());
+        // and leaves the file as it was
+        assert_eq!(read_df_csv(&file.0).unwrap().height(), 1);
+    }
+
+    fn floats(df: &DataFrame, column: &str) -> Vec<Option<f64>> {
+        df.column(column)
+            .unwrap()
+            .as_materialized_series()
+            .cast(&DataType::Float64)
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn aggregate_per_window() {
+        let df = df!(
+            "timestamp" => [0i64, 40, 90, 120, 130, 160],
+            "count" => [1i64, 2, 3, 4, 2, 5],
+            "event_type" => [
+                "sync_qpc_time",
+                "sync_qpc_time",
+                "sync_qpc_time",
+                "sync_qpc_time",
+                "dropped_frame",
+                "sync_qpc_time",
+            ],
+            "run_id" => [0u32; 6],
+            "monitor_id" => [0u32; 6],
+        )
+        .unwrap();
+        let aggregated = aggregate(&df, Duration::from_nanos(100)).unwrap();
+
+        assert_eq!(
+            aggregated
+                .get_column_names()
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "run_id",
+                "monitor_id",
+                "frames",
+                "mean_interval_ns",
+                "dropped",
+                "window_start_ns"
+            ]
+        );
+        assert_eq!(floats(&aggregated, "frames"), [Some(3.0), Some(2.0)]);
+        // the first vblank has no interval, and the dropped row doesn't end one
+        assert_eq!(
+            floats(&aggregated, "mean_interval_ns"),
+            [Some(45.0), Some(35.0)]
+        );
+        assert_eq!(floats(&aggregated, "dropped"), [Some(0.0), Some(2.0)]);
+        assert_eq!(
+            floats(&aggregated, "window_start_ns"),
+            [Some(0.0), Some(100.0)]
+        );
+    }
+}
@@ -0,0 +1,454 @@
+//! What the stimulus looks like and which frames of it are lit.
+
+use std::{fmt, path::PathBuf, str::FromStr, sync::Arc};
+
+/// The stimulus drawn on each frame.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// A full-screen fill switched on and off per frame.
+    Flicker(FlickerPattern),
+    /// A bar stepping down the screen every frame, to make tearing visible.
+    Tearing,
+    /// A bar stepping down the screen over this many frames, one region per
+    /// frame, to map when each part of a rolling-scan panel refreshes.
+    Sweep(u32),
+    /// A vertical sine grating with this many cycles per degree of visual
+    /// angle.
+    Grating(f32),
+    /// A black and white checkerboard with squares of this many pixels.
+    Checker(u32),
+}
+
+/// Bar positions of [`Pattern::Tearing`] before the bar wraps back to the
+/// top.
+pub const TEARING_STEPS: u32 = 16;
+
+impl Pattern {
+    /// Bar positions before the bar wraps back to the top, for the patterns
+    /// that draw one. The bar is `1 / steps` of the screen tall.
+    pub fn bar_steps(&self) -> Option<u32> {
+        match self {
+            Pattern::Tearing => Some(TEARING_STEPS),
+            Pattern::Sweep(steps) => Some(*steps),
+            _ => None,
+        }
+    }
+
+    /// Top edge of the [`Pattern::Tearing`] or [`Pattern::Sweep`] bar on
+    /// frame `frame`, in (fractional) scanlines of a target `height` pixels
+    /// tall. `None` for other patterns.
+    pub fn bar_top(&self, frame: i64, height: u32) -> Option<f32> {
+        let steps = self.bar_steps()?;
+        let step = frame.rem_euclid(steps as i64) as f32;
+        Some(step * height as f32 / steps as f32)
+    }
+
+    /// Whether frame `frame` should be drawn.
+    pub fn is_on(&self, frame: i64) -> bool {
+        match self {
+            Pattern::Flicker(flicker) => flicker.is_on(frame),
+            Pattern::Tearing | Pattern::Sweep(_) | Pattern::Grating(_) | Pattern::Checker(_) => {
+                true
+            }
+        }
+    }
+
+    /// Fragment shader entry point in `shader.wgsl`.
+    pub(crate) fn fragment_entry_point(&self) -> &'static str {
+        match self {
+            Pattern::Flicker(_) => "fs_main",
+            Pattern::Tearing | Pattern::Sweep(_) => "fs_tearing",
+            Pattern::Grating(_) => "fs_grating",
+            Pattern::Checker(_) => "fs_checker",
+        }
+    }
+
+    /// The `spatial` uniform for the shader: cycles per pixel for a grating
+    /// (given the display's pixels per degree), the square size otherwise.
+    pub(crate) fn spatial_uniform(&self, pixels_per_degree: f32) -> f32 {
+        match self {
+            Pattern::Grating(cycles_per_degree) => cycles_per_degree / pixels_per_degree,
+            Pattern::Checker(size) => *size as f32,
+            Pattern::Flicker(_) | Pattern::Tearing | Pattern::Sweep(_) => 0.0,
+        }
+    }
+}
+
+impl Default for Pattern {
+    fn default() -> Self {
+        Pattern::Flicker(FlickerPattern::default())
+    }
+}
+
+/// Parses `tearing`, `sweep:<frames>`, `grating:<cycles per degree>`,
+/// `checker:<pixels>`, or otherwise a [`FlickerPattern`].
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(frequency) = s.strip_prefix("grating:") {
+            return match frequency.parse::<f32>() {
+                Ok(f) if f > 0.0 && f.is_finite() => Ok(Pattern::Grating(f)),
+                _ => Err(format!(
+                    "invalid grating frequency {:?}, expected cycles per degree",
+                    frequency
+                )),
+            };
+        }
+        if let Some(frames) = s.strip_prefix("sweep:") {
+            return match frames.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(Pattern::Sweep(n)),
+                _ => Err(format!(
+                    "invalid sweep length {:?}, expected frames",
+                    frames
+                )),
+            };
+        }
+        if let Some(size) = s.strip_prefix("checker:") {
+            return match size.parse::<u32>() {
+                Ok(px) if px > 0 => Ok(Pattern::Checker(px)),
+                _ => Err(format!("invalid checker size {:?}, expected pixels", size)),
+            };
+        }
+        match s {
+            "tearing" => Ok(Pattern::Tearing),
+            _ => s.parse().map(Pattern::Flicker),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Flicker(flicker) => flicker.fmt(f),
+            Pattern::Tearing => f.write_str("tearing"),
+            Pattern::Sweep(frames) => write!(f, "sweep:{}", frames),
+            Pattern::Grating(cycles_per_degree) => write!(f, "grating:{}", cycles_per_degree),
+            Pattern::Checker(size) => write!(f, "checker:{}", size),
+        }
+    }
+}
+
+/// A periodic on/off sequence, cycled once per frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlickerPattern(Vec<bool>);
+
+impl FlickerPattern {
+    /// Whether frame `frame` should be drawn.
+    pub fn is_on(&self, frame: i64) -> bool {
+        self.0[frame.rem_euclid(self.0.len() as i64) as usize]
+    }
+}
+
+/// Alternates between lit and blank frames.
+impl Default for FlickerPattern {
+    fn default() -> Self {
+        Self(vec![true, false])
+    }
+}
+
+/// Parses a string of `1` (draw) and `0` (blank), e.g. `"11100000"`.
+impl FromStr for FlickerPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let states = s
+            .chars()
+            .map(|c| match c {
+                '1' => Ok(true),
+                '0' => Ok(false),
+                _ => Err(format!(
+                    "invalid pattern character {:?}, expected 0 or 1",
+                    c
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if states.is_empty() {
+            return Err("pattern must not be empty".to_string());
+        }
+        Ok(Self(states))
+    }
+}
+
+impl fmt::Display for FlickerPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &on in &self.0 {
+            f.write_str(if on { "1" } else { "0" })?;
+        }
+        Ok(())
+    }
+}
+
+/// On and off phases of fixed real-time length, independent of the refresh
+/// rate. Times are in nanoseconds since the start of the capture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedPattern {
+    pub on_ns: i64,
+    pub off_ns: i64,
+}
+
+impl TimedPattern {
+    /// Whether the stimulus should be on at time `t`.
+    pub fn is_on(&self, t: i64) -> bool {
+        t.rem_euclid(self.on_ns + self.off_ns) < self.on_ns
+    }
+
+    /// Time of the most recent switch between on and off at or before `t`.
+    pub fn last_transition(&self, t: i64) -> i64 {
+        let phase = t.rem_euclid(self.on_ns + self.off_ns);
+        let cycle_start = t - phase;
+        if phase < self.on_ns {
+            cycle_start
+        } else {
+            cycle_start + self.on_ns
+        }
+    }
+}
+
+/// An 8-bit sRGB color, written as hex like `ff8000` or `#ff8000`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    pub const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    /// Stands out from black and white stimuli, in camera footage too.
+    pub const MAGENTA: Color = Color {
+        r: 255,
+        g: 0,
+        b: 255,
+    };
+
+    /// `0xRRGGBB`, as stored in `frame_color` records.
+    pub fn to_packed(self) -> i64 {
+        ((self.r as i64) << 16) | ((self.g as i64) << 8) | self.b as i64
+    }
+
+    /// Clear color for a target of the given format. sRGB targets encode on
+    /// write and scRGB ones are linear, so for those the value is linearized
+    /// first to show the hex color exactly.
+    pub(crate) fn to_wgpu(self, format: wgpu::TextureFormat) -> wgpu::Color {
+        let linear = format.is_srgb() || format == wgpu::TextureFormat::Rgba16Float;
+        let channel = |c: u8| {
+            let c = c as f64 / 255.0;
+            if !linear {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        wgpu::Color {
+            r: channel(self.r),
+            g: channel(self.g),
+            b: channel(self.b),
+            a: 1.0,
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "invalid color {:?}, expected hex RGB like ff8000",
+                s
+            ));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Self {
+            r: channel(0),
+            g: channel(2),
+            b: channel(4),
+        })
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Fills the whole screen with one of two colors instead of drawing the
+/// stimulus, following the on/off state of the pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FullField {
+    pub on: Color,
+    pub off: Color,
+}
+
+impl FullField {
+    pub fn color(&self, lit: bool) -> Color {
+        if lit {
+            self.on
+        } else {
+            self.off
+        }
+    }
+}
+
+/// A rectangle of the render target in pixels, from its top-left corner,
+/// written as `x,y,width,height`. Only the stimulus inside it is drawn, e.g.
+/// to light just the spot under a photodiode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct Patch {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Patch {
+    /// The part of the patch inside a `width` x `height` target as
+    /// `(x, y, width, height)`, or `None` if none of it is.
+    pub fn clamp(&self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let right = self.x.saturating_add(self.width).min(width);
+        let bottom = self.y.saturating_add(self.height).min(height);
+        (self.x < right && self.y < bottom)
+            .then(|| (self.x, self.y, right - self.x, bottom - self.y))
+    }
+}
+
+impl FromStr for Patch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid patch {:?}, expected x,y,width,height in pixels", s);
+        let values: Vec<u32> = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match values[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Self {
+                x,
+                y,
+                width,
+                height,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A picture drawn at its native size in the middle of the screen on lit
+/// frames, instead of the pattern's stimulus.
+#[derive(Clone, PartialEq)]
+pub struct Image {
+    pub path: PathBuf,
+    pub pixels: Arc<image::RgbaImage>,
+}
+
+impl Image {
+    /// Loads a PNG file.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let pixels = image::open(path)
+            .map_err(|e| format!("failed to load image {:?}: {}", path, e))?
+            .to_rgba8();
+        Ok(Self {
+            path: PathBuf::from(path),
+            pixels: Arc::new(pixels),
+        })
+    }
+}
+
+/// A corner of a custom stimulus shape.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    /// In normalized device coordinates: -1 to 1 from left to right and from
+    /// bottom to top, whatever the resolution.
+    pub position: [f32; 2],
+    /// Blended across each triangle.
+    pub color: Color,
+}
+
+/// Triangles drawn on lit frames instead of the pattern's stimulus.
+///
+/// Loaded from a text file with one vertex per line as `x,y,color`, the
+/// position as in [`Vertex::position`] and the color as hex RGB; every three
+/// vertices make a triangle. Blank lines and lines starting with `#` are
+/// skipped. A white square in the top-right quarter:
+///
+/// ```text
+/// # x, y, color
+/// 0,0,ffffff
+/// 0,1,ffffff
+/// 1,1,ffffff
+/// 0,0,ffffff
+/// 1,1,ffffff
+/// 1,0,ffffff
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vertices {
+    pub path: PathBuf,
+    pub vertices: Vec<Vertex>,
+}
+
+impl Vertices {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+
+        let mut vertices = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                format!(
+                    "{}:{}: expected x,y,color like 0.5,-1,ffffff",
+                    path,
+                    number + 1
+                )
+            };
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let [x, y, color] = fields[..] else {
+                return Err(invalid());
+            };
+            vertices.push(Vertex {
+                position: [
+                    x.parse().map_err(|_| invalid())?,
+                    y.parse().map_err(|_| invalid())?,
+                ],
+                color: color.parse().map_err(|_| invalid())?,
+            });
+        }
+        if vertices.is_empty() || vertices.len() % 3 != 0 {
+            return Err(format!(
+                "{} has {} vertices, expected a non-zero multiple of 3 for a list of triangles",
+                path,
+                vertices.len()
+            ));
+        }
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            vertices,
+        })
+    }
+}
+
+impl fmt::Debug for Image {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Image")
+            .field("path", &self.path)
+            .field("width", &self.pixels.width())
+            .field("height", &self.pixels.height())
+            .finish()
+    }
+}
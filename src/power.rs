@@ -0,0 +1,115 @@
+//! Background sampler of the GPU's power draw and clock through NVML.
+//!
+//! Only built with the `nvml` feature, and only NVIDIA GPUs have NVML; on
+//! other GPUs, or without the driver's library, there is just no sampler.
+//! Samples are taken on a thread of their own, since an NVML query can take
+//! around a millisecond, and handed to the render loop over a channel like
+//! the photodiode's. Drops that line up with falling clocks and power at the
+//! limit point at throttling rather than pacing.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, TryIter},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use nvml_wrapper::{enum_wrappers::device::Clock, error::NvmlError, Nvml};
+
+/// One reading of the GPU. A value is `None` if the GPU doesn't report it.
+#[derive(Debug, Clone, Copy)]
+pub struct PowerSample {
+    /// Board power draw in milliwatts.
+    pub power_mw: Option<u32>,
+    /// Graphics clock in MHz.
+    pub clock_mhz: Option<u32>,
+    pub at: Instant,
+}
+
+pub struct PowerSampler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    rx: Receiver<PowerSample>,
+    device_name: String,
+}
+
+impl PowerSampler {
+    /// Starts sampling the NVIDIA GPU called `adapter_name` (as wgpu reports
+    /// it) every `interval`. Fails with [`NvmlError::NotFound`] if NVML
+    /// doesn't know a GPU of that name, e.g. because it isn't an NVIDIA one.
+    pub fn spawn(adapter_name: &str, interval: Duration) -> Result<Self, NvmlError> {
+        let nvml = Nvml::init()?;
+        let mut found = None;
+        for index in 0..nvml.device_count()? {
+            let name = nvml.device_by_index(index)?.name()?;
+            if adapter_name.contains(&name) || name.contains(adapter_name) {
+                found = Some((index, name));
+                break;
+            }
+        }
+        let (index, device_name) = found.ok_or(NvmlError::NotFound)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let thread_stop = stop.clone();
+        let handle = std::thread::spawn(move || {
+            let device = match nvml.device_by_index(index) {
+                Ok(device) => device,
+                Err(e) => {
+                    log::error!("Failed to open the GPU in NVML: {}", e);
+                    return;
+                }
+            };
+            while !thread_stop.load(Ordering::Relaxed) {
+                let sample = PowerSample {
+                    power_mw: device.power_usage().ok(),
+                    clock_mhz: device.clock_info(Clock::Graphics).ok(),
+                    at: Instant::now(),
+                };
+                if tx.send(sample).is_err() {
+                    break;
+                }
+                std::thread::sleep(interval);
+            }
+        });
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+            rx,
+            device_name,
+        })
+    }
+
+    /// The GPU's name in NVML.
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    /// Returns all samples taken since the last call, without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, PowerSample> {
+        self.rx.try_iter()
+    }
+
+    /// Signals the sampler thread to finish and waits for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for PowerSampler {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,20 @@
+//! Composition timing from the Desktop Window Manager.
+//!
+//! In borderless fullscreen the compositor owns the real vblank, so its view
+//! of the refresh cycle is an independent reference for DXGI's `SyncQPCTime`.
+
+use windows::Win32::{
+    Foundation::HWND,
+    Graphics::Dwm::{DwmGetCompositionTimingInfo, DWM_TIMING_INFO},
+};
+
+/// Returns the DWM's timing information for the whole desktop.
+pub fn get_dwm_timing() -> windows::core::Result<DWM_TIMING_INFO> {
+    let mut info = DWM_TIMING_INFO {
+        cbSize: std::mem::size_of::<DWM_TIMING_INFO>() as u32,
+        ..Default::default()
+    };
+    // a null window asks for the desktop composition timing
+    unsafe { DwmGetCompositionTimingInfo(HWND::default(), &mut info)? };
+    Ok(info)
+}
@@ -0,0 +1,194 @@
+//! Present timing through `IDXGISwapChain::GetFrameStatistics`.
+
+use std::time::Duration;
+
+use windows::Win32::{
+    Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0},
+    Graphics::Dxgi::{
+        IDXGISwapChainMedia, DXGI_FRAME_PRESENTATION_MODE_COMPOSED,
+        DXGI_FRAME_PRESENTATION_MODE_COMPOSITION_FAILURE, DXGI_FRAME_PRESENTATION_MODE_NONE,
+        DXGI_FRAME_PRESENTATION_MODE_OVERLAY, DXGI_FRAME_STATISTICS, DXGI_FRAME_STATISTICS_MEDIA,
+        DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING, DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT,
+    },
+    System::Threading::{ReleaseSemaphore, WaitForSingleObjectEx, INFINITE},
+};
+
+use windows::core::Interface;
+
+use super::{PresentStats, PresentTimingSource};
+use crate::clock::{get_qpc_frequency, get_qpc_timestamp, qpc_to_ns};
+
+pub struct Dx12TimingSource {
+    qpc_freq: i64,
+    // the swapchain's frame latency waitable object, fetched on first use;
+    // invalid if the swapchain has none or it can't be waited on
+    waitable: Option<HANDLE>,
+}
+
+impl Dx12TimingSource {
+    pub fn new() -> Self {
+        Self {
+            qpc_freq: get_qpc_frequency().unwrap(),
+            waitable: None,
+        }
+    }
+}
+
+impl Default for Dx12TimingSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Dx12TimingSource {
+    fn drop(&mut self) {
+        if let Some(handle) = self.waitable.filter(|h| !h.is_invalid()) {
+            let _ = unsafe { CloseHandle(handle) };
+        }
+    }
+}
+
+impl PresentTimingSource for Dx12TimingSource {
+    fn name(&self) -> &'static str {
+        "DXGI frame statistics"
+    }
+
+    fn now(&self) -> i64 {
+        qpc_to_ns(get_qpc_timestamp().unwrap(), self.qpc_freq)
+    }
+
+    fn latest(&mut self, surface: &wgpu::Surface) -> Option<PresentStats> {
+        let stats = get_frame_stats(surface);
+        Some(PresentStats {
+            present_count: stats.PresentCount,
+            present_refresh_count: stats.PresentRefreshCount,
+            sync_refresh_count: Some(stats.SyncRefreshCount),
+            sync_gpu_time: (stats.SyncGPUTime != 0).then_some(stats.SyncGPUTime),
+            sync_time: qpc_to_ns(stats.SyncQPCTime, self.qpc_freq),
+            presented_frame: None,
+        })
+    }
+
+    fn wait_until_ready(&mut self, surface: &wgpu::Surface, timeout: Option<Duration>) -> bool {
+        let handle = *self.waitable.get_or_insert_with(|| {
+            frame_latency_waitable_object(surface).unwrap_or_else(|| {
+                log::warn!("The swapchain has no frame latency waitable object; polling instead");
+                HANDLE::default()
+            })
+        });
+        if handle.is_invalid() {
+            return false;
+        }
+
+        let millis = timeout.map_or(INFINITE, |t| t.as_millis().min(INFINITE as u128 - 1) as u32);
+        // alertable, so it doesn't hold up APCs queued to this thread
+        // timed out, interrupted by an APC, or failed
+        if unsafe { WaitForSingleObjectEx(handle, millis, true) } != WAIT_OBJECT_0 {
+            return false;
+        }
+        // the handle is a semaphore that wgpu waits on again when acquiring
+        // the next frame; give back the count this wait took, so only
+        // wgpu's wait consumes it and the queue depth stays as configured
+        if let Err(e) = unsafe { ReleaseSemaphore(handle, 1, None) } {
+            log::warn!(
+                "Failed to release the frame latency waitable object, polling instead: {}",
+                e
+            );
+            let _ = unsafe { CloseHandle(handle) };
+            self.waitable = Some(HANDLE::default());
+        }
+        true
+    }
+}
+
+/// The handle that DXGI signals whenever the swapchain can take another
+/// frame. `None` unless the swapchain was created with
+/// `DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT`, as wgpu currently
+/// does.
+fn frame_latency_waitable_object(surface: &wgpu::Surface) -> Option<HANDLE> {
+    unsafe {
+        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
+            let sc = surface?.swap_chain().read();
+            let sc = sc.as_ref()?.as_raw();
+            let desc = sc.GetDesc1().ok()?;
+            if desc.Flags & DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0 as u32 == 0 {
+                return None;
+            }
+            Some(sc.GetFrameLatencyWaitableObject()).filter(|handle| !handle.is_invalid())
+        })
+    }
+}
+
+pub fn get_frame_stats(surface: &wgpu::Surface) -> DXGI_FRAME_STATISTICS {
+    let mut present_stats: DXGI_FRAME_STATISTICS = DXGI_FRAME_STATISTICS::default();
+
+    unsafe {
+        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
+            let sc = surface.unwrap().swap_chain().read();
+            let sc = sc.as_ref().unwrap().as_raw();
+            // fails with DXGI_ERROR_FRAME_STATISTICS_DISJOINT until the first
+            // present has been displayed; the zeroed stats are fine for that
+            let _ = sc.GetFrameStatistics(&mut present_stats);
+        })
+    };
+
+    present_stats
+}
+
+/// How the last frame got to the screen, as DXGI's `CompositionMode` code:
+/// through the compositor, a hardware overlay, directly flipped to scanout,
+/// or not at all. `None` if the surface isn't a DX12 one or the swapchain
+/// doesn't support `IDXGISwapChainMedia`.
+pub fn presentation_mode(surface: &wgpu::Surface) -> Option<i32> {
+    unsafe {
+        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
+            let sc = surface?.swap_chain().read();
+            let media: IDXGISwapChainMedia = sc.as_ref()?.as_raw().cast().ok()?;
+            let mut stats = DXGI_FRAME_STATISTICS_MEDIA::default();
+            media.GetFrameStatisticsMedia(&mut stats).ok()?;
+            Some(stats.CompositionMode.0)
+        })
+    }
+}
+
+/// Name of a [`presentation_mode`] code, as used in log messages.
+pub fn presentation_mode_name(mode: i32) -> &'static str {
+    match windows::Win32::Graphics::Dxgi::DXGI_FRAME_PRESENTATION_MODE(mode) {
+        DXGI_FRAME_PRESENTATION_MODE_COMPOSED => "composed",
+        DXGI_FRAME_PRESENTATION_MODE_OVERLAY => "overlay",
+        DXGI_FRAME_PRESENTATION_MODE_NONE => "flip",
+        DXGI_FRAME_PRESENTATION_MODE_COMPOSITION_FAILURE => "composition_failure",
+        _ => "unknown",
+    }
+}
+
+/// Whether the swapchain was created with `DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING`,
+/// which wgpu does where the system supports tearing. Only then does it
+/// present in immediate mode with `DXGI_PRESENT_ALLOW_TEARING`; otherwise
+/// windowed and borderless presents still wait for the compositor. `None`
+/// if the surface isn't a DX12 one.
+pub fn swapchain_allows_tearing(surface: &wgpu::Surface) -> Option<bool> {
+    unsafe {
+        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
+            let sc = surface?.swap_chain().read();
+            let desc = sc.as_ref()?.as_raw().GetDesc1().ok()?;
+            Some(desc.Flags & DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING.0 as u32 != 0)
+        })
+    }
+}
+
+/// Sets the maximum number of frames the swapchain may queue, and returns
+/// the value the swapchain reports back afterwards.
+pub fn set_maximum_frame_latency(
+    surface: &wgpu::Surface,
+    latency: u32,
+) -> windows::core::Result<u32> {
+    unsafe {
+        surface.as_hal::<wgpu::hal::api::Dx12, _, _>(|surface| {
+            let sc = surface.unwrap().swap_chain().read();
+            let sc = sc.as_ref().unwrap().as_raw();
+            sc.SetMaximumFrameLatency(latency)?;
+            sc.GetMaximumFrameLatency()
+        })
+    }
+}
@@ -0,0 +1,93 @@
+//! Backend-specific retrieval of past presentation timestamps.
+//!
+//! Each graphics API exposes vblank/present timing differently: DXGI through
+//! `GetFrameStatistics`, Vulkan through `VK_GOOGLE_display_timing`. The
+//! [`PresentTimingSource`] trait hides those differences from the capture loop.
+
+use std::time::Duration;
+
+#[cfg(windows)]
+pub mod dwm;
+#[cfg(windows)]
+pub mod dx12;
+pub mod synthetic;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod vulkan;
+
+/// Presentation statistics for the most recently displayed frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PresentStats {
+    /// Running count of successful presents.
+    pub present_count: u32,
+    /// Vblank count at which the last present was displayed.
+    pub present_refresh_count: u32,
+    /// Vblank count at `sync_time`, if the source reports it separately
+    /// (DXGI's `SyncRefreshCount`).
+    pub sync_refresh_count: Option<u32>,
+    /// GPU clock at that vblank, in raw ticks, if the source reports it
+    /// (DXGI's `SyncGPUTime`, which most drivers leave at zero).
+    pub sync_gpu_time: Option<i64>,
+    /// Time of that vblank in nanoseconds, in the source's own clock domain
+    /// (see [`PresentTimingSource::now`]).
+    pub sync_time: i64,
+    /// Run and frame index of the present that was displayed, if the source
+    /// can tell it apart from the one just submitted (Vulkan).
+    pub presented_frame: Option<(u32, i64)>,
+}
+
+/// A way of asking the presentation engine when frames actually hit the screen.
+pub trait PresentTimingSource {
+    /// Short human-readable name, used in log messages.
+    fn name(&self) -> &'static str;
+
+    /// Current time in nanoseconds, in the same clock domain as
+    /// [`PresentStats::sync_time`].
+    fn now(&self) -> i64;
+
+    /// Called right before `frame.present()` with the run and index of the
+    /// frame about to be presented. Sources that need to tag presents
+    /// (Vulkan) use this; the default does nothing.
+    fn before_present(&mut self, _surface: &wgpu::Surface, _run_id: u32, _frame_index: i64) {}
+
+    /// Blocks until the swapchain is ready for another frame, which with a
+    /// frame latency of 1 is when the last present reached the display, or
+    /// until `timeout` passed. Returns `false` if it didn't see the swapchain
+    /// become ready: the source can't wait this way (the default, which
+    /// returns without waiting), or the wait timed out or failed. The caller
+    /// then has to pace itself.
+    fn wait_until_ready(&mut self, _surface: &wgpu::Surface, _timeout: Option<Duration>) -> bool {
+        false
+    }
+
+    /// Returns the statistics for the last frame that reached the display,
+    /// or `None` if nothing has been reported yet.
+    fn latest(&mut self, surface: &wgpu::Surface) -> Option<PresentStats>;
+}
+
+/// Picks a timing source matching the backend the device was created on.
+///
+/// Returns `None` (after logging a warning) when the backend offers no way of
+/// querying past presentation times; callers should then fall back to
+/// recording CPU timestamps only.
+pub fn create_timing_source(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+) -> Option<Box<dyn PresentTimingSource>> {
+    let backend = adapter.get_info().backend;
+    let source: Option<Box<dyn PresentTimingSource>> = match backend {
+        #[cfg(windows)]
+        wgpu::Backend::Dx12 => Some(Box::new(dx12::Dx12TimingSource::new())),
+        #[cfg(not(target_arch = "wasm32"))]
+        wgpu::Backend::Vulkan => vulkan::VulkanTimingSource::new(device)
+            .map(|s| Box::new(s) as Box<dyn PresentTimingSource>),
+        _ => None,
+    };
+
+    if source.is_none() {
+        log::warn!(
+            "No present timing available on the {:?} backend; only CPU timestamps will be recorded",
+            backend
+        );
+    }
+    source
+}
@@ -0,0 +1,41 @@
+//! A fixed-rate clock standing in for the display in headless runs.
+
+use super::PresentStats;
+
+/// 60 Hz, the refresh interval assumed when no display is attached.
+pub const DEFAULT_INTERVAL_NS: i64 = 16_666_667;
+
+/// Reports one perfectly regular vblank per presented frame.
+#[derive(Debug, Clone, Copy)]
+pub struct SyntheticClock {
+    interval: i64,
+}
+
+impl SyntheticClock {
+    pub fn new(interval: i64) -> Self {
+        Self { interval }
+    }
+
+    /// Time of the `frame`th frame in nanoseconds since the start.
+    pub fn now(&self, frame: i64) -> i64 {
+        frame * self.interval
+    }
+
+    /// Statistics as if `frame` had been displayed on the following vblank.
+    pub fn stats(&self, frame: i64) -> PresentStats {
+        PresentStats {
+            present_count: (frame + 1) as u32,
+            present_refresh_count: (frame + 1) as u32,
+            sync_refresh_count: None,
+            sync_gpu_time: None,
+            sync_time: self.now(frame + 1),
+            presented_frame: None,
+        }
+    }
+}
+
+impl Default for SyntheticClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_INTERVAL_NS)
+    }
+}
@@ -0,0 +1,153 @@
+//! Present timing through `VK_GOOGLE_display_timing`.
+//!
+//! The extension reports past presents by the `presentID` the application
+//! attached to them, so every present is tagged with a count of the presents
+//! to the swapchain in [`PresentTimingSource::before_present`], which is
+//! mapped back to the run and frame it showed. Timestamps are in the clock domain
+//! of the presentation engine, which is `CLOCK_MONOTONIC` on Linux and QPC on
+//! Windows.
+
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::{PresentStats, PresentTimingSource};
+
+pub struct VulkanTimingSource {
+    display_timing: ash::google::display_timing::Device,
+    refresh_duration: Option<u64>,
+    last: Option<PresentStats>,
+    // the swapchain the present ids count on; a new one starts over
+    swapchain: vk::SwapchainKHR,
+    last_present_id: u32,
+    // run and frame index of the presents not reported back yet, by id
+    pending: HashMap<u32, (u32, i64)>,
+    #[cfg(windows)]
+    qpc_freq: i64,
+}
+
+impl VulkanTimingSource {
+    /// Returns `None` if the device was not created with
+    /// `Features::VULKAN_GOOGLE_DISPLAY_TIMING`.
+    pub fn new(device: &wgpu::Device) -> Option<Self> {
+        if !device
+            .features()
+            .contains(wgpu::Features::VULKAN_GOOGLE_DISPLAY_TIMING)
+        {
+            return None;
+        }
+
+        let display_timing = unsafe {
+            device.as_hal::<wgpu::hal::api::Vulkan, _, _>(|device| {
+                let device = device?;
+                Some(ash::google::display_timing::Device::new(
+                    device.shared_instance().raw_instance(),
+                    device.raw_device(),
+                ))
+            })
+        }?;
+
+        Some(Self {
+            display_timing,
+            refresh_duration: None,
+            last: None,
+            swapchain: vk::SwapchainKHR::null(),
+            last_present_id: 0,
+            pending: HashMap::new(),
+            #[cfg(windows)]
+            qpc_freq: crate::clock::get_qpc_frequency().unwrap(),
+        })
+    }
+
+    fn raw_swapchain(surface: &wgpu::Surface) -> Option<vk::SwapchainKHR> {
+        unsafe {
+            surface.as_hal::<wgpu::hal::api::Vulkan, _, _>(|surface| {
+                surface.and_then(|surface| surface.raw_swapchain())
+            })
+        }
+    }
+}
+
+impl PresentTimingSource for VulkanTimingSource {
+    fn name(&self) -> &'static str {
+        "VK_GOOGLE_display_timing"
+    }
+
+    #[cfg(unix)]
+    fn now(&self) -> i64 {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+        ts.tv_sec * 1_000_000_000 + ts.tv_nsec
+    }
+
+    #[cfg(windows)]
+    fn now(&self) -> i64 {
+        crate::clock::qpc_to_ns(crate::clock::get_qpc_timestamp().unwrap(), self.qpc_freq)
+    }
+
+    fn before_present(&mut self, surface: &wgpu::Surface, run_id: u32, frame_index: i64) {
+        let swapchain = Self::raw_swapchain(surface).unwrap_or_default();
+        if swapchain != self.swapchain {
+            self.swapchain = swapchain;
+            self.last_present_id = 0;
+            self.pending.clear();
+        }
+        // never 0, which the wait loop starts from
+        self.last_present_id = self.last_present_id.checked_add(1).unwrap_or(1);
+        let present_id = self.last_present_id;
+        self.pending.insert(present_id, (run_id, frame_index));
+
+        unsafe {
+            surface.as_hal::<wgpu::hal::api::Vulkan, _, _>(|surface| {
+                if let Some(surface) = surface {
+                    surface.set_next_present_time(vk::PresentTimeGOOGLE {
+                        present_id,
+                        desired_present_time: 0,
+                    });
+                }
+            });
+        }
+    }
+
+    fn latest(&mut self, surface: &wgpu::Surface) -> Option<PresentStats> {
+        let swapchain = Self::raw_swapchain(surface)?;
+
+        if self.refresh_duration.is_none() {
+            self.refresh_duration = unsafe {
+                self.display_timing
+                    .get_refresh_cycle_duration(swapchain)
+                    .ok()
+                    .map(|d| d.refresh_duration)
+            };
+        }
+
+        let timings = unsafe {
+            self.display_timing
+                .get_past_presentation_timing(swapchain)
+                .unwrap_or_default()
+        };
+
+        if let Some(t) = timings.last() {
+            let presented_frame = self.pending.get(&t.present_id).copied();
+            // presents older than the last one reported won't be anymore
+            self.pending.retain(|&id, _| id > t.present_id);
+            let present_refresh_count = match self.refresh_duration {
+                Some(d) if d > 0 => (t.actual_present_time / d) as u32,
+                _ => 0,
+            };
+            self.last = Some(PresentStats {
+                present_count: t.present_id,
+                present_refresh_count,
+                sync_refresh_count: None,
+                sync_gpu_time: None,
+                sync_time: t.actual_present_time as i64,
+                presented_frame,
+            });
+        }
+
+        self.last
+    }
+}
@@ -0,0 +1,102 @@
+//! Scheduling settings for the capture thread.
+//!
+//! Being migrated between cores or preempted by other processes shows up as
+//! jitter in the CPU-side timestamps and late presents. Both settings apply
+//! to the calling thread, which should be the one driving the capture.
+
+use std::io;
+
+use serde::Serialize;
+
+/// The scheduling settings that were applied, as recorded in the metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ThreadSettings {
+    /// Core the capture thread was pinned to.
+    pub pinned_cpu: Option<usize>,
+    /// Whether the process and thread priority were raised.
+    pub high_priority: bool,
+}
+
+/// Restricts the calling thread to CPU core `core`.
+#[cfg(windows)]
+pub fn pin_to_cpu(core: usize) -> io::Result<()> {
+    use windows::Win32::System::Threading::{GetCurrentThread, SetThreadAffinityMask};
+
+    if core >= usize::BITS as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "core index out of range",
+        ));
+    }
+    // returns the previous mask, or 0 on failure
+    match unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << core) } {
+        0 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Restricts the calling thread to CPU core `core`.
+#[cfg(target_os = "linux")]
+pub fn pin_to_cpu(core: usize) -> io::Result<()> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "core index out of range",
+        ));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        // pid 0 is the calling thread
+        match libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn pin_to_cpu(_core: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
+
+/// Raises the process to the high priority class and the calling thread to
+/// the highest priority within it. Stays below real-time, which could starve
+/// the compositor and input handling.
+#[cfg(windows)]
+pub fn raise_priority() -> io::Result<()> {
+    use windows::Win32::System::Threading::{
+        GetCurrentProcess, GetCurrentThread, SetPriorityClass, SetThreadPriority,
+        HIGH_PRIORITY_CLASS, THREAD_PRIORITY_HIGHEST,
+    };
+
+    unsafe {
+        SetPriorityClass(GetCurrentProcess(), HIGH_PRIORITY_CLASS)?;
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_HIGHEST)?;
+    }
+    Ok(())
+}
+
+/// Lowers the calling thread's nice value; needs `CAP_SYS_NICE` or root.
+#[cfg(unix)]
+pub fn raise_priority() -> io::Result<()> {
+    /// What the highest Windows thread priority roughly corresponds to.
+    const NICE: libc::c_int = -10;
+
+    // on Linux `who = 0` with PRIO_PROCESS means the calling thread
+    match unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICE) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(windows, unix)))]
+pub fn raise_priority() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "not supported on this platform",
+    ))
+}
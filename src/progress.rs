@@ -0,0 +1,143 @@
+//! Single-line live status printed while a capture is running.
+
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use crate::VBlankRecord;
+
+/// How often the status line is redrawn at most.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How much is printed while capturing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Nothing until the end of the run.
+    Quiet,
+    /// A status line redrawn every [`UPDATE_INTERVAL`].
+    #[default]
+    Normal,
+    /// A line for every frame.
+    Verbose,
+}
+
+#[derive(Debug, Default)]
+pub struct Progress {
+    verbosity: Verbosity,
+    last_print: Option<Instant>,
+    last_vblank: Option<(i64, i64)>,
+    interval_sum: f64,
+    interval_count: u64,
+    last_interval: Option<f64>,
+    collecting: bool,
+}
+
+impl Progress {
+    pub fn new(verbosity: Verbosity) -> Self {
+        Self {
+            verbosity,
+            ..Self::default()
+        }
+    }
+
+    /// Feeds the vblank record of the latest frame into the running statistics.
+    pub fn update(&mut self, vblank: Option<&VBlankRecord>) {
+        let Some(vblank) = vblank else {
+            return;
+        };
+        if let Some((timestamp, count)) = self.last_vblank {
+            if vblank.count != count {
+                let interval = (vblank.timestamp - timestamp) as f64;
+                self.interval_sum += interval;
+                self.interval_count += 1;
+                self.last_interval = Some(interval);
+            }
+        }
+        self.last_vblank = Some((vblank.timestamp, vblank.count));
+    }
+
+    /// Mean of all intervals seen so far, in nanoseconds.
+    pub fn mean_interval(&self) -> Option<f64> {
+        (self.interval_count > 0).then(|| self.interval_sum / self.interval_count as f64)
+    }
+
+    /// The most recent interval, in nanoseconds.
+    pub fn last_interval(&self) -> Option<f64> {
+        self.last_interval
+    }
+
+    /// Redraws the status line if the last redraw is long enough ago, or
+    /// prints a line for the frame when verbose.
+    ///
+    /// A negative `frame` means the session is still warming up. `total` is
+    /// the frame limit of the run, if it has one. Call this between frames:
+    /// stdout is only locked while writing.
+    pub fn print_if_due(&mut self, frame: i64, total: Option<i64>, dropped: i64) {
+        match self.verbosity {
+            Verbosity::Quiet => return,
+            Verbosity::Verbose => {
+                self.print_frame(frame, dropped);
+                return;
+            }
+            Verbosity::Normal => {}
+        }
+
+        if frame >= 0 && !self.collecting {
+            self.collecting = true;
+            if self.last_print.is_some() {
+                match total {
+                    Some(total) => println!("\rWarm-up finished, collecting {} frames", total),
+                    None => println!("\rWarm-up finished, collecting"),
+                }
+                self.last_print = None;
+            }
+        }
+
+        if self
+            .last_print
+            .is_some_and(|t| t.elapsed() < UPDATE_INTERVAL)
+        {
+            return;
+        }
+        self.last_print = Some(Instant::now());
+
+        let ms = |ns: Option<f64>| ns.map_or("-".to_string(), |ns| format!("{:.3}", ns / 1e6));
+        let mean = ms(self.mean_interval());
+        let last = ms(self.last_interval);
+        let fps = self
+            .mean_interval()
+            .map_or("-".to_string(), |ns| format!("{:.1}", 1e9 / ns));
+
+        let mut stdout = std::io::stdout().lock();
+        if frame < 0 {
+            let _ = write!(stdout, "\rWarming up, {} frames left   ", -frame);
+            let _ = stdout.flush();
+            return;
+        }
+        let total = total.map_or(String::new(), |total| format!(" / {}", total));
+        let _ = write!(
+            stdout,
+            "\rFrame {}{} | mean {} ms ({} fps) | last {} ms | dropped {}   ",
+            frame, total, mean, fps, last, dropped
+        );
+        let _ = stdout.flush();
+    }
+
+    fn print_frame(&self, frame: i64, dropped: i64) {
+        if frame < 0 {
+            return;
+        }
+        let last = self
+            .last_interval
+            .map_or("-".to_string(), |ns| format!("{:.3}", ns / 1e6));
+        println!("Frame {}: interval {} ms, dropped {}", frame, last, dropped);
+    }
+
+    /// Ends the status line so following output starts on a fresh line.
+    pub fn finish(&self) {
+        if self.verbosity == Verbosity::Normal {
+            println!();
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! The stimulus pipeline and its per-frame uniforms.
+
+use std::borrow::Cow;
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    pattern::{Image, Patch, Vertices},
+    SessionConfig,
+};
+
+const SHADER_SOURCE: &str = include_str!("shader.wgsl");
+
+/// FNV-1a hash of `shader.wgsl` as hex, to tell which shader a capture was
+/// rendered with. Unlike `DefaultHasher` it is stable across Rust versions.
+pub fn shader_hash() -> String {
+    let hash = SHADER_SOURCE
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Per-frame values visible to the shaders. Layout matches `Uniforms` in
+/// `shader.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    /// Render target size in pixels.
+    resolution: [f32; 2],
+    /// Index of the frame being drawn.
+    frame: u32,
+    /// Spatial parameter of the pattern, see [`Pattern::spatial_uniform`].
+    spatial: f32,
+    /// Top of the tearing or sweep bar, see [`Pattern::bar_top`].
+    bar_top: f32,
+    /// Bar positions per cycle, see [`Pattern::bar_steps`].
+    bar_steps: u32,
+}
+
+/// A vertex of the `--vertices` stimulus as uploaded. Layout matches the
+/// inputs of `vs_vertices` in `shader.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuVertex {
+    position: [f32; 2],
+    /// RGBA as written to the target, see [`Color::to_wgpu`](crate::pattern::Color::to_wgpu).
+    color: [f32; 4],
+}
+
+const GPU_VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+    wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+const GPU_VERTEX_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<GpuVertex>() as u64,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &GPU_VERTEX_ATTRIBUTES,
+};
+
+/// Blend component that leaves the render target as it was.
+const KEEP_DESTINATION: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::Zero,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Add,
+};
+
+pub struct Renderer {
+    pipeline: wgpu::RenderPipeline,
+    // the --vertices stimulus and its vertex count, drawn by `pipeline`
+    vertex_buffer: Option<(wgpu::Buffer, u32)>,
+    // the fs_load pipeline, if there is any load to draw
+    load_pipeline: Option<wgpu::RenderPipeline>,
+    counter_pipeline: Option<wgpu::RenderPipeline>,
+    gpu_load: u32,
+    patch: Option<Patch>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    spatial: f32,
+    bar_steps: u32,
+}
+
+impl Renderer {
+    /// Builds the pipelines for the stimulus of `config`, its
+    /// [`image`](SessionConfig::image) or [`vertices`](SessionConfig::vertices)
+    /// if it has one, the
+    /// [`gpu_load`](SessionConfig::gpu_load) passes and the
+    /// [`frame_counter`](SessionConfig::frame_counter).
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        format: wgpu::TextureFormat,
+        config: &SessionConfig,
+    ) -> Self {
+        // Load the shaders from disk
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER_SOURCE)),
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("uniforms"),
+            size: std::mem::size_of::<Uniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let image_view = Self::create_image_texture(device, queue, config.image.as_ref())
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        // nearest, so the image shows pixel for pixel
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("image"),
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("uniforms"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("uniforms"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&image_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label,
+                               vertex_entry_point,
+                               buffers: &[wgpu::VertexBufferLayout<'_>],
+                               fragment_entry_point,
+                               target: wgpu::ColorTargetState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some(vertex_entry_point),
+                    buffers,
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(fragment_entry_point),
+                    compilation_options: Default::default(),
+                    targets: &[Some(target)],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let fullscreen_pipeline = |label, entry_point, target| {
+            create_pipeline(label, "vs_main", &[], entry_point, target)
+        };
+
+        let entry_point = if config.image.is_some() {
+            "fs_image"
+        } else {
+            config.pattern.fragment_entry_point()
+        };
+        let pipeline = match &config.vertices {
+            Some(_) => create_pipeline(
+                "stimulus",
+                "vs_vertices",
+                &[GPU_VERTEX_LAYOUT],
+                "fs_vertices",
+                format.into(),
+            ),
+            None => fullscreen_pipeline("stimulus", entry_point, format.into()),
+        };
+        let vertex_buffer = config
+            .vertices
+            .as_ref()
+            .map(|vertices| Self::create_vertex_buffer(device, format, vertices));
+        // keeps the destination; an empty write mask would let drivers skip
+        // the fragment work altogether
+        let load_pipeline = (config.gpu_load > 0).then(|| {
+            fullscreen_pipeline(
+                "gpu load",
+                "fs_load",
+                wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState {
+                        color: KEEP_DESTINATION,
+                        alpha: KEEP_DESTINATION,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                },
+            )
+        });
+        let counter_pipeline = config
+            .frame_counter
+            .then(|| fullscreen_pipeline("frame counter", "fs_counter", format.into()));
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            load_pipeline,
+            counter_pipeline,
+            gpu_load: config.gpu_load,
+            patch: config.patch,
+            uniform_buffer,
+            bind_group,
+            spatial: config.pattern.spatial_uniform(config.pixels_per_degree),
+            bar_steps: config.pattern.bar_steps().unwrap_or(1),
+        }
+    }
+
+    /// Uploads `image`, or a white pixel as a placeholder for patterns that
+    /// don't sample it.
+    fn create_image_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: Option<&Image>,
+    ) -> wgpu::Texture {
+        let (width, height, pixels) = match image {
+            Some(image) => (
+                image.pixels.width(),
+                image.pixels.height(),
+                image.pixels.as_raw().as_slice(),
+            ),
+            None => (1, 1, &[255u8; 4][..]),
+        };
+        device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("image"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                // PNGs are sRGB encoded
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            pixels,
+        )
+    }
+
+    /// Uploads the `--vertices` stimulus with its colors as written to a
+    /// target of `format`, and returns it with its vertex count.
+    fn create_vertex_buffer(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        vertices: &Vertices,
+    ) -> (wgpu::Buffer, u32) {
+        let vertices: Vec<GpuVertex> = vertices
+            .vertices
+            .iter()
+            .map(|vertex| {
+                let color = vertex.color.to_wgpu(format);
+                GpuVertex {
+                    position: vertex.position,
+                    color: [
+                        color.r as f32,
+                        color.g as f32,
+                        color.b as f32,
+                        color.a as f32,
+                    ],
+                }
+            })
+            .collect();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vertices"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        (buffer, vertices.len() as u32)
+    }
+
+    /// Uploads the uniforms for the frame about to be drawn.
+    pub fn update(&self, queue: &wgpu::Queue, frame: i64, width: u32, height: u32, bar_top: f32) {
+        let uniforms = Uniforms {
+            resolution: [width as f32, height as f32],
+            frame: frame as u32,
+            spatial: self.spatial,
+            bar_top,
+            bar_steps: self.bar_steps,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// Records the load draws, then the stimulus draw (or nothing for a blank
+    /// frame), then the frame counter, which is drawn on every frame. Only
+    /// the stimulus is cut to the [`patch`](SessionConfig::patch) of a
+    /// `width` x `height` target.
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass<'_>, lit: bool, width: u32, height: u32) {
+        rpass.set_bind_group(0, &self.bind_group, &[]);
+        if let Some(load_pipeline) = &self.load_pipeline {
+            rpass.set_pipeline(load_pipeline);
+            for _ in 0..self.gpu_load {
+                rpass.draw(0..6, 0..1);
+            }
+        }
+        rpass.set_pipeline(&self.pipeline);
+        let scissor = match self.patch {
+            Some(patch) => patch.clamp(width, height),
+            None => Some((0, 0, width, height)),
+        };
+        if let (true, Some((x, y, w, h))) = (lit, scissor) {
+            rpass.set_scissor_rect(x, y, w, h);
+            match &self.vertex_buffer {
+                Some((buffer, count)) => {
+                    rpass.set_vertex_buffer(0, buffer.slice(..));
+                    rpass.draw(0..*count, 0..1);
+                }
+                None => rpass.draw(0..6, 0..1),
+            }
+            rpass.set_scissor_rect(0, 0, width, height);
+        } else {
+            // do nothing
+        }
+        if let Some(counter_pipeline) = &self.counter_pipeline {
+            rpass.set_pipeline(counter_pipeline);
+            rpass.draw(0..6, 0..1);
+        }
+    }
+}
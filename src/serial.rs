@@ -0,0 +1,179 @@
+//! Background reader for an external photodiode / trigger box on a serial port.
+//!
+//! The device is expected to send one decimal timestamp per line. Lines are
+//! parsed on a dedicated thread and handed to the render loop over a channel,
+//! so a slow or silent device never blocks frame presentation.
+//!
+//! Serial lines from lab hardware are noisy. Lines that don't parse, and
+//! timestamps that don't increase, are dropped and counted instead of being
+//! recorded; the count is logged when the reader stops.
+
+use std::{
+    io::{BufRead, BufReader},
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, TryIter},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// One timestamp from the device.
+#[derive(Debug, Clone, Copy)]
+pub struct PhotodiodeEvent {
+    /// As sent by the device, in its own clock.
+    pub timestamp: i64,
+    /// When its line arrived on this machine, to estimate the serial latency.
+    pub received: Instant,
+}
+
+pub struct PhotodiodeReader {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    rx: Receiver<PhotodiodeEvent>,
+    rejected: Arc<AtomicU64>,
+    qpc_timestamps: bool,
+}
+
+impl PhotodiodeReader {
+    /// Opens `port` at `baud_rate` and starts the reader thread.
+    pub fn spawn(port: &str, baud_rate: u32) -> serialport::Result<Self> {
+        let port = serialport::new(port, baud_rate)
+            // short timeout so the thread notices the stop flag promptly
+            .timeout(Duration::from_millis(100))
+            .data_bits(serialport::DataBits::Eight)
+            .flow_control(serialport::FlowControl::None)
+            .open()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+
+        let rejected = Arc::new(AtomicU64::new(0));
+
+        let thread_stop = stop.clone();
+        let thread_rejected = rejected.clone();
+        let handle = std::thread::spawn(move || {
+            let mut reader = BufReader::new(port);
+            let mut line = String::new();
+            let mut last = None;
+            while !thread_stop.load(Ordering::Relaxed) {
+                match reader.read_line(&mut line) {
+                    // a timeout can leave a partial line in the buffer, so only
+                    // parse once we've seen the newline
+                    Ok(_) if line.ends_with('\n') => {
+                        match line.trim().parse::<i64>() {
+                            Ok(timestamp) if last.map_or(true, |last| timestamp > last) => {
+                                last = Some(timestamp);
+                                let event = PhotodiodeEvent {
+                                    timestamp,
+                                    received: Instant::now(),
+                                };
+                                if tx.send(event).is_err() {
+                                    break;
+                                }
+                            }
+                            Ok(timestamp) => {
+                                log::debug!(
+                                    "Ignoring out-of-order photodiode timestamp {}",
+                                    timestamp
+                                );
+                                thread_rejected.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(_) => {
+                                log::debug!("Ignoring malformed photodiode line {:?}", line.trim());
+                                thread_rejected.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                        line.clear();
+                    }
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+                    Err(e) => {
+                        log::error!("Photodiode serial read failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+            rx,
+            rejected,
+            qpc_timestamps: false,
+        })
+    }
+
+    /// Declares that the device reports raw QueryPerformanceCounter values of
+    /// this machine, so timestamps outside the capture can be rejected too.
+    pub fn with_qpc_timestamps(mut self) -> Self {
+        self.qpc_timestamps = true;
+        self
+    }
+
+    /// Whether the device reports this machine's QPC values, see
+    /// [`with_qpc_timestamps`](Self::with_qpc_timestamps).
+    pub fn qpc_timestamps(&self) -> bool {
+        self.qpc_timestamps
+    }
+
+    /// Returns all timestamps received since the last call, without blocking.
+    pub fn try_iter(&self) -> TryIter<'_, PhotodiodeEvent> {
+        self.rx.try_iter()
+    }
+
+    /// Like [`try_iter`](Self::try_iter), but drops and counts the
+    /// timestamps outside `window`, if there is one.
+    pub fn drain_within(&self, window: Option<RangeInclusive<i64>>) -> Vec<PhotodiodeEvent> {
+        self.rx
+            .try_iter()
+            .filter(|event| {
+                let inside = window
+                    .as_ref()
+                    .map_or(true, |window| window.contains(&event.timestamp));
+                if !inside {
+                    log::debug!(
+                        "Ignoring photodiode timestamp {} outside the capture",
+                        event.timestamp
+                    );
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                }
+                inside
+            })
+            .collect()
+    }
+
+    /// Lines and timestamps dropped so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Signals the reader thread to finish and waits for it.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+            let rejected = self.rejected();
+            if rejected > 0 {
+                log::warn!(
+                    "Rejected {} malformed or out-of-range photodiode lines",
+                    rejected
+                );
+            }
+        }
+    }
+}
+
+impl Drop for PhotodiodeReader {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
@@ -0,0 +1,2121 @@
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use polars::prelude::*;
+use winit::{dpi::PhysicalSize, window::Window};
+
+#[cfg(feature = "nvml")]
+use crate::power::PowerSampler;
+use crate::{
+    capture_dataframe,
+    gpu_timer::GpuTimer,
+    metadata::ClockAnchors,
+    output::JsonLinesWriter,
+    pattern::{Color, FullField, Image, Patch, Pattern, TimedPattern, Vertices},
+    present::{self, synthetic::SyntheticClock, PresentStats, PresentTimingSource},
+    render::Renderer,
+    serial::PhotodiodeReader,
+    stats,
+    wait::{self, WaitMode},
+    EventType, VBlankRecord,
+};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// DX12 on Windows, Vulkan everywhere else
+    Auto,
+    Dx12,
+    Vulkan,
+}
+
+impl Backend {
+    pub fn to_wgpu(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto if cfg!(windows) => wgpu::Backends::DX12,
+            Backend::Auto => wgpu::Backends::VULKAN,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Wait for vblank, never tear
+    Fifo,
+    /// Wait for vblank, but tear if a frame is late
+    FifoRelaxed,
+    /// Replace the queued frame, present on vblank
+    Mailbox,
+    /// Present immediately, may tear
+    Immediate,
+}
+
+impl PresentMode {
+    pub fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+/// Bit depth of the swapchain format.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatBits {
+    /// 8 bits per channel unorm (sRGB or not)
+    #[value(name = "8")]
+    Eight,
+    /// 10 bits per color channel unorm (`Rgb10a2Unorm`)
+    #[value(name = "10")]
+    Ten,
+    /// 16-bit float scRGB (`Rgba16Float`), the HDR format on DXGI
+    #[value(name = "16f")]
+    HalfFloat,
+}
+
+impl FormatBits {
+    fn matches(self, format: wgpu::TextureFormat) -> bool {
+        use wgpu::TextureFormat as F;
+        match self {
+            FormatBits::Eight => matches!(
+                format,
+                F::Bgra8Unorm | F::Bgra8UnormSrgb | F::Rgba8Unorm | F::Rgba8UnormSrgb
+            ),
+            FormatBits::Ten => format == F::Rgb10a2Unorm,
+            FormatBits::HalfFloat => format == F::Rgba16Float,
+        }
+    }
+}
+
+/// GPU to prefer when no adapter is selected explicitly.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerPreference {
+    /// Usually the integrated GPU
+    Low,
+    /// Usually the discrete GPU
+    High,
+}
+
+impl PowerPreference {
+    pub fn to_wgpu(self) -> wgpu::PowerPreference {
+        match self {
+            PowerPreference::Low => wgpu::PowerPreference::LowPower,
+            PowerPreference::High => wgpu::PowerPreference::HighPerformance,
+        }
+    }
+}
+
+/// Which device limits to request.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LimitsPreset {
+    /// WebGL2-level limits, raised to the adapter's maximum texture resolution
+    Downlevel,
+    /// Everything the adapter supports
+    #[default]
+    Adapter,
+}
+
+impl LimitsPreset {
+    fn limits(self, adapter: &wgpu::Adapter) -> wgpu::Limits {
+        match self {
+            // Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
+            LimitsPreset::Downlevel => {
+                wgpu::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+            }
+            LimitsPreset::Adapter => adapter.limits(),
+        }
+    }
+}
+
+/// Settings for creating a [`TimingSession`].
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub backend: Backend,
+    /// Adapter to use, by index among the adapters of `backend` or by (part
+    /// of) its name. Falls back to `power_preference` if there is no such
+    /// adapter or it can't render to the window.
+    pub adapter: Option<String>,
+    pub power_preference: Option<PowerPreference>,
+    /// The stimulus and which frames draw it.
+    pub pattern: Pattern,
+    /// Display pixels per degree of visual angle, to scale the spatial
+    /// frequency of [`Pattern::Grating`].
+    pub pixels_per_degree: f32,
+    /// Draw this image on lit frames instead of the pattern's stimulus; the
+    /// pattern still decides which frames are lit.
+    pub image: Option<Image>,
+    /// Draw these triangles on lit frames instead of the pattern's
+    /// stimulus, like `image`.
+    pub vertices: Option<Vertices>,
+    /// Switch the stimulus on and off by elapsed time instead of by frame;
+    /// overrides the on/off states of `pattern`.
+    pub timed: Option<TimedPattern>,
+    /// Clear the whole screen to one of two colors instead of drawing the
+    /// stimulus; the pattern then only decides which.
+    pub full_field: Option<FullField>,
+    /// Draw the stimulus only inside this rectangle, leaving the rest of
+    /// the screen at the clear color. Parts outside the render target are
+    /// cut off.
+    pub patch: Option<Patch>,
+    /// Measure the GPU duration of each frame with timestamp queries, if the
+    /// adapter supports them.
+    pub gpu_timing: bool,
+    /// Extra full-screen passes of busy fragment work drawn every frame, to
+    /// see whether the display keeps its timing with the GPU near its frame
+    /// budget. They don't change what is shown.
+    pub gpu_load: u32,
+    /// Draw the low 16 bits of the frame index as a row of black and white
+    /// squares in the top-left corner, so a camera can read which frame is
+    /// on screen and check it against the `frame_index` column.
+    pub frame_counter: bool,
+    /// Frames of `roll_color` shown at the start of each run before the
+    /// pattern begins, as a marker for external recordings. They are
+    /// recorded, but the pattern and `--frames` count from after them.
+    pub preroll: u32,
+    /// Frames of `roll_color` shown at the end of each run, once its stop
+    /// condition is met; see [`TimingSession::begin_postroll`].
+    pub postroll: u32,
+    pub roll_color: Color,
+    /// Frames to render before recording starts, to let the swapchain and
+    /// compositor settle.
+    pub warmup_frames: u32,
+    /// How to wait for each frame to reach the display.
+    pub wait_mode: WaitMode,
+    /// Give up on a frame that isn't reported as displayed within this time,
+    /// e.g. because the monitor went to sleep. `None` waits forever.
+    pub vblank_timeout: Option<Duration>,
+    /// Swapchain format bit depth; `None` takes the surface's preferred
+    /// format, as does a depth it doesn't support.
+    pub format_bits: Option<FormatBits>,
+    /// Requested swapchain present mode; falls back to FIFO if unsupported.
+    pub present_mode: PresentMode,
+    /// With the immediate present mode, record a `tear` row for every
+    /// present estimated to have torn.
+    pub allow_tearing: bool,
+    /// With `false`, benchmark throughput instead of measuring display timing:
+    /// present immediately, don't wait for vblanks and only record CPU
+    /// frame times as `vsync_off_cpu_time`.
+    pub vsync: bool,
+    /// Device limits, shared by the windowed and headless paths.
+    pub limits: LimitsPreset,
+    /// Frames the swapchain may queue ahead of the display.
+    pub max_frame_latency: u32,
+    /// Keep only the most recent this many records (and dropped frames), so
+    /// memory stays bounded however long the capture runs.
+    pub ring: Option<usize>,
+    /// Expected refresh interval in ns, e.g. from the video mode. Without it
+    /// the interval measured over the previous frames is used. In headless
+    /// mode this sets the rate of the synthetic clock.
+    pub target_interval: Option<i64>,
+    /// How far a frame interval may deviate from the expected one, as a
+    /// fraction of it, before it is recorded as a `long_frame`.
+    pub long_frame_tolerance: f64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Auto,
+            adapter: None,
+            power_preference: None,
+            pattern: Pattern::default(),
+            pixels_per_degree: 40.0,
+            image: None,
+            vertices: None,
+            timed: None,
+            full_field: None,
+            patch: None,
+            gpu_timing: false,
+            gpu_load: 0,
+            frame_counter: false,
+            preroll: 0,
+            postroll: 0,
+            roll_color: Color::MAGENTA,
+            warmup_frames: 10,
+            wait_mode: WaitMode::default(),
+            vblank_timeout: None,
+            format_bits: None,
+            present_mode: PresentMode::Fifo,
+            allow_tearing: false,
+            vsync: true,
+            limits: LimitsPreset::default(),
+            max_frame_latency: 1,
+            ring: None,
+            target_interval: None,
+            long_frame_tolerance: 0.5,
+        }
+    }
+}
+
+/// Why [`TimingSession::poll_frame`] could not continue.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The swapchain failed in a way reconfiguring doesn't fix.
+    Surface(wgpu::SurfaceError),
+    /// No present was reported within the vblank timeout. A `stall` record
+    /// has been added.
+    Stalled(Duration),
+    /// The GPU device was lost and could not be recreated.
+    DeviceLost,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Surface(e) => write!(f, "surface error: {}", e),
+            FrameError::Stalled(timeout) => {
+                write!(f, "no vblank reported within {} ms", timeout.as_millis())
+            }
+            FrameError::DeviceLost => write!(f, "GPU device lost and could not be recreated"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<wgpu::SurfaceError> for FrameError {
+    fn from(e: wgpu::SurfaceError) -> Self {
+        FrameError::Surface(e)
+    }
+}
+
+/// How often to look for a usable window size before configuring the surface.
+const SURFACE_CONFIG_RETRIES: u32 = 20;
+const SURFACE_CONFIG_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Fraction of a refresh interval either side of the vblank assumed to be
+/// vertical blanking, where an immediate present doesn't show a tear. Real
+/// blanking is around 2 to 5% of the lines.
+const TEAR_BLANKING_FRACTION: f64 = 0.05;
+
+/// Texture format of the offscreen target in headless mode.
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A frame that reached the display.
+struct Presented {
+    stats: PresentStats,
+    /// When the CPU finished presenting it, in the timing source's clock.
+    submitted: i64,
+    /// How often the timing source was polled until it reported the frame;
+    /// `None` for the synthetic clock, which isn't polled.
+    polls: Option<u32>,
+}
+
+/// Where frames are rendered to.
+enum RenderTarget {
+    Surface {
+        // kept to recreate the surface after a device loss
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        config: wgpu::SurfaceConfiguration,
+    },
+    /// Headless mode: no swapchain and no real vblanks, timing comes from a
+    /// synthetic clock instead.
+    Offscreen {
+        texture: wgpu::Texture,
+        clock: SyntheticClock,
+    },
+    /// While a lost device is recreated: the old target is gone and its
+    /// replacement not made yet, which it stays if that fails. Keeps what
+    /// the old target reported about itself.
+    Lost {
+        size: (u32, u32),
+        format: wgpu::TextureFormat,
+        surface_config: Option<wgpu::SurfaceConfiguration>,
+    },
+}
+
+/// GPU state and collected records for one capture window.
+pub struct TimingSession {
+    target: RenderTarget,
+    // what the session was created from, to rebuild it after a device loss
+    config: SessionConfig,
+    adapter: wgpu::Adapter,
+    device: wgpu::Device,
+    // set from the device lost callback
+    device_lost: Arc<AtomicBool>,
+    device_losses: i64,
+    queue: wgpu::Queue,
+    renderer: Renderer,
+    pattern: Pattern,
+    timed: Option<TimedPattern>,
+    // input events: the next sequence number, those waiting for a frame and
+    // those answered by the frame being rendered
+    input_count: i64,
+    pending_inputs: Vec<i64>,
+    shown_inputs: Vec<i64>,
+    // lit state of the previous recorded frame, to spot timed transitions
+    last_lit: Option<bool>,
+    // DXGI composition mode of the previous frame, to report fallbacks
+    last_present_path: Option<i32>,
+    // start-up milestones, written out with the first recorded frame
+    startup: Vec<(EventType, Instant)>,
+    startup_written: bool,
+    full_field: Option<FullField>,
+    preroll: u32,
+    postroll: u32,
+    // postroll frames still to show, once the run's stop condition was met
+    postroll_left: Option<u32>,
+    roll_color: Color,
+    gpu_timer: Option<GpuTimer>,
+    wait_mode: WaitMode,
+    vblank_timeout: Option<Duration>,
+    vsync: bool,
+    // as reported back by the swapchain, where that's possible
+    effective_frame_latency: Option<u32>,
+    // what the surface offered and what was asked of it; empty and `None`
+    // when headless
+    present_modes: Vec<wgpu::PresentMode>,
+    requested_present_mode: Option<wgpu::PresentMode>,
+    // whether presents can tear and `tear` rows are recorded, see
+    // `SessionConfig::allow_tearing`
+    tearing: bool,
+    warmup_frames: u32,
+
+    timing_source: Option<Box<dyn PresentTimingSource>>,
+    photodiode: Option<PhotodiodeReader>,
+    #[cfg(feature = "nvml")]
+    power: Option<PowerSampler>,
+    stream: Option<JsonLinesWriter>,
+    on_record: Option<Box<dyn FnMut(&VBlankRecord)>>,
+
+    records: Vec<VBlankRecord>,
+    // records before this index have been written to `stream`
+    streamed: usize,
+    ring: Option<usize>,
+    // start of the capture in the timing source's clock (QPC on DX12)
+    win_start: i64,
+    cpu_start: Instant,
+    anchors: ClockAnchors,
+
+    run_id: u32,
+    monitor_id: u32,
+    frame_index: i64,
+    last_present_count: u32,
+    last_refresh_count: Option<i64>,
+    // last vblank and the refresh interval, in the timing source's clock
+    last_sync_time: Option<i64>,
+    refresh_interval: Option<i64>,
+    target_interval: Option<i64>,
+    long_frame_tolerance: f64,
+    // (frame index, missed refresh intervals)
+    dropped_frames: Vec<(i64, i64)>,
+    // kept separately, as the list above is trimmed in ring mode
+    dropped_total: i64,
+}
+
+impl TimingSession {
+    /// Sets up the device, swapchain and pipeline for rendering into `window`.
+    pub async fn new(window: Arc<Window>, config: SessionConfig) -> Self {
+        let begin = Instant::now();
+        let instance_desc = wgpu::InstanceDescriptor {
+            backends: config.backend.to_wgpu(),
+            ..Default::default()
+        };
+        let instance = wgpu::Instance::new(&instance_desc);
+        let surface = instance.create_surface(window.clone()).unwrap();
+        // Request an adapter which can render to our surface
+        let adapter = Self::select_adapter(&instance, &config, Some(&surface)).await;
+
+        let (device, queue) = Self::request_device(&adapter, &config).await;
+
+        Self::from_surface(window, surface, adapter, device, queue, config, begin)
+    }
+
+    /// Like [`new`](Self::new), but on the application's own surface, adapter
+    /// and device, for using the session as a timing add-on to a window and
+    /// event loop that the application owns. Call
+    /// [`poll_frame`](Self::poll_frame) from its `RedrawRequested` handler to
+    /// render and record a frame, and [`resize`](Self::resize) from its
+    /// `Resized` handler.
+    ///
+    /// `surface` must belong to `window` and `adapter` must be able to
+    /// present to it. The session takes it over: it configures the swapchain,
+    /// presents to it and reads its statistics, so the application must not
+    /// present to it itself. Request `device` with
+    /// [`device_features`](Self::device_features) for the Vulkan present
+    /// timing and `gpu_timing`, which are left out otherwise. After a device
+    /// loss the session recreates the surface and device with an instance of
+    /// its own.
+    pub fn attach(
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: SessionConfig,
+    ) -> Self {
+        Self::from_surface(
+            window,
+            surface,
+            adapter,
+            device,
+            queue,
+            config,
+            Instant::now(),
+        )
+    }
+
+    fn from_surface(
+        window: Arc<Window>,
+        surface: wgpu::Surface<'static>,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: SessionConfig,
+        begin: Instant,
+    ) -> Self {
+        let swapchain_capabilities = surface.get_capabilities(&adapter);
+        let swapchain_format =
+            Self::select_format(&swapchain_capabilities.formats, config.format_bits);
+        let renderer = Renderer::new(&device, &queue, swapchain_format, &config);
+        let pipeline_created = Instant::now();
+
+        let mut surface_config = Self::default_surface_config(&window, &surface, &adapter);
+        surface_config.format = swapchain_format;
+
+        let requested = if config.vsync {
+            config.present_mode.to_wgpu()
+        } else {
+            wgpu::PresentMode::Immediate
+        };
+        log::info!(
+            "Surface supports present modes {:?}",
+            swapchain_capabilities.present_modes
+        );
+        surface_config.present_mode = if swapchain_capabilities.present_modes.contains(&requested) {
+            log::info!("Using present mode {:?} as requested", requested);
+            requested
+        } else {
+            log::warn!(
+                "PRESENT MODE FALLBACK: {:?} is not supported by this surface (supported: {:?}), using Fifo \
+                 instead; the capture measures Fifo, not {:?}",
+                requested,
+                swapchain_capabilities.present_modes,
+                requested
+            );
+            wgpu::PresentMode::Fifo
+        };
+        surface_config.desired_maximum_frame_latency = config.max_frame_latency;
+        surface.configure(&device, &surface_config);
+        let surface_configured = Instant::now();
+
+        // only DXGI can tell us what the driver actually went with
+        #[cfg(windows)]
+        let effective_frame_latency = if adapter.get_info().backend == wgpu::Backend::Dx12 {
+            match present::dx12::set_maximum_frame_latency(&surface, config.max_frame_latency) {
+                Ok(effective) => {
+                    if effective == config.max_frame_latency {
+                        log::info!("Maximum frame latency set to {}", effective);
+                    } else {
+                        log::warn!(
+                            "Requested a maximum frame latency of {}, but the swapchain reports {}",
+                            config.max_frame_latency,
+                            effective
+                        );
+                    }
+                    Some(effective)
+                }
+                Err(e) => {
+                    log::warn!("Failed to set the maximum frame latency: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        #[cfg(not(windows))]
+        let effective_frame_latency = None;
+
+        let tearing = config.allow_tearing
+            && Self::check_tearing(&surface, &adapter, surface_config.present_mode);
+
+        let timing_source = present::create_timing_source(&adapter, &device);
+        if let Some(source) = &timing_source {
+            log::info!("Using {} for present timing", source.name());
+        }
+        if config.wait_mode == WaitMode::Waitable
+            && adapter.get_info().backend != wgpu::Backend::Dx12
+        {
+            log::warn!("The waitable wait mode needs DX12; sleeping between polls instead");
+        }
+
+        let target = RenderTarget::Surface {
+            window,
+            surface,
+            config: surface_config,
+        };
+        let mut session = Self::from_parts(
+            target,
+            config,
+            adapter,
+            device,
+            queue,
+            renderer,
+            timing_source,
+        );
+        session.effective_frame_latency = effective_frame_latency;
+        session.tearing = tearing;
+        session.present_modes = swapchain_capabilities.present_modes;
+        session.requested_present_mode = Some(requested);
+        session.startup = vec![
+            (EventType::StartupBegin, begin),
+            (EventType::StartupPipelineCreated, pipeline_created),
+            (EventType::StartupSurfaceConfigured, surface_configured),
+        ];
+        session
+    }
+
+    /// Sets up a session that renders into an offscreen texture of the given
+    /// size, with vblanks synthesized from a fixed clock: 60 Hz unless
+    /// [`SessionConfig::target_interval`] says otherwise.
+    ///
+    /// Nothing here needs a display, so this works in CI and produces the
+    /// same records on every run.
+    pub async fn new_headless(config: SessionConfig, width: u32, height: u32) -> Self {
+        let begin = Instant::now();
+        let instance_desc = wgpu::InstanceDescriptor {
+            backends: config.backend.to_wgpu(),
+            ..Default::default()
+        };
+        let instance = wgpu::Instance::new(&instance_desc);
+
+        let adapter = Self::select_adapter(&instance, &config, None).await;
+
+        let (device, queue) = Self::request_device(&adapter, &config).await;
+        let renderer = Renderer::new(&device, &queue, OFFSCREEN_FORMAT, &config);
+        let pipeline_created = Instant::now();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen target"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: OFFSCREEN_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let target = RenderTarget::Offscreen {
+            texture,
+            clock: config
+                .target_interval
+                .map_or_else(SyntheticClock::default, SyntheticClock::new),
+        };
+        let mut session = Self::from_parts(target, config, adapter, device, queue, renderer, None);
+        session.startup = vec![
+            (EventType::StartupBegin, begin),
+            (EventType::StartupPipelineCreated, pipeline_created),
+        ];
+        session
+    }
+
+    fn from_parts(
+        target: RenderTarget,
+        config: SessionConfig,
+        adapter: wgpu::Adapter,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        renderer: Renderer,
+        timing_source: Option<Box<dyn PresentTimingSource>>,
+    ) -> Self {
+        let (win_start, cpu_start, anchors) = capture_anchors(timing_source.as_deref());
+
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        let device_lost = watch_device(&device);
+
+        Self {
+            target,
+            config: config.clone(),
+            adapter,
+            device,
+            device_lost,
+            device_losses: 0,
+            queue,
+            renderer,
+            pattern: config.pattern,
+            timed: config.timed,
+            input_count: 0,
+            pending_inputs: Vec::new(),
+            shown_inputs: Vec::new(),
+            last_lit: None,
+            last_present_path: None,
+            startup: Vec::new(),
+            startup_written: false,
+            full_field: config.full_field,
+            preroll: config.preroll,
+            postroll: config.postroll,
+            postroll_left: None,
+            roll_color: config.roll_color,
+            gpu_timer,
+            wait_mode: config.wait_mode,
+            vblank_timeout: config.vblank_timeout,
+            vsync: config.vsync,
+            effective_frame_latency: None,
+            present_modes: Vec::new(),
+            requested_present_mode: None,
+            tearing: false,
+            warmup_frames: config.warmup_frames,
+            timing_source,
+            photodiode: None,
+            #[cfg(feature = "nvml")]
+            power: None,
+            stream: None,
+            on_record: None,
+            records: Vec::new(),
+            streamed: 0,
+            ring: config.ring,
+            win_start,
+            cpu_start,
+            anchors,
+            run_id: 0,
+            monitor_id: 0,
+            // warm-up frames count up from below zero
+            frame_index: -(config.warmup_frames as i64),
+            last_present_count: 0,
+            last_refresh_count: None,
+            last_sync_time: None,
+            refresh_interval: None,
+            target_interval: config.target_interval,
+            long_frame_tolerance: config.long_frame_tolerance,
+            dropped_frames: Vec::new(),
+            dropped_total: 0,
+        }
+    }
+
+    /// Picks the first of the surface's `formats` with the requested bit
+    /// depth, or else its preferred (first) one.
+    fn select_format(
+        formats: &[wgpu::TextureFormat],
+        bits: Option<FormatBits>,
+    ) -> wgpu::TextureFormat {
+        let Some(bits) = bits else {
+            return formats[0];
+        };
+        match formats.iter().copied().find(|&f| bits.matches(f)) {
+            Some(format) => {
+                log::info!("Using swapchain format {:?}", format);
+                format
+            }
+            None => {
+                log::warn!(
+                    "No {:?} swapchain format on this surface (supported: {:?}), using {:?}",
+                    bits,
+                    formats,
+                    formats[0]
+                );
+                formats[0]
+            }
+        }
+    }
+
+    /// Queries the surface configuration for the window's current size.
+    ///
+    /// Some drivers transiently report a zero size (or no configuration)
+    /// while entering fullscreen, so this retries for a while before settling
+    /// for a 1x1 surface that the next resize corrects.
+    fn default_surface_config(
+        window: &Window,
+        surface: &wgpu::Surface<'_>,
+        adapter: &wgpu::Adapter,
+    ) -> wgpu::SurfaceConfiguration {
+        for attempt in 1..=SURFACE_CONFIG_RETRIES {
+            let size = window.inner_size();
+            if size.width > 0 && size.height > 0 {
+                if let Some(config) = surface.get_default_config(adapter, size.width, size.height) {
+                    return config;
+                }
+            }
+            log::warn!(
+                "No usable surface configuration for a {}x{} window yet, retrying ({}/{})",
+                size.width,
+                size.height,
+                attempt,
+                SURFACE_CONFIG_RETRIES
+            );
+            window.request_redraw();
+            #[cfg(not(target_arch = "wasm32"))]
+            std::thread::sleep(SURFACE_CONFIG_RETRY_DELAY);
+        }
+
+        log::warn!("Window size is still degenerate, starting with a 1x1 surface");
+        surface
+            .get_default_config(adapter, 1, 1)
+            .expect("Surface is not supported by the adapter")
+    }
+
+    /// Picks the adapter named by [`SessionConfig::adapter`], or else lets
+    /// wgpu choose one by power preference.
+    async fn select_adapter(
+        instance: &wgpu::Instance,
+        config: &SessionConfig,
+        surface: Option<&wgpu::Surface<'_>>,
+    ) -> wgpu::Adapter {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(selector) = config.adapter.as_deref() {
+            let adapters = instance.enumerate_adapters(config.backend.to_wgpu());
+            let found = match selector.parse::<usize>() {
+                Ok(index) => adapters.get(index),
+                Err(_) => adapters
+                    .iter()
+                    .find(|a| a.get_info().name.contains(selector)),
+            };
+
+            match found {
+                Some(adapter) if surface.map_or(true, |s| adapter.is_surface_supported(s)) => {
+                    return adapter.clone();
+                }
+                Some(adapter) => log::warn!(
+                    "Adapter {} can't render to the window, falling back to the default",
+                    adapter.get_info().name
+                ),
+                None => {
+                    let names: Vec<String> = adapters
+                        .iter()
+                        .enumerate()
+                        .map(|(i, a)| format!("{}: {}", i, a.get_info().name))
+                        .collect();
+                    log::warn!(
+                        "No adapter matching {:?} (available: {}), falling back to the default",
+                        selector,
+                        names.join(", ")
+                    );
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        if config.adapter.is_some() {
+            log::warn!("Selecting an adapter is not supported on the web, using the default");
+        }
+
+        instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: config
+                    .power_preference
+                    .map_or(wgpu::PowerPreference::default(), PowerPreference::to_wgpu),
+                force_fallback_adapter: false,
+                compatible_surface: surface,
+            })
+            .await
+            .expect("Failed to find an appropriate adapter")
+    }
+
+    /// The features of `adapter` that a session with `config` uses, for
+    /// requesting the device passed to [`attach`](Self::attach).
+    pub fn device_features(adapter: &wgpu::Adapter, config: &SessionConfig) -> wgpu::Features {
+        // needed for the Vulkan present timing path, harmless elsewhere
+        let mut wanted = wgpu::Features::VULKAN_GOOGLE_DISPLAY_TIMING;
+        if config.gpu_timing {
+            if adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+                wanted |= wgpu::Features::TIMESTAMP_QUERY;
+                log::info!("GPU render times will be recorded as gpu_render rows");
+            } else {
+                log::warn!(
+                    "{} does not support timestamp queries; GPU render times will not be recorded",
+                    adapter.get_info().name
+                );
+            }
+        }
+        adapter.features() & wanted
+    }
+
+    async fn request_device(
+        adapter: &wgpu::Adapter,
+        config: &SessionConfig,
+    ) -> (wgpu::Device, wgpu::Queue) {
+        let required_features = Self::device_features(adapter, config);
+        let limits = config.limits.limits(adapter);
+        log::info!(
+            "Device limits ({:?}): max texture {}px, max color attachment bytes/sample {}",
+            config.limits,
+            limits.max_texture_dimension_2d,
+            limits.max_color_attachment_bytes_per_sample
+        );
+        log::debug!("Full device limits: {:?}", limits);
+
+        // Create the logical device and command queue
+        adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: None,
+                required_features,
+                required_limits: limits,
+                memory_hints: wgpu::MemoryHints::MemoryUsage,
+                trace: wgpu::Trace::Off,
+            })
+            .await
+            .expect("Failed to create device")
+    }
+
+    /// Records the GPU power and clock samples of `sampler` alongside the
+    /// frame timings.
+    #[cfg(feature = "nvml")]
+    pub fn with_power_sampler(mut self, sampler: PowerSampler) -> Self {
+        self.power = Some(sampler);
+        self
+    }
+
+    /// Records photodiode events from `reader` alongside the frame timings.
+    pub fn with_photodiode(mut self, reader: PhotodiodeReader) -> Self {
+        self.photodiode = Some(reader);
+        self
+    }
+
+    /// Also writes every record to `stream` as soon as its frame is done,
+    /// flushing once per frame.
+    pub fn with_json_stream(mut self, stream: JsonLinesWriter) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    /// Calls `f` with every record as soon as it is produced, during
+    /// [`poll_frame`](Self::poll_frame), e.g. for live plotting. Replaces any
+    /// previous callback; the records are still collected for
+    /// [`into_dataframe`](Self::into_dataframe) as usual.
+    pub fn on_record(&mut self, f: impl FnMut(&VBlankRecord) + 'static) {
+        self.on_record = Some(Box::new(f));
+    }
+
+    /// Tags all records of this session with `monitor_id`, to tell apart the
+    /// streams of sessions running side by side on different displays.
+    pub fn with_monitor_id(mut self, monitor_id: u32) -> Self {
+        self.monitor_id = monitor_id;
+        self
+    }
+
+    /// Reconfigures the surface after the window was resized.
+    ///
+    /// A zero-sized window, as seen in the middle of fullscreen transitions,
+    /// can't be configured; the previous size is kept until a usable one
+    /// arrives.
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            log::debug!("Ignoring resize to {}x{}", size.width, size.height);
+            return;
+        }
+        if let RenderTarget::Surface {
+            surface, config, ..
+        } = &mut self.target
+        {
+            config.width = size.width;
+            config.height = size.height;
+            surface.configure(&self.device, config);
+        }
+    }
+
+    /// Size of the render target in pixels.
+    fn target_size(&self) -> (u32, u32) {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => (config.width, config.height),
+            RenderTarget::Offscreen { texture, .. } => (texture.width(), texture.height()),
+            RenderTarget::Lost { size, .. } => *size,
+        }
+    }
+
+    /// Texture format of the render target.
+    pub fn target_format(&self) -> wgpu::TextureFormat {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => config.format,
+            RenderTarget::Offscreen { texture, .. } => texture.format(),
+            RenderTarget::Lost { format, .. } => *format,
+        }
+    }
+
+    /// The swapchain present mode in effect, or `None` when headless.
+    pub fn present_mode(&self) -> Option<wgpu::PresentMode> {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => Some(config.present_mode),
+            RenderTarget::Offscreen { .. } => None,
+            RenderTarget::Lost { surface_config, .. } => {
+                surface_config.as_ref().map(|config| config.present_mode)
+            }
+        }
+    }
+
+    /// The maximum frame latency the swapchain was configured with, or
+    /// `None` when headless.
+    pub fn max_frame_latency(&self) -> Option<u32> {
+        match &self.target {
+            RenderTarget::Surface { config, .. } => Some(config.desired_maximum_frame_latency),
+            RenderTarget::Offscreen { .. } => None,
+            RenderTarget::Lost { surface_config, .. } => surface_config
+                .as_ref()
+                .map(|config| config.desired_maximum_frame_latency),
+        }
+    }
+
+    /// Whether presents can tear on `surface`, for
+    /// [`allow_tearing`](SessionConfig::allow_tearing). Warns why not.
+    fn check_tearing(
+        surface: &wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        present_mode: wgpu::PresentMode,
+    ) -> bool {
+        if present_mode != wgpu::PresentMode::Immediate {
+            log::warn!(
+                "Tearing needs the immediate present mode, not {:?}; no tear rows",
+                present_mode
+            );
+            return false;
+        }
+        #[cfg(windows)]
+        if adapter.get_info().backend == wgpu::Backend::Dx12 {
+            return match present::dx12::swapchain_allows_tearing(surface) {
+                Some(true) => {
+                    log::info!(
+                        "The swapchain allows tearing; presenting with DXGI_PRESENT_ALLOW_TEARING"
+                    );
+                    true
+                }
+                _ => {
+                    log::warn!("The system doesn't allow tearing in windows; presents wait for the compositor");
+                    false
+                }
+            };
+        }
+        let _ = (surface, adapter);
+        log::info!("Presenting immediately; the driver decides whether frames actually tear");
+        true
+    }
+
+    /// Whether presents can tear and `tear` rows are being recorded.
+    pub fn tearing(&self) -> bool {
+        self.tearing
+    }
+
+    /// The present modes the surface supports; empty when headless.
+    pub fn supported_present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.present_modes
+    }
+
+    /// The present mode that was asked for, which
+    /// [`present_mode`](Self::present_mode) differs from if the surface
+    /// didn't support it; `None` when headless.
+    pub fn requested_present_mode(&self) -> Option<wgpu::PresentMode> {
+        self.requested_present_mode
+    }
+
+    /// The maximum frame latency the swapchain reports actually using (DX12
+    /// only).
+    pub fn effective_frame_latency(&self) -> Option<u32> {
+        self.effective_frame_latency
+    }
+
+    /// Whether this is a vsync-off benchmark rather than a timing capture.
+    pub fn is_benchmark(&self) -> bool {
+        !self.vsync
+    }
+
+    /// Whether this session renders offscreen with a synthetic clock.
+    pub fn is_headless(&self) -> bool {
+        matches!(self.target, RenderTarget::Offscreen { .. })
+    }
+
+    /// Renders and presents one frame, then waits until the display reports it.
+    ///
+    /// Returns the vblank record for the frame, or `None` if the backend has no
+    /// present timing source (in which case only the CPU timestamp is kept).
+    /// Warm-up frames are rendered and timed but not recorded, and also
+    /// return `None`.
+    ///
+    /// A lost or outdated surface is reconfigured and the frame skipped
+    /// (`Ok(None)` without advancing [`frame_index`](Self::frame_index)). Errors
+    /// are only returned for failures the session can't recover from, such as
+    /// running out of memory or a display that stopped reporting vblanks; the
+    /// records collected so far remain available.
+    pub fn poll_frame(&mut self) -> Result<Option<VBlankRecord>, FrameError> {
+        if self.device_lost.load(Ordering::Acquire) {
+            self.recover_device()?;
+            return Ok(None);
+        }
+
+        if self.frame_index == 0 && self.run_id == 0 {
+            // measure relative to the first recorded frame, not the warm-up
+            (self.win_start, self.cpu_start, self.anchors) =
+                capture_anchors(self.timing_source.as_deref());
+        }
+
+        let display_time = self.predicted_display_time();
+        let lit = match self.timed {
+            _ if self.is_roll_frame() => false,
+            Some(timed) => timed.is_on(display_time),
+            None => self.pattern.is_on(self.stimulus_frame_index()),
+        };
+        self.shown_inputs = std::mem::take(&mut self.pending_inputs);
+        let presented = match self.render_frame(lit) {
+            Ok(presented) => presented,
+            Err(FrameError::Surface(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated)) => {
+                log::warn!("Surface lost or outdated, reconfiguring");
+                // show the inputs on the next frame instead
+                self.pending_inputs = std::mem::take(&mut self.shown_inputs);
+                if let RenderTarget::Surface {
+                    surface, config, ..
+                } = &self.target
+                {
+                    surface.configure(&self.device, config);
+                }
+                return Ok(None);
+            }
+            Err(FrameError::Surface(wgpu::SurfaceError::Timeout)) => {
+                log::warn!("Timed out acquiring the next swapchain texture");
+                self.pending_inputs = std::mem::take(&mut self.shown_inputs);
+                return Ok(None);
+            }
+            Err(FrameError::Stalled(timeout)) => {
+                let now = self.timing_source.as_ref().map_or(0, |s| s.now());
+                self.push_record(
+                    now - self.win_start,
+                    timeout.as_nanos() as i64,
+                    EventType::Stall,
+                    lit,
+                );
+                self.stream_records();
+                return Err(FrameError::Stalled(timeout));
+            }
+            Err(e) => return Err(e),
+        };
+        let recording = !self.is_warming_up();
+        if recording && !self.startup_written {
+            self.write_startup();
+        }
+
+        // without present stats we can only count frames
+        let mut refresh_count = self.frame_index;
+        let mut vblank = None;
+
+        if let Some(Presented {
+            stats: present_stats,
+            submitted,
+            polls,
+        }) = presented
+        {
+            self.last_present_count = present_stats.present_count;
+            refresh_count = present_stats.present_refresh_count as i64;
+
+            // compared against the expectation from before this frame
+            let expected_interval = self.expected_interval();
+            let mut frame_interval = None;
+            if let (Some(last_sync), Some(last_count)) =
+                (self.last_sync_time, self.last_refresh_count)
+            {
+                let refreshes = refresh_count - last_count;
+                if refreshes > 0 {
+                    frame_interval = Some(present_stats.sync_time - last_sync);
+                    self.refresh_interval = Some((present_stats.sync_time - last_sync) / refreshes);
+                }
+            }
+            self.last_sync_time = Some(present_stats.sync_time);
+
+            // get frame stats timestamp (in ns)
+            let fs_timestamp = present_stats.sync_time - self.win_start;
+
+            for input in std::mem::take(&mut self.shown_inputs) {
+                self.push_record(fs_timestamp, input, EventType::InputPresented, lit);
+            }
+
+            if let Some(timed) = self.timed.filter(|_| recording) {
+                if self.last_lit.is_some_and(|last| last != lit) {
+                    // the switch shows on the first vblank after it was due
+                    let intended = timed.last_transition(display_time);
+                    let error = fs_timestamp - intended;
+                    log::debug!(
+                        "Switched {} {:.3} ms after the intended time",
+                        if lit { "on" } else { "off" },
+                        error as f64 / 1e6
+                    );
+                    self.push_record(intended, lit as i64, EventType::TransitionIntended, lit);
+                    self.push_record(fs_timestamp, error, EventType::TransitionActual, lit);
+                }
+                self.last_lit = Some(lit);
+            }
+
+            if recording {
+                // the frame the source says was displayed, which can be an
+                // earlier one than was just submitted
+                let shown_frame = present_stats
+                    .presented_frame
+                    .filter(|&(run, _)| run == self.run_id)
+                    .map_or(self.frame_index, |(_, frame)| frame);
+                vblank = Some(
+                    self.push_frame_record(
+                        shown_frame,
+                        fs_timestamp,
+                        refresh_count,
+                        EventType::SyncQpcTime,
+                        lit,
+                    )
+                    .clone(),
+                );
+                // both ends come from the same iteration, so they pair up directly
+                self.push_record(
+                    fs_timestamp,
+                    present_stats.sync_time - submitted,
+                    EventType::SubmitToVblank,
+                    lit,
+                );
+                self.push_record(
+                    fs_timestamp,
+                    present_stats.present_count as i64,
+                    EventType::PresentCount,
+                    lit,
+                );
+                if let Some(sync_refresh_count) = present_stats.sync_refresh_count {
+                    self.push_record(
+                        fs_timestamp,
+                        sync_refresh_count as i64,
+                        EventType::SyncRefreshCount,
+                        lit,
+                    );
+                    let depth =
+                        sync_refresh_count as i64 - present_stats.present_refresh_count as i64;
+                    self.push_record(fs_timestamp, depth, EventType::QueueDepth, lit);
+                }
+                if let Some(gpu_time) = present_stats.sync_gpu_time {
+                    self.push_record(fs_timestamp, gpu_time, EventType::SyncGpuTime, lit);
+                }
+                if let Some(polls) = polls {
+                    self.push_record(fs_timestamp, polls as i64, EventType::PollCount, lit);
+                }
+                if let Some(interval) =
+                    expected_interval.filter(|&interval| self.tearing && interval > 0)
+                {
+                    // the flip lands where scanout is at submission, counting
+                    // scanlines from the last vblank before it
+                    let phase = (submitted - present_stats.sync_time).rem_euclid(interval) as f64
+                        / interval as f64;
+                    if phase > TEAR_BLANKING_FRACTION && phase < 1.0 - TEAR_BLANKING_FRACTION {
+                        let scanline = phase * self.target_size().1 as f64;
+                        let position = (scanline * 1000.0).round() as i64;
+                        self.push_record(
+                            submitted - self.win_start,
+                            position,
+                            EventType::Tear,
+                            lit,
+                        );
+                    }
+                }
+                if self.preroll > 0 && self.frame_index == self.preroll as i64 {
+                    self.push_record(
+                        fs_timestamp,
+                        self.preroll as i64,
+                        EventType::PrerollEnd,
+                        lit,
+                    );
+                }
+                if self.postroll_left == Some(self.postroll) {
+                    self.push_record(
+                        fs_timestamp,
+                        self.postroll as i64,
+                        EventType::PostrollStart,
+                        lit,
+                    );
+                }
+                #[cfg(windows)]
+                self.record_present_path(fs_timestamp, lit);
+                // the same position the shader drew, next to the present time
+                if let Some(bar_top) = self
+                    .pattern
+                    .bar_top(self.stimulus_frame_index(), self.target_size().1)
+                {
+                    let phase = (bar_top as f64 * 1000.0).round() as i64;
+                    self.push_record(fs_timestamp, phase, EventType::TearPhase, lit);
+                }
+            }
+
+            if let (Some(interval), Some(expected)) = (frame_interval, expected_interval) {
+                let long = stats::is_long_frame(
+                    interval as f64,
+                    expected as f64,
+                    self.long_frame_tolerance,
+                );
+                if recording && long {
+                    self.push_record(fs_timestamp, interval, EventType::LongFrame, lit);
+                }
+            }
+
+            // a present that spanned more than one refresh interval
+            // means the display repeated the previous frame
+            if let Some(last) = self.last_refresh_count.filter(|_| recording) {
+                let missed = refresh_count - last - 1;
+                if missed > 0 {
+                    self.dropped_frames.push((self.frame_index, missed));
+                    self.dropped_total += missed;
+                    self.push_record(fs_timestamp, missed, EventType::DroppedFrame, lit);
+                }
+            }
+            self.last_refresh_count = Some(refresh_count);
+        }
+
+        match (recording, self.vsync) {
+            (true, true) => self.record_frame_events(refresh_count, lit),
+            (true, false) => {
+                let cpu_timestamp = self.cpu_start.elapsed().as_nanos() as i64;
+                vblank = Some(
+                    self.push_record(
+                        cpu_timestamp,
+                        self.frame_index,
+                        EventType::VsyncOffCpuTime,
+                        lit,
+                    )
+                    .clone(),
+                );
+            }
+            (false, _) => {
+                if let Some(reader) = &self.photodiode {
+                    // discard anything that arrived during the warm-up
+                    reader.try_iter().for_each(drop);
+                }
+            }
+        }
+
+        self.frame_index += 1;
+        if let Some(left) = self.postroll_left.as_mut() {
+            *left = left.saturating_sub(1);
+        }
+        self.stream_records();
+
+        Ok(vblank)
+    }
+
+    /// Rebuilds the device, swapchain and pipeline after the device was lost,
+    /// keeping the records and continuing the capture.
+    ///
+    /// A `device_lost` record marks the gap, and everything recorded so far
+    /// is streamed out first in case the recovery takes the process down.
+    fn recover_device(&mut self) -> Result<(), FrameError> {
+        self.device_losses += 1;
+        log::error!(
+            "GPU device lost, recreating it (loss {})",
+            self.device_losses
+        );
+
+        let now = self.now();
+        self.push_record(now, self.device_losses, EventType::DeviceLost, false);
+        self.stream_records();
+
+        #[cfg(target_arch = "wasm32")]
+        return Err(FrameError::DeviceLost);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let config = self.config.clone();
+            let (window, surface_config) = match &self.target {
+                RenderTarget::Surface { window, config, .. } => {
+                    (Some(window.clone()), Some(config.clone()))
+                }
+                RenderTarget::Offscreen { .. } | RenderTarget::Lost { .. } => (None, None),
+            };
+            let (width, height) = self.target_size();
+            // the old surface goes before the new one is created on the same
+            // window, which a second swapchain on it would fail on
+            let lost = RenderTarget::Lost {
+                size: (width, height),
+                format: self.target_format(),
+                surface_config,
+            };
+            let clock = match std::mem::replace(&mut self.target, lost) {
+                RenderTarget::Offscreen { clock, .. } => Some(clock),
+                _ => None,
+            };
+            self.timing_source = None;
+            // creating the device panics on failure; treat that like any
+            // other unrecoverable frame error so the records get saved
+            let fresh = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match window {
+                Some(window) => pollster::block_on(Self::new(window, config)),
+                None => pollster::block_on(Self::new_headless(config, width, height)),
+            }))
+            .map_err(|_| FrameError::DeviceLost)?;
+
+            // the capture's clock anchors (`win_start`, `cpu_start`) stay, so
+            // timestamps carry on from before the loss; the present timing
+            // sources all read absolute clocks, and headless runs keep their
+            // synthetic clock
+            self.target = fresh.target;
+            if let (
+                Some(clock),
+                RenderTarget::Offscreen {
+                    clock: fresh_clock, ..
+                },
+            ) = (clock, &mut self.target)
+            {
+                *fresh_clock = clock;
+            }
+            self.adapter = fresh.adapter;
+            self.device = fresh.device;
+            self.device_lost = fresh.device_lost;
+            self.queue = fresh.queue;
+            self.renderer = fresh.renderer;
+            self.gpu_timer = fresh.gpu_timer;
+            self.timing_source = fresh.timing_source;
+            self.effective_frame_latency = fresh.effective_frame_latency;
+            self.tearing = fresh.tearing;
+
+            // the new swapchain counts presents from scratch
+            self.last_present_count = 0;
+            self.last_refresh_count = None;
+            self.last_sync_time = None;
+            log::info!(
+                "Device recreated on {}, resuming",
+                self.adapter.get_info().name
+            );
+            Ok(())
+        }
+    }
+
+    /// Records the start-up milestones, relative to the start of the capture
+    /// like `cpu_time` rows.
+    fn write_startup(&mut self) {
+        self.startup_written = true;
+        let Some(&(_, begin)) = self.startup.first() else {
+            return;
+        };
+        // kept afterwards, so later frames don't note the milestones again
+        for (event_type, at) in self.startup.clone() {
+            let timestamp = signed_ns(self.cpu_start, at);
+            self.push_record(timestamp, signed_ns(begin, at), event_type, false);
+        }
+    }
+
+    /// Writes the records added since the last call to the JSON stream. On
+    /// failure streaming stops, but the records are still kept in memory.
+    fn stream_records(&mut self) {
+        let Some(stream) = self.stream.as_mut() else {
+            return;
+        };
+        let result = self.records[self.streamed..]
+            .iter()
+            .try_for_each(|record| stream.write(record))
+            .and_then(|()| stream.flush());
+        self.streamed = self.records.len();
+
+        if let Err(e) = result {
+            log::error!("Failed to write JSON stream, no longer streaming: {}", e);
+            self.stream = None;
+        }
+    }
+
+    /// Draws the frame and presents it. Returns the present statistics once
+    /// the display has picked the frame up.
+    fn render_frame(&mut self, lit: bool) -> Result<Option<Presented>, FrameError> {
+        let (frame, view) = match &self.target {
+            RenderTarget::Surface { surface, .. } => {
+                let frame = surface.get_current_texture()?;
+                note_milestone(&mut self.startup, EventType::StartupFirstAcquire);
+                let view = frame
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(frame), view)
+            }
+            RenderTarget::Offscreen { texture, .. } => (
+                None,
+                texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+            RenderTarget::Lost { .. } => return Err(FrameError::DeviceLost),
+        };
+        let (width, height) = self.target_size();
+        let bar_top = self
+            .pattern
+            .bar_top(self.stimulus_frame_index(), height)
+            .unwrap_or(0.0);
+        self.renderer
+            .update(&self.queue, self.frame_index, width, height, bar_top);
+
+        let input_frame = !self.shown_inputs.is_empty();
+        let roll_frame = self.is_roll_frame();
+        let clear = if input_frame {
+            // a full white flash, easiest to catch with a photodiode
+            wgpu::Color::WHITE
+        } else if roll_frame {
+            self.roll_color.to_wgpu(self.target_format())
+        } else {
+            self.full_field.map_or(wgpu::Color::BLACK, |fill| {
+                fill.color(lit).to_wgpu(self.target_format())
+            })
+        };
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(clear),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: self.gpu_timer.as_ref().map(|t| t.timestamp_writes()),
+                occlusion_query_set: None,
+            });
+            // in full-field mode (and for input feedback) the clear is the whole stimulus
+            self.renderer.draw(
+                &mut rpass,
+                lit && self.full_field.is_none() && !input_frame,
+                width,
+                height,
+            );
+        }
+
+        if let Some(timer) = &self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+
+        let vsync = self.vsync;
+        Ok(match (&self.target, frame) {
+            (RenderTarget::Surface { surface, .. }, Some(frame)) => {
+                if let Some(source) = self.timing_source.as_mut() {
+                    source.before_present(surface, self.run_id, self.frame_index);
+                }
+                frame.present();
+                note_milestone(&mut self.startup, EventType::StartupFirstPresent);
+
+                let expected_vblank = self
+                    .last_sync_time
+                    .zip(self.refresh_interval.or(self.target_interval))
+                    .map(|(last, interval)| last + interval);
+                // the benchmark mode doesn't wait for the display at all
+                match self.timing_source.as_deref_mut().filter(|_| vsync) {
+                    Some(source) => {
+                        let submitted = source.now();
+                        let (stats, polls) = wait::wait_for_present(
+                            source,
+                            surface,
+                            self.last_present_count,
+                            self.wait_mode,
+                            expected_vblank,
+                            self.vblank_timeout,
+                        )
+                        .ok_or(FrameError::Stalled(self.vblank_timeout.unwrap_or_default()))?;
+                        note_milestone(&mut self.startup, EventType::StartupFirstVblank);
+                        Some(Presented {
+                            stats,
+                            submitted,
+                            polls: Some(polls),
+                        })
+                    }
+                    None => None,
+                }
+            }
+            (RenderTarget::Offscreen { .. }, _) if !vsync => {
+                // only wait for the GPU, to measure its throughput
+                let _ = self.device.poll(wgpu::PollType::Wait);
+                None
+            }
+            (RenderTarget::Offscreen { clock, .. }, _) => {
+                // keep the GPU work in lockstep with the synthetic clock
+                let _ = self.device.poll(wgpu::PollType::Wait);
+                Some(Presented {
+                    stats: clock.stats(self.frame_index),
+                    submitted: clock.now(self.frame_index),
+                    polls: None,
+                })
+            }
+            (RenderTarget::Surface { .. }, None) | (RenderTarget::Lost { .. }, _) => unreachable!(),
+        })
+    }
+
+    /// When the frame about to be rendered should reach the display, in ns
+    /// since the start of the capture: the vblank after the last one, or now
+    /// while the refresh interval is still unknown.
+    fn predicted_display_time(&self) -> i64 {
+        match &self.target {
+            RenderTarget::Offscreen { clock, .. } => clock.now(self.frame_index + 1),
+            RenderTarget::Surface { .. } | RenderTarget::Lost { .. } => {
+                match (
+                    self.last_sync_time,
+                    self.refresh_interval.or(self.target_interval),
+                ) {
+                    (Some(last), Some(interval)) => last + interval - self.win_start,
+                    _ => self.timing_source.as_ref().map_or(0, |s| s.now()) - self.win_start,
+                }
+            }
+        }
+    }
+
+    /// Records the CPU-side events of the frame that was just displayed.
+    fn record_frame_events(&mut self, refresh_count: i64, lit: bool) {
+        // take cpu timestamp (in ns)
+        let cpu_timestamp = match &self.target {
+            RenderTarget::Offscreen { clock, .. } => clock.now(self.frame_index + 1),
+            RenderTarget::Surface { .. } | RenderTarget::Lost { .. } => {
+                self.cpu_start.elapsed().as_nanos() as i64
+            }
+        };
+
+        self.push_record(cpu_timestamp, refresh_count, EventType::CpuTime, lit);
+
+        if let Some(fill) = self.full_field {
+            self.push_record(
+                cpu_timestamp,
+                fill.color(lit).to_packed(),
+                EventType::FrameColor,
+                lit,
+            );
+        }
+
+        // the frame has been displayed by now, so this doesn't stall
+        if let Some(duration) = self.gpu_timer.as_ref().and_then(|t| t.read(&self.device)) {
+            self.push_record(cpu_timestamp, duration, EventType::GpuRender, lit);
+        }
+
+        #[cfg(windows)]
+        self.record_dwm_vblank(lit);
+
+        let window = self.photodiode_window();
+        let photodiode_events = self
+            .photodiode
+            .as_ref()
+            .map_or(Vec::new(), |reader| reader.drain_within(window));
+        for event in photodiode_events {
+            self.push_record(event.timestamp, refresh_count, EventType::Photodiode, lit);
+            let received = signed_ns(self.cpu_start, event.received);
+            self.push_record(received, event.timestamp, EventType::PhotodiodeArrival, lit);
+        }
+
+        #[cfg(feature = "nvml")]
+        {
+            let samples: Vec<_> = self
+                .power
+                .as_ref()
+                .map_or(Vec::new(), |s| s.try_iter().collect());
+            for sample in samples {
+                let at = signed_ns(self.cpu_start, sample.at);
+                if let Some(power) = sample.power_mw {
+                    self.push_record(at, power as i64, EventType::GpuPowerMw, lit);
+                }
+                if let Some(clock) = sample.clock_mhz {
+                    self.push_record(at, clock as i64, EventType::GpuClockMhz, lit);
+                }
+            }
+        }
+    }
+
+    /// The QPC values a photodiode timestamp may take: from the start of the
+    /// run until now. `None` unless the device reports QPC values.
+    #[cfg(windows)]
+    fn photodiode_window(&self) -> Option<RangeInclusive<i64>> {
+        if !self.photodiode.as_ref()?.qpc_timestamps() {
+            return None;
+        }
+        Some(self.anchors.qpc_start?..=crate::clock::get_qpc_timestamp().ok()?)
+    }
+
+    #[cfg(not(windows))]
+    fn photodiode_window(&self) -> Option<RangeInclusive<i64>> {
+        None
+    }
+
+    /// Records the compositor's last vblank as a second reference for the
+    /// present timing source. Only meaningful with a real display.
+    #[cfg(windows)]
+    fn record_dwm_vblank(&mut self, lit: bool) {
+        let RenderTarget::Surface { .. } = self.target else {
+            return;
+        };
+        let Some(qpc_freq) = self.anchors.qpc_frequency else {
+            return;
+        };
+
+        match present::dwm::get_dwm_timing() {
+            Ok(info) => {
+                let vblank =
+                    crate::clock::qpc_to_ns(info.qpcVBlank as i64, qpc_freq) - self.win_start;
+                self.push_record(vblank, info.cRefresh as i64, EventType::DwmVblank, lit);
+            }
+            Err(e) => log::debug!("DwmGetCompositionTimingInfo failed: {}", e),
+        }
+    }
+
+    /// Whether the last frame displayed bypassed the compositor, by a direct
+    /// flip or a hardware overlay. `None` before the first frame, and off
+    /// DX12 surfaces.
+    pub fn independent_flip(&self) -> Option<bool> {
+        // DXGI_FRAME_PRESENTATION_MODE_OVERLAY and _NONE
+        self.last_present_path.map(|mode| matches!(mode, 1 | 2))
+    }
+
+    /// Records whether the frame just displayed was composed or flipped, and
+    /// warns when that changes, e.g. when borderless fullscreen falls back to
+    /// composition.
+    #[cfg(windows)]
+    fn record_present_path(&mut self, timestamp: i64, lit: bool) {
+        let RenderTarget::Surface { surface, .. } = &self.target else {
+            return;
+        };
+        let Some(mode) = present::dx12::presentation_mode(surface) else {
+            return;
+        };
+
+        if let Some(last) = self.last_present_path.filter(|&last| last != mode) {
+            log::warn!(
+                "Present path changed from {} to {} at frame {}",
+                present::dx12::presentation_mode_name(last),
+                present::dx12::presentation_mode_name(mode),
+                self.frame_index
+            );
+        } else if self.last_present_path.is_none() {
+            log::info!(
+                "Present path: {}",
+                present::dx12::presentation_mode_name(mode)
+            );
+        }
+        self.last_present_path = Some(mode);
+        self.push_record(timestamp, mode as i64, EventType::PresentPath, lit);
+    }
+
+    fn push_record(
+        &mut self,
+        timestamp: i64,
+        count: i64,
+        event_type: EventType,
+        lit: bool,
+    ) -> &VBlankRecord {
+        self.push_frame_record(self.frame_index, timestamp, count, event_type, lit)
+    }
+
+    /// Like [`push_record`](Self::push_record), for frame `frame_index`
+    /// rather than the current one.
+    fn push_frame_record(
+        &mut self,
+        frame_index: i64,
+        timestamp: i64,
+        count: i64,
+        event_type: EventType,
+        lit: bool,
+    ) -> &VBlankRecord {
+        self.trim_ring(false);
+        self.records.push(VBlankRecord {
+            timestamp,
+            count,
+            event_type,
+            frame_index,
+            lit,
+            run_id: self.run_id,
+            monitor_id: self.monitor_id,
+        });
+        let record = self.records.last().unwrap();
+        if let Some(f) = self.on_record.as_mut() {
+            f(record);
+        }
+        record
+    }
+
+    /// Drops what no longer fits in the ring. To keep this cheap, up to twice
+    /// the capacity is held between trims unless `exact` is set.
+    fn trim_ring(&mut self, exact: bool) {
+        let Some(capacity) = self.ring else {
+            return;
+        };
+        let limit = if exact { capacity } else { 2 * capacity };
+
+        if self.records.len() > limit {
+            let excess = self.records.len() - capacity;
+            self.records.drain(..excess);
+            self.streamed = self.streamed.saturating_sub(excess);
+        }
+        if self.dropped_frames.len() > limit {
+            let excess = self.dropped_frames.len() - capacity;
+            self.dropped_frames.drain(..excess);
+        }
+    }
+
+    /// The current time in ns since the start of the capture, in the clock of
+    /// the vblank records where there is one.
+    fn now(&self) -> i64 {
+        match &self.target {
+            RenderTarget::Surface { .. } | RenderTarget::Lost { .. } => match &self.timing_source {
+                Some(source) => source.now() - self.win_start,
+                None => self.cpu_start.elapsed().as_nanos() as i64,
+            },
+            RenderTarget::Offscreen { clock, .. } => clock.now(self.frame_index),
+        }
+    }
+
+    /// Records a mouse or keyboard event that just happened and flashes the
+    /// next frame white in response.
+    ///
+    /// The event is recorded as an `input` row timestamped with the present
+    /// clock, and the vblank that shows the flash as an `input_presented` row;
+    /// both carry the same sequence number in `count`. Their difference is the
+    /// input-to-photon latency as far as the display reports it; with a
+    /// photodiode the flash itself can be timed. Ignored while warming up.
+    pub fn register_input(&mut self) {
+        if self.is_warming_up() {
+            return;
+        }
+        let now = self.now();
+
+        let input = self.input_count;
+        self.input_count += 1;
+        self.push_record(now, input, EventType::Input, false);
+        // several events before the next frame share its flash
+        self.pending_inputs.push(input);
+    }
+
+    /// Starts another repetition: renders the warm-up again and continues
+    /// recording with the next `run_id`.
+    ///
+    /// Timestamps keep counting from the start of the first run.
+    pub fn next_run(&mut self) {
+        self.run_id += 1;
+        self.frame_index = -(self.warmup_frames as i64);
+        self.postroll_left = None;
+    }
+
+    /// Starts showing the postroll frames, once the run is over. Returns
+    /// whether there are any; if so, keep polling frames until
+    /// [`postroll_done`](Self::postroll_done).
+    pub fn begin_postroll(&mut self) -> bool {
+        if self.postroll == 0 {
+            return false;
+        }
+        self.postroll_left.get_or_insert(self.postroll);
+        true
+    }
+
+    /// Whether all postroll frames have been presented.
+    pub fn postroll_done(&self) -> bool {
+        self.postroll_left == Some(0)
+    }
+
+    /// Index of the current frame within the pattern, not counting the
+    /// preroll. Negative while warming up and during the preroll.
+    pub fn stimulus_frame_index(&self) -> i64 {
+        self.frame_index - self.preroll as i64
+    }
+
+    /// Whether the current frame is a preroll or postroll frame.
+    fn is_roll_frame(&self) -> bool {
+        (0..self.preroll as i64).contains(&self.frame_index)
+            || self.postroll_left.is_some_and(|left| left > 0)
+    }
+
+    /// Index of the current repetition, starting at 0.
+    pub fn run_id(&self) -> u32 {
+        self.run_id
+    }
+
+    /// Display this session's records are tagged with.
+    pub fn monitor_id(&self) -> u32 {
+        self.monitor_id
+    }
+
+    /// Whether the frames being presented are still part of the warm-up.
+    pub fn is_warming_up(&self) -> bool {
+        self.frame_index < 0
+    }
+
+    /// Number of frames recorded so far. Negative while warming up.
+    pub fn frame_index(&self) -> i64 {
+        self.frame_index
+    }
+
+    /// All records collected so far, or in ring mode the most recent ones.
+    pub fn records(&self) -> &[VBlankRecord] {
+        let start = self
+            .ring
+            .map_or(0, |capacity| self.records.len().saturating_sub(capacity));
+        &self.records[start..]
+    }
+
+    /// Takes the records collected so far out of the session, e.g. to write
+    /// them out in parts during a long capture. Statistics at the end only
+    /// cover the records added afterwards, while the counters (such as the
+    /// dropped frames) keep going.
+    pub fn take_records(&mut self) -> Vec<VBlankRecord> {
+        self.stream_records();
+        self.trim_ring(true);
+        self.streamed = 0;
+        std::mem::take(&mut self.records)
+    }
+
+    /// The refresh interval frames are checked against, in ns: the configured
+    /// target if there is one, otherwise the most recently measured interval.
+    pub fn expected_interval(&self) -> Option<i64> {
+        self.target_interval.or(self.refresh_interval)
+    }
+
+    /// Frames after which the display skipped refreshes, as
+    /// `(frame index, missed intervals)`. In ring mode only the most recent.
+    pub fn dropped_frames(&self) -> &[(i64, i64)] {
+        let start = self.ring.map_or(0, |capacity| {
+            self.dropped_frames.len().saturating_sub(capacity)
+        });
+        &self.dropped_frames[start..]
+    }
+
+    /// Total number of refresh intervals missed so far, including any that
+    /// have left the ring.
+    pub fn dropped_frame_count(&self) -> i64 {
+        self.dropped_total
+    }
+
+    /// The clock values the recorded timestamps are relative to.
+    pub fn clock_anchors(&self) -> &ClockAnchors {
+        &self.anchors
+    }
+
+    /// Whether GPU render times are being recorded: `--gpu-timing` was asked
+    /// for and the adapter supports timestamp queries.
+    pub fn gpu_timing(&self) -> bool {
+        self.gpu_timer.is_some()
+    }
+
+    pub fn adapter(&self) -> &wgpu::Adapter {
+        &self.adapter
+    }
+
+    /// Moves the records of `other`, typically a session on a second display,
+    /// into this one.
+    ///
+    /// Each session counts from its own first frame, so the timestamps of
+    /// `other` are shifted onto this session's timeline: present clock rows by
+    /// the difference of the present clock anchors, CPU rows by the difference
+    /// of the `Instant` anchors. Both sessions must use the same present clock
+    /// (the same backend), which makes the vblanks of the two displays
+    /// directly comparable. Photodiode rows are kept as received.
+    ///
+    /// In ring mode each session keeps its own most recent records; the
+    /// merged session is no longer trimmed.
+    pub fn merge(&mut self, mut other: TimingSession) {
+        if let Some(reader) = other.photodiode.take() {
+            reader.stop();
+        }
+        self.trim_ring(true);
+        other.trim_ring(true);
+        self.ring = None;
+
+        let present_offset = other.win_start - self.win_start;
+        let cpu_offset = signed_ns(self.cpu_start, other.cpu_start);
+
+        self.records
+            .extend(other.records.into_iter().map(|mut record| {
+                record.timestamp += match RecordClock::of(record.event_type) {
+                    RecordClock::Present => present_offset,
+                    RecordClock::Cpu => cpu_offset,
+                    RecordClock::Device => 0,
+                };
+                record
+            }));
+    }
+
+    /// Stops any background readers and returns the collected records; in
+    /// ring mode only the most recent.
+    pub fn into_records(mut self) -> Vec<VBlankRecord> {
+        if let Some(reader) = self.photodiode.take() {
+            reader.stop();
+        }
+        self.trim_ring(true);
+        std::mem::take(&mut self.records)
+    }
+
+    /// The collected records as a DataFrame with a `utc_time` column; see
+    /// [`capture_dataframe`].
+    pub fn into_dataframe(self) -> PolarsResult<DataFrame> {
+        let anchor = self.anchors.system_time_unix_ns;
+        capture_dataframe(self.into_records(), anchor)
+    }
+}
+
+/// Notes the time of a start-up milestone the first time it happens.
+fn note_milestone(startup: &mut Vec<(EventType, Instant)>, event_type: EventType) {
+    if !startup.iter().any(|&(e, _)| e == event_type) {
+        startup.push((event_type, Instant::now()));
+    }
+}
+
+/// Nanoseconds from `from` to `to`, negative if `to` is earlier.
+/// The clock a record's timestamp was taken with, which decides how
+/// [`TimingSession::merge`] shifts it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordClock {
+    /// The present timing source, relative to `win_start`.
+    Present,
+    /// `Instant`s, relative to `cpu_start`.
+    Cpu,
+    /// The photodiode's own clock.
+    Device,
+}
+
+impl RecordClock {
+    fn of(event_type: EventType) -> Self {
+        match event_type {
+            EventType::CpuTime
+            | EventType::VsyncOffCpuTime
+            | EventType::FrameColor
+            | EventType::GpuRender
+            | EventType::PhotodiodeArrival
+            | EventType::GpuPowerMw
+            | EventType::GpuClockMhz => RecordClock::Cpu,
+            event_type if event_type.is_startup() => RecordClock::Cpu,
+            EventType::Photodiode => RecordClock::Device,
+            _ => RecordClock::Present,
+        }
+    }
+}
+
+fn signed_ns(from: Instant, to: Instant) -> i64 {
+    if to >= from {
+        (to - from).as_nanos() as i64
+    } else {
+        -((from - to).as_nanos() as i64)
+    }
+}
+
+/// How often to try sampling the anchors; the attempt with the tightest
+/// bracket is kept, which filters out being preempted in between.
+const ANCHOR_ATTEMPTS: usize = 5;
+
+/// One reading of the present clock and QPC taken around `Instant::now()`.
+struct AnchorSample {
+    instant: Instant,
+    // midpoints of the readings before and after the instant
+    present: i64,
+    qpc: Option<i64>,
+    // gap between the present clock readings, in ns
+    width: i64,
+}
+
+fn sample_anchor(timing_source: Option<&dyn PresentTimingSource>) -> AnchorSample {
+    let present_now = || timing_source.map_or(0, |s| s.now());
+    #[cfg(windows)]
+    let qpc_now = || crate::clock::get_qpc_timestamp().ok();
+    #[cfg(not(windows))]
+    let qpc_now = || None::<i64>;
+    let midpoint = |a: i64, b: i64| a + (b - a) / 2;
+
+    // nested, so both brackets are centred on the instant
+    let present_before = present_now();
+    let qpc_before = qpc_now();
+    let instant = Instant::now();
+    let qpc_after = qpc_now();
+    let present_after = present_now();
+
+    AnchorSample {
+        instant,
+        present: midpoint(present_before, present_after),
+        qpc: qpc_before.zip(qpc_after).map(|(a, b)| midpoint(a, b)),
+        width: present_after - present_before,
+    }
+}
+
+/// Registers a device lost callback, returning the flag it sets.
+///
+/// Validation errors that follow a loss are logged instead of panicking, as
+/// the session recreates the device on the next frame anyway.
+fn watch_device(device: &wgpu::Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+
+    let flag = lost.clone();
+    device.set_device_lost_callback(move |reason, message| {
+        log::error!("Device lost ({:?}): {}", reason, message);
+        flag.store(true, Ordering::Release);
+    });
+
+    let flag = lost.clone();
+    device.on_uncaptured_error(Box::new(move |error| {
+        if flag.load(Ordering::Acquire) {
+            log::debug!("Ignoring error on the lost device: {}", error);
+        } else {
+            panic!("wgpu error: {}", error);
+        }
+    }));
+
+    lost
+}
+
+/// Samples the starting points of all clocks the records are relative to.
+///
+/// The present clock (for `sync_qpc_time` rows) and QPC are read right before
+/// and after `Instant::now()` (for `cpu_time` rows), and the midpoints are
+/// taken as their values at that instant. This puts both kinds of rows on the
+/// same zero point, up to half the bracket width, which is recorded as
+/// [`ClockAnchors::anchor_uncertainty_ns`]. Typically that is well below a
+/// microsecond; what remains is the unknown position of the instant inside
+/// the bracket, and any difference in how the two clocks advance over a long
+/// capture, which this does not correct.
+fn capture_anchors(
+    timing_source: Option<&dyn PresentTimingSource>,
+) -> (i64, Instant, ClockAnchors) {
+    let sample = (0..ANCHOR_ATTEMPTS)
+        .map(|_| sample_anchor(timing_source))
+        .min_by_key(|s| s.width)
+        .unwrap();
+    let win_start = sample.present;
+    let cpu_start = sample.instant;
+    let system_start = SystemTime::now();
+
+    #[cfg(windows)]
+    let qpc_frequency = crate::clock::get_qpc_frequency().ok();
+    #[cfg(not(windows))]
+    let qpc_frequency = None;
+
+    let anchors = ClockAnchors {
+        qpc_frequency,
+        qpc_start: sample.qpc,
+        present_clock_start_ns: win_start,
+        anchor_uncertainty_ns: timing_source.map(|_| sample.width / 2),
+        system_time_unix_ns: system_start
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as i64),
+        system_time: chrono::DateTime::<chrono::Utc>::from(system_start).to_rfc3339(),
+    };
+
+    (win_start, cpu_start, anchors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_shifts_each_event_type_by_its_clock() {
+        // stamped from `cpu_start` or other `Instant`s
+        let cpu = [
+            EventType::CpuTime,
+            EventType::VsyncOffCpuTime,
+            EventType::FrameColor,
+            EventType::GpuRender,
+            EventType::PhotodiodeArrival,
+            EventType::GpuPowerMw,
+            EventType::GpuClockMhz,
+            EventType::StartupBegin,
+            EventType::StartupPipelineCreated,
+            EventType::StartupSurfaceConfigured,
+            EventType::StartupFirstAcquire,
+            EventType::StartupFirstPresent,
+            EventType::StartupFirstVblank,
+        ];
+
+        for event_type in EventType::ALL {
+            let expected = if cpu.contains(&event_type) {
+                RecordClock::Cpu
+            } else if event_type == EventType::Photodiode {
+                RecordClock::Device
+            } else {
+                RecordClock::Present
+            };
+            assert_eq!(RecordClock::of(event_type), expected, "{}", event_type);
+        }
+    }
+}
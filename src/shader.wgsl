@@ -1,3 +1,24 @@
+struct Uniforms {
+    // render target size in pixels
+    resolution: vec2<f32>,
+    frame: u32,
+    // cycles per pixel for fs_grating, square size in pixels for fs_checker
+    spatial: f32,
+    // top edge of the fs_tearing bar in scanlines, as recorded in tear_phase rows
+    bar_top: f32,
+    // number of bar positions before the bar wraps back to the top, see
+    // Pattern::bar_steps
+    bar_steps: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+// the --image stimulus, a 1x1 placeholder otherwise
+@group(0) @binding(1)
+var image_texture: texture_2d<f32>;
+@group(0) @binding(2)
+var image_sampler: sampler;
+
 @vertex
 fn vs_main(@builtin(vertex_index) ix: u32) -> @builtin(position) vec4<f32> {
     // Generate a full screen quad in normalized device coordinates
@@ -17,7 +38,94 @@ fn vs_main(@builtin(vertex_index) ix: u32) -> @builtin(position) vec4<f32> {
     return vec4(vertex, 0.0, 1.0);
 }
 
+struct ColoredVertex {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// The --vertices stimulus, laid out as `GpuVertex` in render.rs.
+@vertex
+fn vs_vertices(@location(0) position: vec2<f32>, @location(1) color: vec4<f32>) -> ColoredVertex {
+    return ColoredVertex(vec4(position, 0.0, 1.0), color);
+}
+
+@fragment
+fn fs_vertices(vertex: ColoredVertex) -> @location(0) vec4<f32> {
+    return vertex.color;
+}
+
 @fragment
 fn fs_main() -> @location(0) vec4<f32> {
     return vec4<f32>(1.0, 1.0, 1.0, 1.0);
-}
\ No newline at end of file
+}
+
+// A full-width white bar that moves down one step per frame, for the tearing
+// and sweep patterns. A tear shows up as the bar being split or doubled.
+@fragment
+fn fs_tearing(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let bar_height = uniforms.resolution.y / f32(uniforms.bar_steps);
+    if pos.y >= uniforms.bar_top && pos.y < uniforms.bar_top + bar_height {
+        return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
+const PI: f32 = 3.14159265358979;
+
+// iterations of busy work per pixel in fs_load
+const LOAD_ITERATIONS: u32 = 64u;
+
+// Busy work for --gpu-load. Drawn with a blend that keeps the destination,
+// so it costs a full-screen pass of fragment work without changing the image.
+@fragment
+fn fs_load(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    var x = pos.x * 0.001 + pos.y * 0.0007 + f32(uniforms.frame);
+    for (var i = 0u; i < LOAD_ITERATIONS; i++) {
+        x = sin(x * 1.0001 + 0.5) * cos(x);
+    }
+    return vec4<f32>(x, x, x, 1.0);
+}
+
+// A vertical sine grating between black and white.
+@fragment
+fn fs_grating(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let l = 0.5 + 0.5 * sin(2.0 * PI * pos.x * uniforms.spatial);
+    return vec4<f32>(l, l, l, 1.0);
+}
+
+// A black and white checkerboard, white in the top-left square.
+@fragment
+fn fs_checker(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let cell = vec2<u32>(floor(pos.xy / uniforms.spatial));
+    let l = f32((cell.x + cell.y + 1u) % 2u);
+    return vec4<f32>(l, l, l, 1.0);
+}
+
+// bits of the frame index drawn by fs_counter, and the size of each bit's
+// square in pixels
+const COUNTER_BITS: u32 = 16u;
+const COUNTER_CELL: f32 = 16.0;
+
+// The low bits of the frame index as a row of squares in the top-left
+// corner, least significant bit first: white for 1, black for 0.
+@fragment
+fn fs_counter(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let bit = u32(pos.x / COUNTER_CELL);
+    if bit >= COUNTER_BITS || pos.y >= COUNTER_CELL {
+        discard;
+    }
+    let l = f32((uniforms.frame >> bit) & 1u);
+    return vec4<f32>(l, l, l, 1.0);
+}
+
+// The image at its native size, centered, on black.
+@fragment
+fn fs_image(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(image_texture));
+    let uv = (pos.xy - (uniforms.resolution - size) / 2.0) / size;
+    if any(uv < vec2(0.0)) || any(uv >= vec2(1.0)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+    // explicit level, as this branch isn't uniform
+    return textureSampleLevel(image_texture, image_sampler, uv, 0.0);
+}
@@ -0,0 +1,99 @@
+//! SQLite output collecting many captures in one database.
+//!
+//! Only built with the `sqlite` feature. Every capture adds a row to
+//! `sessions` and its records to `records`, keyed by the session's `id`, so
+//! captures can be compared with plain SQL instead of globbing CSVs:
+//!
+//! ```sql
+//! SELECT s.tags, count(*) FROM records r JOIN sessions s ON s.id = r.session_id
+//! WHERE r.event_type = 'dropped_frame' GROUP BY s.id;
+//! ```
+
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::{metadata::CaptureMetadata, VBlankRecord};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    system_time TEXT,
+    crate_version TEXT NOT NULL,
+    backend TEXT,
+    adapter TEXT,
+    pattern TEXT,
+    present_mode TEXT,
+    expected_interval_ns INTEGER,
+    -- the full sidecar as JSON, and its tags as a JSON object
+    metadata TEXT NOT NULL,
+    tags TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS records (
+    session_id INTEGER NOT NULL REFERENCES sessions(id),
+    timestamp INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    event_type TEXT NOT NULL,
+    frame_index INTEGER NOT NULL,
+    lit INTEGER NOT NULL,
+    run_id INTEGER NOT NULL,
+    monitor_id INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS records_session ON records(session_id, event_type);
+";
+
+/// Adds a capture to the database at `path`, creating it and its tables if
+/// needed. All rows go in one transaction, so a failed write leaves no
+/// partial session behind. Returns the new session id.
+pub fn write_sqlite(
+    path: &Path,
+    records: &[VBlankRecord],
+    metadata: &CaptureMetadata,
+) -> rusqlite::Result<i64> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let metadata_json = serde_json::to_string(metadata).expect("metadata serializes");
+    let tags_json = serde_json::to_string(&metadata.tags).expect("tags serialize");
+
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO sessions (system_time, crate_version, backend, adapter, pattern, present_mode,
+             expected_interval_ns, metadata, tags)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            metadata.clock.as_ref().map(|clock| &clock.system_time),
+            metadata.crate_version,
+            metadata.backend,
+            metadata.adapter,
+            metadata.pattern,
+            metadata.present_mode,
+            metadata.expected_interval_ns,
+            metadata_json,
+            tags_json,
+        ],
+    )?;
+    let session_id = tx.last_insert_rowid();
+
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO records (session_id, timestamp, count, event_type, frame_index, lit, run_id, monitor_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        for record in records {
+            insert.execute(params![
+                session_id,
+                record.timestamp,
+                record.count,
+                record.event_type.as_str(),
+                record.frame_index,
+                record.lit,
+                record.run_id,
+                record.monitor_id,
+            ])?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(session_id)
+}
@@ -0,0 +1,909 @@
+//! End-of-run summary statistics over the collected records.
+
+use std::{collections::HashSet, fmt};
+
+use serde::Serialize;
+
+use crate::{EventType, VBlankRecord};
+
+/// Distribution of frame-to-frame vblank intervals, in nanoseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntervalSummary {
+    pub n: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p99: f64,
+}
+
+/// Returns the intervals between successive `sync_qpc_time` records.
+///
+/// Records that report the same refresh `count` as their predecessor describe
+/// the same vblank and are skipped, so each interval spans distinct refreshes.
+/// Intervals never span two runs or two monitors.
+pub fn vblank_intervals(records: &[VBlankRecord]) -> Vec<f64> {
+    let mut intervals = Vec::new();
+    let mut last: Option<&VBlankRecord> = None;
+
+    for record in records
+        .iter()
+        .filter(|r| r.event_type == EventType::SyncQpcTime)
+    {
+        if let Some(prev) =
+            last.filter(|prev| prev.run_id == record.run_id && prev.monitor_id == record.monitor_id)
+        {
+            if record.count == prev.count {
+                continue;
+            }
+            intervals.push((record.timestamp - prev.timestamp) as f64);
+        }
+        last = Some(record);
+    }
+
+    intervals
+}
+
+/// Returns the CPU frame times of a vsync-off benchmark, from successive
+/// `vsync_off_cpu_time` records within each run.
+pub fn benchmark_intervals(records: &[VBlankRecord]) -> Vec<f64> {
+    records
+        .iter()
+        .filter(|r| r.event_type == EventType::VsyncOffCpuTime)
+        .collect::<Vec<_>>()
+        .windows(2)
+        .filter(|pair| pair[0].run_id == pair[1].run_id)
+        .map(|pair| (pair[1].timestamp - pair[0].timestamp) as f64)
+        .collect()
+}
+
+/// Whether `interval` deviates from `expected` by more than `tolerance`, given
+/// as a fraction of `expected`.
+pub fn is_long_frame(interval: f64, expected: f64, tolerance: f64) -> bool {
+    (interval - expected).abs() > expected * tolerance
+}
+
+/// Value at quantile `q` (0..=1) of an already sorted slice, by nearest rank.
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl IntervalSummary {
+    /// Summarises the given intervals, or returns `None` if there are none.
+    pub fn from_intervals(intervals: &[f64]) -> Option<Self> {
+        if intervals.is_empty() {
+            return None;
+        }
+
+        let mut sorted = intervals.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+
+        let n = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        let variance = sorted.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+
+        Some(Self {
+            n,
+            mean,
+            median: quantile(&sorted, 0.5),
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            max: sorted[n - 1],
+            p99: quantile(&sorted, 0.99),
+        })
+    }
+
+    pub fn from_records(records: &[VBlankRecord]) -> Option<Self> {
+        Self::from_intervals(&vblank_intervals(records))
+    }
+}
+
+impl fmt::Display for IntervalSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |ns: f64| ns / 1e6;
+        writeln!(f, "Frame intervals ({} samples):", self.n)?;
+        writeln!(f, "  mean   {:8.3} ms", ms(self.mean))?;
+        writeln!(f, "  median {:8.3} ms", ms(self.median))?;
+        writeln!(f, "  jitter {:8.3} ms (std dev)", ms(self.std_dev))?;
+        writeln!(f, "  min    {:8.3} ms", ms(self.min))?;
+        writeln!(f, "  max    {:8.3} ms", ms(self.max))?;
+        write!(f, "  p99    {:8.3} ms", ms(self.p99))
+    }
+}
+
+/// Frame interval counts in equal-width bins, laid out relative to the
+/// expected refresh interval.
+///
+/// The bins cover up to about 2.5 refresh intervals and are placed so that
+/// half, single and double intervals each fall in the middle of a bin (exactly
+/// so when the bin count is a multiple of 5). Longer intervals are counted in
+/// the last bin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Lower edge of the first bin, in ns.
+    pub start: f64,
+    /// Width of each bin, in ns.
+    pub width: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// Returns `None` if there are no intervals or no bins.
+    pub fn from_intervals(intervals: &[f64], expected: f64, bins: usize) -> Option<Self> {
+        if intervals.is_empty() || bins == 0 || expected <= 0.0 {
+            return None;
+        }
+
+        let width = 2.5 * expected / bins as f64;
+        // bin i is centred on i * width
+        let start = -width / 2.0;
+        let mut counts = vec![0; bins];
+        for &interval in intervals {
+            let bin = ((interval - start) / width).floor().max(0.0) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+
+        Some(Self {
+            start,
+            width,
+            counts,
+        })
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BAR_WIDTH: usize = 50;
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+
+        write!(f, "Frame interval histogram:")?;
+        for (i, &count) in self.counts.iter().enumerate() {
+            let centre = self.start + (i as f64 + 0.5) * self.width;
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max));
+            let overflow = if i + 1 == self.counts.len() { "+" } else { " " };
+            write!(
+                f,
+                "\n  {:8.3} ms{} |{:<width$} {}",
+                centre.max(0.0) / 1e6,
+                overflow,
+                bar,
+                count,
+                width = BAR_WIDTH
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Distribution of the `poll_count` rows in power-of-two buckets: 1, 2-3,
+/// 4-7 and so on. Spin waits poll thousands of times a frame, so a linear
+/// scale would hide the low end.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PollHistogram {
+    /// Frames with polls in `2^i..2^(i+1)`, up to the highest bucket used.
+    pub counts: Vec<usize>,
+    pub total_polls: u64,
+}
+
+impl PollHistogram {
+    /// Returns `None` without any `poll_count` rows.
+    pub fn from_records(records: &[VBlankRecord]) -> Option<Self> {
+        let mut counts = Vec::new();
+        let mut total_polls = 0;
+        for record in records
+            .iter()
+            .filter(|r| r.event_type == EventType::PollCount)
+        {
+            let polls = record.count.max(1) as u64;
+            let bucket = polls.ilog2() as usize;
+            if counts.len() <= bucket {
+                counts.resize(bucket + 1, 0);
+            }
+            counts[bucket] += 1;
+            total_polls += polls;
+        }
+        (!counts.is_empty()).then_some(Self {
+            counts,
+            total_polls,
+        })
+    }
+}
+
+impl fmt::Display for PollHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const BAR_WIDTH: usize = 50;
+        let frames: usize = self.counts.iter().sum();
+        let max = self.counts.iter().copied().max().unwrap_or(0).max(1);
+
+        write!(
+            f,
+            "Polls per frame: {:.1} mean",
+            self.total_polls as f64 / frames as f64
+        )?;
+        for (i, &count) in self.counts.iter().enumerate() {
+            let range = match i {
+                0 => "1".to_string(),
+                _ => format!("{}-{}", 1u64 << i, (1u64 << (i + 1)) - 1),
+            };
+            let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max));
+            write!(
+                f,
+                "\n  {:>13} |{:<width$} {}",
+                range,
+                bar,
+                count,
+                width = BAR_WIDTH
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Autocorrelation a lag needs to count as a beat.
+pub const BEAT_MIN_CORRELATION: f64 = 0.3;
+/// Fewer intervals than this are too short to find a beat in.
+const BEAT_MIN_SAMPLES: usize = 16;
+/// Bounds on the work of [`Beat::detect`], which is quadratic otherwise.
+const BEAT_MAX_LAG: usize = 1000;
+const BEAT_MAX_SAMPLES: usize = 100_000;
+
+/// A slow periodic modulation of the frame intervals, as left by render and
+/// display clocks running at slightly different rates. Mean and jitter alone
+/// don't show it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beat {
+    /// Period in frames: the lag with the strongest autocorrelation.
+    pub period_frames: usize,
+    /// The same period in ns, at the mean interval.
+    pub period_ns: f64,
+    /// Autocorrelation at that lag, between [`BEAT_MIN_CORRELATION`] and 1.
+    pub correlation: f64,
+}
+
+impl Beat {
+    /// Autocorrelates the intervals over lags of 2 up to half their number
+    /// (at most 1000) and returns the strongest, if it is strong enough to be
+    /// a beat. Long captures are judged by their last 100000 intervals.
+    pub fn detect(intervals: &[f64]) -> Option<Self> {
+        let intervals = &intervals[intervals.len().saturating_sub(BEAT_MAX_SAMPLES)..];
+        let n = intervals.len();
+        if n < BEAT_MIN_SAMPLES {
+            return None;
+        }
+        let mean = intervals.iter().sum::<f64>() / n as f64;
+        let deviations: Vec<f64> = intervals.iter().map(|x| x - mean).collect();
+        let variance = deviations.iter().map(|d| d * d).sum::<f64>();
+        if variance <= 0.0 {
+            return None;
+        }
+
+        let (lag, correlation) = (2..=(n / 2).min(BEAT_MAX_LAG))
+            .map(|lag| {
+                let covariance: f64 = deviations
+                    .iter()
+                    .zip(&deviations[lag..])
+                    .map(|(a, b)| a * b)
+                    .sum();
+                (lag, covariance / variance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        (correlation >= BEAT_MIN_CORRELATION).then_some(Self {
+            period_frames: lag,
+            period_ns: lag as f64 * mean,
+            correlation,
+        })
+    }
+}
+
+impl fmt::Display for Beat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Beat: frame intervals repeat every {} frames ({:.3} s, autocorrelation {:.2})",
+            self.period_frames,
+            self.period_ns / 1e9,
+            self.correlation
+        )
+    }
+}
+
+/// Relative difference between measured and reported refresh rate that still
+/// counts as a match.
+pub const REFRESH_RATE_TOLERANCE: f64 = 0.01;
+
+/// The refresh rate the display actually ran at, next to the one its video
+/// mode advertised.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RefreshRateCheck {
+    /// From the median vblank interval.
+    pub measured_hz: f64,
+    /// `None` if the platform doesn't report the mode's rate.
+    pub reported_hz: Option<f64>,
+    /// Whether they agree within [`REFRESH_RATE_TOLERANCE`].
+    pub matches: Option<bool>,
+}
+
+impl RefreshRateCheck {
+    pub fn new(median_interval: f64, reported_millihertz: Option<u32>) -> Self {
+        let measured_hz = 1e9 / median_interval;
+        let reported_hz = reported_millihertz
+            .filter(|&mhz| mhz > 0)
+            .map(|mhz| mhz as f64 / 1000.0);
+        Self {
+            measured_hz,
+            reported_hz,
+            matches: reported_hz.map(|hz| (measured_hz - hz).abs() <= hz * REFRESH_RATE_TOLERANCE),
+        }
+    }
+}
+
+impl fmt::Display for RefreshRateCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Refresh rate: {:.3} Hz measured", self.measured_hz)?;
+        match (self.reported_hz, self.matches) {
+            (Some(hz), Some(true)) => write!(f, ", {:.3} Hz reported (match)", hz),
+            (Some(hz), _) => write!(f, ", {:.3} Hz reported (MISMATCH)", hz),
+            (None, _) => write!(f, ", not reported by the display"),
+        }
+    }
+}
+
+/// How far from a whole number of refresh intervals a vblank interval may
+/// be, as a fraction of one, and still lie on the fixed-refresh grid.
+pub const VRR_GRID_TOLERANCE: f64 = 0.1;
+/// Fraction of intervals that may be off the grid before the display counts
+/// as refreshing at a variable rate.
+pub const VRR_MIN_OFF_GRID_FRACTION: f64 = 0.05;
+
+/// Whether the display refreshed at a fixed rate or followed the frames, as a
+/// variable refresh rate display (G-Sync, FreeSync, VESA Adaptive-Sync) does.
+///
+/// At a fixed rate every vblank interval is a whole number of refresh
+/// intervals, since a late frame waits for the next refresh. With VRR the
+/// display refreshes when the frame arrives, so intervals of frames slower
+/// than the maximum rate fall in between. A capture whose frames all kept up
+/// looks the same either way and reads as fixed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RefreshRegime {
+    /// Whether more than [`VRR_MIN_OFF_GRID_FRACTION`] of the intervals were
+    /// off the grid. Dropped and long frames are counted against a fixed
+    /// grid, so they don't mean anything then.
+    pub variable: bool,
+    /// Fraction of the intervals further than [`VRR_GRID_TOLERANCE`] from a
+    /// whole number of refresh intervals.
+    pub off_grid_fraction: f64,
+}
+
+impl RefreshRegime {
+    /// Checks the vblank intervals against the grid of the expected refresh
+    /// interval (the maximum rate, for a VRR display), or of their median
+    /// without one. Intervals under half a refresh, which FIFO never
+    /// produces, are left to [`Diagnosis`]. `None` without any intervals.
+    pub fn detect(records: &[VBlankRecord], expected_interval: Option<i64>) -> Option<Self> {
+        let intervals = vblank_intervals(records);
+        let summary = IntervalSummary::from_intervals(&intervals)?;
+        let expected = expected_interval.map_or(summary.median, |interval| interval as f64);
+
+        let refreshes: Vec<f64> = intervals
+            .iter()
+            .map(|interval| interval / expected)
+            .filter(|&refreshes| refreshes >= 0.5)
+            .collect();
+        if refreshes.is_empty() {
+            return None;
+        }
+        let off_grid = refreshes
+            .iter()
+            .filter(|refreshes| (*refreshes - refreshes.round()).abs() > VRR_GRID_TOLERANCE)
+            .count();
+        let off_grid_fraction = off_grid as f64 / refreshes.len() as f64;
+
+        Some(Self {
+            variable: off_grid_fraction > VRR_MIN_OFF_GRID_FRACTION,
+            off_grid_fraction,
+        })
+    }
+}
+
+impl fmt::Display for RefreshRegime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regime = if self.variable {
+            "variable (VRR)"
+        } else {
+            "fixed"
+        };
+        write!(
+            f,
+            "Refresh regime: {}, {:.1}% of intervals off the refresh grid",
+            regime,
+            self.off_grid_fraction * 100.0
+        )
+    }
+}
+
+/// Intervals shorter than this fraction of the expected one mean a frame was
+/// shown early, as when queued frames are replaced or reordered.
+pub const DIAGNOSIS_SHORT_INTERVAL: f64 = 0.5;
+/// Fraction of intervals that may be short before a capture counts as
+/// mailbox-like.
+pub const DIAGNOSIS_MAX_SHORT_FRACTION: f64 = 0.01;
+/// Median GPU time of the late frames, as a fraction of the expected
+/// interval, from which their drops are blamed on the GPU.
+pub const DIAGNOSIS_GPU_BOUND: f64 = 0.9;
+
+/// A one-line reading of a capture's statistics with a hint on what to do,
+/// for whoever runs the rig.
+///
+/// [`Diagnosis::classify`] applies these rules in order; the first that
+/// matches wins:
+///
+/// 1. More than [`DIAGNOSIS_MAX_SHORT_FRACTION`] of the vblank intervals are
+///    shorter than [`DIAGNOSIS_SHORT_INTERVAL`] of the expected interval:
+///    [`MailboxReordering`](Self::MailboxReordering). FIFO never shows a
+///    frame early, so something replaced or reordered queued frames.
+/// 2. No dropped frames, no long frames and no [`Beat`]:
+///    [`CleanFifo`](Self::CleanFifo).
+/// 3. Dropped frames whose median GPU render time (`--gpu-timing`) is at
+///    least [`DIAGNOSIS_GPU_BOUND`] of the expected interval:
+///    [`GpuBound`](Self::GpuBound).
+/// 4. Anything else, i.e. late frames without a GPU to blame:
+///    [`CompositorInterference`](Self::CompositorInterference).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Diagnosis {
+    CleanFifo,
+    MailboxReordering {
+        short_intervals: usize,
+    },
+    GpuBound {
+        /// Median GPU render time of the late frames, in ns.
+        median_gpu_ns: f64,
+    },
+    CompositorInterference {
+        /// Whether GPU times were recorded, ruling the GPU out.
+        gpu_timed: bool,
+    },
+}
+
+impl Diagnosis {
+    /// Classifies the records of a (non-benchmark) capture against the
+    /// expected refresh interval, or their median interval without one.
+    /// `None` if there are too few vblanks to judge.
+    pub fn classify(records: &[VBlankRecord], expected_interval: Option<i64>) -> Option<Self> {
+        let intervals = vblank_intervals(records);
+        let summary = IntervalSummary::from_intervals(&intervals)?;
+        let expected = expected_interval.map_or(summary.median, |interval| interval as f64);
+
+        let short_intervals = intervals
+            .iter()
+            .filter(|&&interval| interval < expected * DIAGNOSIS_SHORT_INTERVAL)
+            .count();
+        if short_intervals as f64 > intervals.len() as f64 * DIAGNOSIS_MAX_SHORT_FRACTION {
+            return Some(Self::MailboxReordering { short_intervals });
+        }
+
+        let late_frames: HashSet<(i64, u32, u32)> = records
+            .iter()
+            .filter(|r| matches!(r.event_type, EventType::DroppedFrame | EventType::LongFrame))
+            .map(|r| (r.frame_index, r.run_id, r.monitor_id))
+            .collect();
+        if late_frames.is_empty() && Beat::detect(&intervals).is_none() {
+            return Some(Self::CleanFifo);
+        }
+
+        let gpu_times: Vec<&VBlankRecord> = records
+            .iter()
+            .filter(|r| r.event_type == EventType::GpuRender)
+            .collect();
+        let mut late_gpu_times: Vec<f64> = gpu_times
+            .iter()
+            .filter(|r| late_frames.contains(&(r.frame_index, r.run_id, r.monitor_id)))
+            .map(|r| r.count as f64)
+            .collect();
+        late_gpu_times.sort_by(f64::total_cmp);
+        if !late_gpu_times.is_empty() {
+            let median_gpu_ns = quantile(&late_gpu_times, 0.5);
+            if median_gpu_ns >= expected * DIAGNOSIS_GPU_BOUND {
+                return Some(Self::GpuBound { median_gpu_ns });
+            }
+        }
+
+        Some(Self::CompositorInterference {
+            gpu_timed: !gpu_times.is_empty(),
+        })
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Diagnosis: ")?;
+        match self {
+            Self::CleanFifo => write!(f, "clean FIFO; every frame was shown on its own refresh"),
+            Self::MailboxReordering { short_intervals } => write!(
+                f,
+                "mailbox-like reordering; {} frames were shown early. Use --present-mode fifo \
+                 and check the driver's vsync override",
+                short_intervals
+            ),
+            Self::GpuBound { median_gpu_ns } => write!(
+                f,
+                "GPU-bound drops; late frames took {:.3} ms to render. Lower the load or \
+                 use a simpler stimulus",
+                median_gpu_ns / 1e6
+            ),
+            Self::CompositorInterference { gpu_timed: true } => write!(
+                f,
+                "compositor interference; frames were late although the GPU was fast enough. \
+                 Use --fullscreen exclusive and close overlays and other windows"
+            ),
+            Self::CompositorInterference { gpu_timed: false } => write!(
+                f,
+                "compositor interference, probably; frames were late. Use --fullscreen exclusive \
+                 and close overlays, or rerun with --gpu-timing to rule out the GPU"
+            ),
+        }
+    }
+}
+
+/// Serial latency of the photodiode, estimated from a `--calibrate-photodiode`
+/// capture: how long after the vblank of each onset its line arrived.
+///
+/// This is the whole delay from the frame reaching the display to the host
+/// knowing about it (panel response, the device's own processing, transit
+/// and USB polling), so it holds for any device clock.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PhotodiodeCalibration {
+    /// Onsets with a photodiode line.
+    pub samples: usize,
+    /// Lines that came before any onset, or after one that already had its line.
+    pub unmatched: usize,
+    pub mean_ns: f64,
+    pub std_dev_ns: f64,
+}
+
+impl PhotodiodeCalibration {
+    /// Pairs each `photodiode_arrival` with the last onset (a lit frame after
+    /// a blank one) before it, within the same run. Needs a pattern whose
+    /// flashes are further apart than the latency. `None` without any pairs.
+    pub fn from_records(records: &[VBlankRecord]) -> Option<Self> {
+        let mut onsets = Vec::new();
+        let mut last_lit: Option<(u32, bool)> = None;
+        for record in records
+            .iter()
+            .filter(|r| r.event_type == EventType::SyncQpcTime && r.monitor_id == 0)
+        {
+            if record.lit && last_lit.is_some_and(|(run, lit)| run == record.run_id && !lit) {
+                onsets.push((record.run_id, record.timestamp));
+            }
+            last_lit = Some((record.run_id, record.lit));
+        }
+
+        let mut latencies = Vec::new();
+        let mut unmatched = 0;
+        let mut last_onset = None;
+        for arrival in records
+            .iter()
+            .filter(|r| r.event_type == EventType::PhotodiodeArrival)
+        {
+            let onset = onsets
+                .iter()
+                .rev()
+                .find(|&&(run, at)| run == arrival.run_id && at <= arrival.timestamp)
+                .copied();
+            match onset {
+                Some(onset) if last_onset != Some(onset) => {
+                    latencies.push((arrival.timestamp - onset.1) as f64);
+                    last_onset = Some(onset);
+                }
+                _ => unmatched += 1,
+            }
+        }
+
+        let summary = IntervalSummary::from_intervals(&latencies)?;
+        Some(Self {
+            samples: latencies.len(),
+            unmatched,
+            mean_ns: summary.mean,
+            std_dev_ns: summary.std_dev,
+        })
+    }
+}
+
+impl fmt::Display for PhotodiodeCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Photodiode latency: {:.3} ms mean, {:.3} ms std dev ({:.4} ms² variance) over {} flashes, {} unmatched lines",
+            self.mean_ns / 1e6,
+            self.std_dev_ns / 1e6,
+            self.std_dev_ns * self.std_dev_ns / 1e12,
+            self.samples,
+            self.unmatched
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vblank(timestamp: i64, count: i64, run_id: u32) -> VBlankRecord {
+        VBlankRecord {
+            timestamp,
+            count,
+            event_type: EventType::SyncQpcTime,
+            frame_index: count,
+            lit: true,
+            run_id,
+            monitor_id: 0,
+        }
+    }
+
+    fn event(event_type: EventType, frame_index: i64, count: i64) -> VBlankRecord {
+        VBlankRecord {
+            event_type,
+            frame_index,
+            ..vblank(0, count, 0)
+        }
+    }
+
+    /// `n` vblanks 16 ms apart.
+    fn steady(n: i64) -> Vec<VBlankRecord> {
+        (0..n).map(|i| vblank(i * 16_000_000, i, 0)).collect()
+    }
+
+    #[test]
+    fn summary_of_no_intervals_is_none() {
+        assert_eq!(IntervalSummary::from_intervals(&[]), None);
+        assert_eq!(IntervalSummary::from_records(&[vblank(0, 1, 0)]), None);
+    }
+
+    #[test]
+    fn summary_of_a_single_interval() {
+        let summary = IntervalSummary::from_intervals(&[16_000_000.0]).unwrap();
+
+        assert_eq!(
+            summary,
+            IntervalSummary {
+                n: 1,
+                mean: 16_000_000.0,
+                median: 16_000_000.0,
+                std_dev: 0.0,
+                min: 16_000_000.0,
+                max: 16_000_000.0,
+                p99: 16_000_000.0,
+            }
+        );
+    }
+
+    #[test]
+    fn summary_of_unsorted_intervals() {
+        let summary = IntervalSummary::from_intervals(&[40.0, 10.0, 30.0, 20.0]).unwrap();
+
+        assert_eq!(summary.n, 4);
+        assert_eq!(summary.mean, 25.0);
+        // nearest rank takes the lower of the two middle values
+        assert_eq!(summary.median, 20.0);
+        // the population standard deviation, which is the jitter
+        assert_eq!(summary.std_dev, 125f64.sqrt());
+        assert_eq!((summary.min, summary.max), (10.0, 40.0));
+        assert_eq!(summary.p99, 40.0);
+    }
+
+    #[test]
+    fn quantile_by_nearest_rank() {
+        let sorted = [1.0, 2.0, 3.0, 4.0, 5.0];
+
+        assert_eq!(quantile(&sorted, 0.0), 1.0);
+        assert_eq!(quantile(&sorted, 0.2), 1.0);
+        assert_eq!(quantile(&sorted, 0.21), 2.0);
+        assert_eq!(quantile(&sorted, 0.5), 3.0);
+        assert_eq!(quantile(&sorted, 1.0), 5.0);
+        assert_eq!(quantile(&[7.0], 0.99), 7.0);
+    }
+
+    #[test]
+    fn long_frames_deviate_by_more_than_the_tolerance() {
+        assert!(!is_long_frame(100.0, 100.0, 0.5));
+        assert!(!is_long_frame(150.0, 100.0, 0.5));
+        assert!(is_long_frame(151.0, 100.0, 0.5));
+        // short frames count as well
+        assert!(is_long_frame(49.0, 100.0, 0.5));
+    }
+
+    #[test]
+    fn intervals_skip_repeated_counts_and_run_boundaries() {
+        let records = [
+            vblank(0, 1, 0),
+            // the same vblank reported twice
+            vblank(0, 1, 0),
+            vblank(16_000_000, 2, 0),
+            vblank(16_000_000, 2, 0),
+            vblank(33_000_000, 3, 0),
+            vblank(5_000_000, 1, 1),
+            vblank(22_000_000, 2, 1),
+        ];
+
+        assert_eq!(
+            vblank_intervals(&records),
+            [16_000_000.0, 17_000_000.0, 17_000_000.0]
+        );
+        let summary = IntervalSummary::from_records(&records).unwrap();
+        assert_eq!(summary.n, 3);
+        assert_eq!(summary.median, 17_000_000.0);
+    }
+
+    #[test]
+    fn histogram_centres_bins_on_multiples_of_the_interval() {
+        // bins of 50 ns from -25 ns, so 100 ns sits in the middle of bin 2
+        let histogram =
+            Histogram::from_intervals(&[0.0, 24.0, 25.0, 74.0, 75.0, 100.0], 100.0, 5).unwrap();
+
+        assert_eq!(histogram.start, -25.0);
+        assert_eq!(histogram.width, 50.0);
+        assert_eq!(histogram.counts, [2, 2, 2, 0, 0]);
+    }
+
+    #[test]
+    fn histogram_counts_longer_intervals_in_the_last_bin() {
+        let histogram =
+            Histogram::from_intervals(&[200.0, 224.0, 225.0, 10_000.0], 100.0, 5).unwrap();
+
+        assert_eq!(histogram.counts, [0, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn histogram_without_intervals_or_bins_is_none() {
+        assert_eq!(Histogram::from_intervals(&[], 100.0, 5), None);
+        assert_eq!(Histogram::from_intervals(&[100.0], 100.0, 0), None);
+        assert_eq!(Histogram::from_intervals(&[100.0], 0.0, 5), None);
+    }
+
+    #[test]
+    fn beat_finds_the_period_of_a_modulation() {
+        let intervals: Vec<f64> = (0..200)
+            .map(|i| 16e6 + 1e5 * (std::f64::consts::TAU * i as f64 / 10.0).sin())
+            .collect();
+        let beat = Beat::detect(&intervals).unwrap();
+
+        assert_eq!(beat.period_frames, 10);
+        assert!((beat.period_ns - 160e6).abs() < 1e3, "{}", beat.period_ns);
+        assert!(beat.correlation > 0.9, "{}", beat.correlation);
+    }
+
+    #[test]
+    fn no_beat_in_steady_or_short_captures() {
+        assert_eq!(Beat::detect(&[16e6; 200]), None);
+
+        let short: Vec<f64> = (0..BEAT_MIN_SAMPLES - 1)
+            .map(|i| 16e6 + 1e5 * (i % 2) as f64)
+            .collect();
+        assert_eq!(Beat::detect(&short), None);
+    }
+
+    #[test]
+    fn diagnosis_of_steady_vblanks_is_clean_fifo() {
+        assert_eq!(
+            Diagnosis::classify(&steady(100), Some(16_000_000)),
+            Some(Diagnosis::CleanFifo)
+        );
+        assert_eq!(Diagnosis::classify(&steady(1), None), None);
+    }
+
+    #[test]
+    fn diagnosis_flags_early_frames_as_mailbox_reordering() {
+        let mut records = steady(100);
+        // five frames shown 4 ms after the previous one
+        for (i, record) in records.iter_mut().enumerate().skip(50).take(5) {
+            record.timestamp = 49 * 16_000_000 + (i as i64 - 49) * 4_000_000;
+        }
+
+        assert_eq!(
+            Diagnosis::classify(&records, Some(16_000_000)),
+            Some(Diagnosis::MailboxReordering { short_intervals: 5 })
+        );
+    }
+
+    #[test]
+    fn diagnosis_blames_slow_renders_on_the_gpu() {
+        let mut records = steady(100);
+        records.push(event(EventType::DroppedFrame, 10, 1));
+        records.push(event(EventType::GpuRender, 10, 15_500_000));
+        records.push(event(EventType::GpuRender, 11, 1_000_000));
+
+        assert_eq!(
+            Diagnosis::classify(&records, Some(16_000_000)),
+            Some(Diagnosis::GpuBound {
+                median_gpu_ns: 15_500_000.0
+            })
+        );
+    }
+
+    #[test]
+    fn diagnosis_blames_the_compositor_without_a_slow_gpu() {
+        let mut records = steady(100);
+        records.push(event(EventType::DroppedFrame, 10, 1));
+        assert_eq!(
+            Diagnosis::classify(&records, Some(16_000_000)),
+            Some(Diagnosis::CompositorInterference { gpu_timed: false })
+        );
+
+        records.push(event(EventType::GpuRender, 10, 1_000_000));
+        assert_eq!(
+            Diagnosis::classify(&records, Some(16_000_000)),
+            Some(Diagnosis::CompositorInterference { gpu_timed: true })
+        );
+    }
+
+    #[test]
+    fn photodiode_latency_from_the_onset_before_each_arrival() {
+        // lit on odd frames, so the onsets are at 16, 48 and 80 ms
+        let mut records: Vec<VBlankRecord> = (0..6)
+            .map(|i| VBlankRecord {
+                lit: i % 2 == 1,
+                ..vblank(i * 16_000_000, i, 0)
+            })
+            .collect();
+        for timestamp in [2_000_000, 26_000_000, 27_000_000, 60_000_000, 91_000_000] {
+            records.push(VBlankRecord {
+                timestamp,
+                ..event(EventType::PhotodiodeArrival, 0, 0)
+            });
+        }
+        let calibration = PhotodiodeCalibration::from_records(&records).unwrap();
+
+        assert_eq!(calibration.samples, 3);
+        // the line before any onset and the second one after 16 ms
+        assert_eq!(calibration.unmatched, 2);
+        assert!((calibration.mean_ns - 11_000_000.0).abs() < 1e-3);
+        assert!((calibration.std_dev_ns - (2e12f64 / 3.0).sqrt()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn photodiode_calibration_without_onsets_is_none() {
+        let mut records = steady(10);
+        records.push(VBlankRecord {
+            timestamp: 50_000_000,
+            ..event(EventType::PhotodiodeArrival, 0, 0)
+        });
+
+        assert_eq!(PhotodiodeCalibration::from_records(&records), None);
+    }
+
+    /// Vblanks after the given intervals, in ns.
+    fn vblanks_after(intervals: impl IntoIterator<Item = i64>) -> Vec<VBlankRecord> {
+        let mut timestamp = 0;
+        let mut records = vec![vblank(0, 0, 0)];
+        for (i, interval) in intervals.into_iter().enumerate() {
+            timestamp += interval;
+            records.push(vblank(timestamp, i as i64 + 1, 0));
+        }
+        records
+    }
+
+    #[test]
+    fn dropped_frames_stay_on_the_fixed_refresh_grid() {
+        let intervals = (0..100).map(|i| if i % 10 == 0 { 32_000_000 } else { 16_000_000 });
+        let regime = RefreshRegime::detect(&vblanks_after(intervals), Some(16_000_000)).unwrap();
+
+        assert!(!regime.variable);
+        assert_eq!(regime.off_grid_fraction, 0.0);
+    }
+
+    #[test]
+    fn intervals_between_refreshes_mean_variable_refresh() {
+        // every tenth frame took 20 ms, which a fixed 16 ms grid can't show
+        let intervals = (0..100).map(|i| if i % 10 == 0 { 20_000_000 } else { 16_000_000 });
+        let regime = RefreshRegime::detect(&vblanks_after(intervals), Some(16_000_000)).unwrap();
+
+        assert!(regime.variable);
+        assert_eq!(regime.off_grid_fraction, 0.1);
+    }
+
+    #[test]
+    fn refresh_regime_without_intervals_is_none() {
+        assert_eq!(RefreshRegime::detect(&steady(1), None), None);
+    }
+}
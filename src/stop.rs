@@ -0,0 +1,328 @@
+//! When a run of the capture is complete.
+
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::TimingSession;
+
+/// Frames collected per run when no stop condition is given.
+pub const DEFAULT_FRAMES: i64 = 1000;
+
+/// Something that ends the current run once it happens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopCondition {
+    /// Once `n` frames have been recorded, frame indices 0 to `n - 1`, the
+    /// same frames a `--synthetic` capture of `n` frames generates.
+    Frames(i64),
+    /// Once this much wall-clock time has passed since the warm-up ended.
+    Duration(Duration),
+    /// Once this many refreshes have been dropped in the run.
+    DroppedFrames(i64),
+    /// Once this much wall-clock time has passed since the program started,
+    /// over all runs and including the set-up. Ends the whole capture,
+    /// warm-up or postroll or not.
+    MaxRuntime(Duration),
+}
+
+impl StopCondition {
+    /// Whether no further runs should follow.
+    pub fn ends_capture(&self) -> bool {
+        matches!(self, StopCondition::MaxRuntime(_))
+    }
+}
+
+impl fmt::Display for StopCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopCondition::Frames(n) => write!(f, "{} frames collected", n),
+            StopCondition::Duration(d) => write!(f, "{:.1} s elapsed", d.as_secs_f64()),
+            StopCondition::DroppedFrames(n) => write!(f, "{} dropped frames", n),
+            StopCondition::MaxRuntime(d) => {
+                write!(f, "maximum runtime of {:.1} s reached", d.as_secs_f64())
+            }
+        }
+    }
+}
+
+/// What [`StopConditions::check`] looks at in a session.
+pub trait RunProgress {
+    /// See [`TimingSession::is_warming_up`].
+    fn is_warming_up(&self) -> bool;
+    /// See [`TimingSession::stimulus_frame_index`].
+    fn stimulus_frame_index(&self) -> i64;
+    /// See [`TimingSession::dropped_frame_count`].
+    fn dropped_frame_count(&self) -> i64;
+    /// See [`TimingSession::begin_postroll`].
+    fn begin_postroll(&mut self) -> bool;
+    /// See [`TimingSession::postroll_done`].
+    fn postroll_done(&self) -> bool;
+}
+
+impl RunProgress for TimingSession {
+    fn is_warming_up(&self) -> bool {
+        self.is_warming_up()
+    }
+
+    fn stimulus_frame_index(&self) -> i64 {
+        self.stimulus_frame_index()
+    }
+
+    fn dropped_frame_count(&self) -> i64 {
+        self.dropped_frame_count()
+    }
+
+    fn begin_postroll(&mut self) -> bool {
+        self.begin_postroll()
+    }
+
+    fn postroll_done(&self) -> bool {
+        self.postroll_done()
+    }
+}
+
+/// A set of stop conditions, of which the first one met ends the run.
+#[derive(Debug, Clone)]
+pub struct StopConditions {
+    conditions: Vec<StopCondition>,
+    // end of the warm-up, and the dropped count at that point
+    started: Option<(Instant, i64)>,
+    // the condition that ended the run, while its postroll is shown
+    met: Option<StopCondition>,
+    // the limit, and when it counts from
+    max_runtime: Option<(Duration, Instant)>,
+}
+
+impl StopConditions {
+    /// Falls back to [`DEFAULT_FRAMES`] frames if `conditions` is empty.
+    pub fn new(mut conditions: Vec<StopCondition>) -> Self {
+        if conditions.is_empty() {
+            conditions.push(StopCondition::Frames(DEFAULT_FRAMES));
+        }
+        Self {
+            conditions,
+            started: None,
+            met: None,
+            max_runtime: None,
+        }
+    }
+
+    /// Adds a [`StopCondition::MaxRuntime`] counting from `started`, which
+    /// takes precedence over all the others.
+    pub fn with_max_runtime(mut self, limit: Option<Duration>, started: Instant) -> Self {
+        self.max_runtime = limit.map(|limit| (limit, started));
+        self
+    }
+
+    /// The frame limit, if there is one, e.g. for showing progress.
+    pub fn frames(&self) -> Option<i64> {
+        self.conditions.iter().find_map(|c| match c {
+            StopCondition::Frames(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    /// Checks the conditions against the session after a frame. Returns the
+    /// one that ended the run, if any; never while warming up. Once one is
+    /// met, the session's postroll frames are shown before it is returned.
+    /// The maximum runtime is the exception to both.
+    pub fn check(&mut self, session: &mut impl RunProgress) -> Option<StopCondition> {
+        if let Some((limit, _)) = self
+            .max_runtime
+            .filter(|(limit, started)| started.elapsed() >= *limit)
+        {
+            return Some(StopCondition::MaxRuntime(limit));
+        }
+        if session.is_warming_up() {
+            return None;
+        }
+        if let Some(condition) = self.met {
+            return session.postroll_done().then_some(condition);
+        }
+        let (start, dropped_at_start) = *self
+            .started
+            .get_or_insert_with(|| (Instant::now(), session.dropped_frame_count()));
+        let dropped = session.dropped_frame_count() - dropped_at_start;
+
+        let condition = self
+            .conditions
+            .iter()
+            .copied()
+            .find(|condition| match *condition {
+                StopCondition::Frames(n) => session.stimulus_frame_index() >= n,
+                StopCondition::Duration(d) => start.elapsed() >= d,
+                StopCondition::DroppedFrames(n) => dropped >= n,
+                StopCondition::MaxRuntime(_) => false,
+            })?;
+        if session.begin_postroll() {
+            self.met = Some(condition);
+            return None;
+        }
+        Some(condition)
+    }
+
+    /// Starts counting from scratch for the next run.
+    pub fn next_run(&mut self) {
+        self.started = None;
+        self.met = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A run without postroll, right after its warm-up.
+    #[derive(Default)]
+    struct Run {
+        frame_index: i64,
+        dropped: i64,
+        postroll_left: Option<u32>,
+        postroll: u32,
+    }
+
+    impl RunProgress for Run {
+        fn is_warming_up(&self) -> bool {
+            self.frame_index < 0
+        }
+
+        fn stimulus_frame_index(&self) -> i64 {
+            self.frame_index
+        }
+
+        fn dropped_frame_count(&self) -> i64 {
+            self.dropped
+        }
+
+        fn begin_postroll(&mut self) -> bool {
+            if self.postroll == 0 {
+                return false;
+            }
+            self.postroll_left.get_or_insert(self.postroll);
+            true
+        }
+
+        fn postroll_done(&self) -> bool {
+            self.postroll_left == Some(0)
+        }
+    }
+
+    #[test]
+    fn frames_by_default() {
+        let mut stop = StopConditions::new(Vec::new());
+        let mut run = Run {
+            frame_index: DEFAULT_FRAMES - 1,
+            ..Run::default()
+        };
+
+        assert_eq!(stop.frames(), Some(DEFAULT_FRAMES));
+        assert_eq!(stop.check(&mut run), None);
+        run.frame_index += 1;
+        assert_eq!(
+            stop.check(&mut run),
+            Some(StopCondition::Frames(DEFAULT_FRAMES))
+        );
+    }
+
+    #[test]
+    fn nothing_ends_the_warm_up() {
+        let mut stop = StopConditions::new(vec![
+            StopCondition::Frames(0),
+            StopCondition::Duration(Duration::ZERO),
+            StopCondition::DroppedFrames(0),
+        ]);
+        let mut run = Run {
+            frame_index: -1,
+            ..Run::default()
+        };
+
+        assert_eq!(stop.check(&mut run), None);
+    }
+
+    #[test]
+    fn duration_counts_from_the_end_of_the_warm_up() {
+        let mut stop = StopConditions::new(vec![StopCondition::Duration(Duration::ZERO)]);
+        assert_eq!(
+            stop.check(&mut Run::default()),
+            Some(StopCondition::Duration(Duration::ZERO))
+        );
+
+        let mut stop =
+            StopConditions::new(vec![StopCondition::Duration(Duration::from_secs(3600))]);
+        assert_eq!(stop.check(&mut Run::default()), None);
+    }
+
+    #[test]
+    fn dropped_frames_count_from_the_end_of_the_warm_up() {
+        let mut stop = StopConditions::new(vec![StopCondition::DroppedFrames(2)]);
+        // dropped while warming up
+        let mut run = Run {
+            dropped: 5,
+            ..Run::default()
+        };
+
+        assert_eq!(stop.check(&mut run), None);
+        run.dropped = 6;
+        assert_eq!(stop.check(&mut run), None);
+        run.dropped = 7;
+        assert_eq!(stop.check(&mut run), Some(StopCondition::DroppedFrames(2)));
+
+        // and from scratch in the next run
+        stop.next_run();
+        assert_eq!(stop.check(&mut run), None);
+    }
+
+    #[test]
+    fn first_condition_met_wins() {
+        let mut stop = StopConditions::new(vec![
+            StopCondition::Frames(100),
+            StopCondition::DroppedFrames(1),
+        ]);
+        let mut run = Run {
+            frame_index: 50,
+            ..Run::default()
+        };
+
+        assert_eq!(stop.check(&mut run), None);
+        run.dropped = 1;
+        assert_eq!(stop.check(&mut run), Some(StopCondition::DroppedFrames(1)));
+    }
+
+    #[test]
+    fn met_condition_waits_for_the_postroll() {
+        let mut stop = StopConditions::new(vec![StopCondition::Frames(10)]);
+        let mut run = Run {
+            frame_index: 10,
+            postroll: 2,
+            ..Run::default()
+        };
+
+        assert_eq!(stop.check(&mut run), None);
+        assert_eq!(run.postroll_left, Some(2));
+        run.postroll_left = Some(1);
+        assert_eq!(stop.check(&mut run), None);
+        run.postroll_left = Some(0);
+        assert_eq!(stop.check(&mut run), Some(StopCondition::Frames(10)));
+    }
+
+    #[test]
+    fn max_runtime_counts_from_the_given_start() {
+        let limit = Duration::from_secs(60);
+        let mut run = Run {
+            frame_index: -1,
+            ..Run::default()
+        };
+
+        let mut stop =
+            StopConditions::new(Vec::new()).with_max_runtime(Some(limit), Instant::now());
+        assert_eq!(stop.check(&mut run), None);
+
+        // started a minute ago, before this run's warm-up
+        let started = Instant::now().checked_sub(limit).unwrap();
+        let mut stop = StopConditions::new(Vec::new()).with_max_runtime(Some(limit), started);
+        let condition = stop.check(&mut run);
+        assert_eq!(condition, Some(StopCondition::MaxRuntime(limit)));
+        assert!(condition.unwrap().ends_capture());
+    }
+}
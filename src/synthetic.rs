@@ -0,0 +1,332 @@
+//! Seeded stand-in for a capture, producing the records a real one would
+//! without a GPU or display.
+//!
+//! This exercises everything downstream of the capture (the DataFrame,
+//! output files and statistics) reproducibly: the same seed and settings
+//! always give the same records. A [`Replay`] takes the vblank intervals
+//! from a real capture instead, for realistic input without the display.
+
+use serde::Serialize;
+
+use crate::{pattern::Pattern, stats, EventType, VBlankRecord};
+
+/// How long after its vblank a synthetic frame is reported as displayed on
+/// the CPU, before jitter, in ns.
+const CPU_LATENCY_NS: i64 = 500_000;
+
+/// Settings of a synthetic capture, also recorded in the metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyntheticCapture {
+    /// Refresh interval in ns.
+    pub interval_ns: i64,
+    /// Vblank times are off by up to this much either way, in ns.
+    pub jitter_ns: i64,
+    /// Probability that a frame misses its vblank and is shown one refresh late.
+    pub drop_rate: f64,
+    pub seed: u64,
+    /// See [`SessionConfig::long_frame_tolerance`](crate::SessionConfig::long_frame_tolerance).
+    pub long_frame_tolerance: f64,
+    /// Intervals replayed from a real capture instead of `interval_ns`. The
+    /// jitter and drops above are added on top.
+    pub replay: Option<Replay>,
+}
+
+impl SyntheticCapture {
+    /// Records for `frames` frames of run `run_id`, lit as `pattern` says: a
+    /// `sync_qpc_time` and `cpu_time` row per frame, and `dropped_frame` and
+    /// `long_frame` rows where a frame was late. Each run draws from its own
+    /// stream of the seed, so repeats differ; a sequential [`Replay`] starts
+    /// over in each.
+    pub fn generate(&self, frames: i64, pattern: &Pattern, run_id: u32) -> Vec<VBlankRecord> {
+        let mut rng = SplitMix64(self.seed.wrapping_add(run_id as u64));
+        let mut records = Vec::with_capacity(frames.max(0) as usize * 2);
+        let mut refresh_count = 0;
+        // vblank time before jitter
+        let mut vblank_time = 0;
+        let mut last_vblank = None;
+
+        for frame_index in 0..frames {
+            let lit = pattern.is_on(frame_index);
+            let record = |timestamp, count, event_type| VBlankRecord {
+                timestamp,
+                count,
+                event_type,
+                frame_index,
+                lit,
+                run_id,
+                monitor_id: 0,
+            };
+
+            let late = (rng.next_f64() < self.drop_rate) as i64;
+            let jitter = ((rng.next_f64() * 2.0 - 1.0) * self.jitter_ns as f64).round() as i64;
+            let (interval, replay_missed) = match &self.replay {
+                Some(replay) => {
+                    let interval = replay.interval(frame_index, &mut rng);
+                    // a replayed interval spanning several refreshes was a drop
+                    let refreshes =
+                        (interval as f64 / self.interval_ns as f64).round().max(1.0) as i64;
+                    (interval, refreshes - 1)
+                }
+                None => (self.interval_ns, 0),
+            };
+            let missed = late + replay_missed;
+            refresh_count += 1 + missed;
+            vblank_time += interval + late * self.interval_ns;
+            let vblank = vblank_time + jitter;
+
+            records.push(record(vblank, refresh_count, EventType::SyncQpcTime));
+            if let Some(last) = last_vblank {
+                let interval = vblank - last;
+                if stats::is_long_frame(
+                    interval as f64,
+                    self.interval_ns as f64,
+                    self.long_frame_tolerance,
+                ) {
+                    records.push(record(vblank, interval, EventType::LongFrame));
+                }
+            }
+            if missed > 0 {
+                records.push(record(vblank, missed, EventType::DroppedFrame));
+            }
+            records.push(record(
+                vblank + CPU_LATENCY_NS + jitter.abs(),
+                refresh_count,
+                EventType::CpuTime,
+            ));
+            last_vblank = Some(vblank);
+        }
+
+        records
+    }
+}
+
+/// Vblank intervals taken from a real capture (`--seed-from-capture`).
+#[derive(Debug, Clone, Serialize)]
+pub struct Replay {
+    /// The capture file they were read from.
+    pub source: String,
+    pub order: ReplayOrder,
+    /// The intervals in ns, in the order they were captured.
+    #[serde(skip)]
+    pub intervals: Vec<i64>,
+}
+
+/// How [`Replay`] intervals are drawn.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplayOrder {
+    /// The exact sequence, repeated if the replay is longer than the capture.
+    Sequence,
+    /// Drawn at random from the captured intervals with the seed, keeping
+    /// their distribution but not their order.
+    Shuffle,
+}
+
+impl Replay {
+    /// Takes the vblank intervals of monitor 0 in `records`, run by run.
+    /// `None` if there are none.
+    pub fn from_records(
+        source: String,
+        records: &[VBlankRecord],
+        order: ReplayOrder,
+    ) -> Option<Self> {
+        let mut intervals = Vec::new();
+        let mut last: Option<&VBlankRecord> = None;
+        for record in records
+            .iter()
+            .filter(|r| r.event_type == EventType::SyncQpcTime && r.monitor_id == 0)
+        {
+            match last {
+                // the same vblank reported again
+                Some(prev) if prev.run_id == record.run_id && prev.count == record.count => {
+                    continue
+                }
+                Some(prev) if prev.run_id == record.run_id => {
+                    intervals.push(record.timestamp - prev.timestamp)
+                }
+                _ => {}
+            }
+            last = Some(record);
+        }
+        intervals.retain(|&interval| interval > 0);
+        (!intervals.is_empty()).then_some(Self {
+            source,
+            order,
+            intervals,
+        })
+    }
+
+    /// Median of the intervals in ns, the refresh interval they replay.
+    pub fn median_interval(&self) -> i64 {
+        let mut sorted = self.intervals.clone();
+        sorted.sort_unstable();
+        sorted[sorted.len() / 2]
+    }
+
+    fn interval(&self, frame_index: i64, rng: &mut SplitMix64) -> i64 {
+        let index = match self.order {
+            ReplayOrder::Sequence => frame_index as usize,
+            ReplayOrder::Shuffle => rng.next_u64() as usize,
+        };
+        self.intervals[index % self.intervals.len()]
+    }
+}
+
+/// The SplitMix64 generator: tiny, fast and good enough for test data, and
+/// its output never changes between versions of a dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL_NS: i64 = 16_666_667;
+
+    fn capture(seed: u64, drop_rate: f64, replay: Option<Replay>) -> SyntheticCapture {
+        SyntheticCapture {
+            interval_ns: INTERVAL_NS,
+            jitter_ns: 100_000,
+            drop_rate,
+            seed,
+            long_frame_tolerance: 0.5,
+            replay,
+        }
+    }
+
+    fn replay(intervals: Vec<i64>, order: ReplayOrder) -> Replay {
+        Replay {
+            source: "test.csv".to_string(),
+            order,
+            intervals,
+        }
+    }
+
+    fn vblank(timestamp: i64, count: i64, run_id: u32) -> VBlankRecord {
+        VBlankRecord {
+            timestamp,
+            count,
+            event_type: EventType::SyncQpcTime,
+            frame_index: 0,
+            lit: true,
+            run_id,
+            monitor_id: 0,
+        }
+    }
+
+    fn rows(records: &[VBlankRecord]) -> Vec<(i64, i64, EventType, i64, bool, u32)> {
+        records
+            .iter()
+            .map(|r| {
+                (
+                    r.timestamp,
+                    r.count,
+                    r.event_type,
+                    r.frame_index,
+                    r.lit,
+                    r.run_id,
+                )
+            })
+            .collect()
+    }
+
+    fn vblank_intervals(records: &[VBlankRecord]) -> Vec<i64> {
+        let vblanks: Vec<i64> = records
+            .iter()
+            .filter(|r| r.event_type == EventType::SyncQpcTime)
+            .map(|r| r.timestamp)
+            .collect();
+        vblanks.windows(2).map(|pair| pair[1] - pair[0]).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_records() {
+        let pattern = Pattern::default();
+        let a = capture(7, 0.1, None).generate(500, &pattern, 0);
+        let b = capture(7, 0.1, None).generate(500, &pattern, 0);
+        let other = capture(8, 0.1, None).generate(500, &pattern, 0);
+
+        assert_eq!(rows(&a), rows(&b));
+        assert_ne!(rows(&a), rows(&other));
+    }
+
+    #[test]
+    fn no_drop_rate_drops_nothing() {
+        let records = capture(3, 0.0, None).generate(1000, &Pattern::default(), 0);
+
+        assert!(records
+            .iter()
+            .all(|r| r.event_type != EventType::DroppedFrame));
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| r.event_type == EventType::SyncQpcTime)
+                .count(),
+            1000
+        );
+    }
+
+    #[test]
+    fn sequence_replay_wraps_around() {
+        let mut capture = capture(
+            0,
+            0.0,
+            Some(replay(vec![16_000_000, 17_000_000], ReplayOrder::Sequence)),
+        );
+        capture.jitter_ns = 0;
+        let records = capture.generate(5, &Pattern::default(), 0);
+
+        assert_eq!(
+            vblank_intervals(&records),
+            [17_000_000, 16_000_000, 17_000_000, 16_000_000]
+        );
+        assert!(records
+            .iter()
+            .all(|r| r.event_type != EventType::DroppedFrame));
+    }
+
+    #[test]
+    fn replay_skips_repeated_vblanks_and_run_boundaries() {
+        let records = [
+            vblank(0, 1, 0),
+            vblank(0, 1, 0),
+            vblank(16_000_000, 2, 0),
+            vblank(33_000_000, 3, 0),
+            // a new run starting over
+            vblank(1_000_000, 1, 1),
+            vblank(18_000_000, 2, 1),
+        ];
+        let replay =
+            Replay::from_records("test.csv".to_string(), &records, ReplayOrder::Sequence).unwrap();
+
+        assert_eq!(replay.intervals, [16_000_000, 17_000_000, 17_000_000]);
+        assert!(
+            Replay::from_records("test.csv".to_string(), &records[..2], ReplayOrder::Sequence)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn median_interval_of_odd_and_even_lengths() {
+        let odd = replay(vec![30, 10, 20], ReplayOrder::Sequence);
+        assert_eq!(odd.median_interval(), 20);
+
+        // the upper of the two middle values
+        let even = replay(vec![40, 10, 30, 20], ReplayOrder::Sequence);
+        assert_eq!(even.median_interval(), 30);
+    }
+}
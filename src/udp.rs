@@ -0,0 +1,91 @@
+//! UDP datagrams announcing stimulus onsets, for acquisition machines on the
+//! lab network.
+//!
+//! One datagram is sent for every displayed frame that switches the stimulus
+//! on (lit after an unlit frame, or the first lit frame of the capture). Each
+//! is [`ONSET_PACKET_SIZE`] bytes, all integers little-endian:
+//!
+//! | Offset | Type     | Field                                              |
+//! |--------|----------|----------------------------------------------------|
+//! | 0      | `[u8; 4]`| magic, `b"TMON"`                                   |
+//! | 4      | `i64`    | frame index within the run                         |
+//! | 12     | `i64`    | vblank time in ns of the present clock (QPC on Windows) |
+//!
+//! Sending happens on a background thread, so a slow network never delays the
+//! capture loop; onsets queued while the socket is blocked are still sent in
+//! order.
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Sender},
+    thread,
+};
+
+use crate::VBlankRecord;
+
+/// Marks a datagram as an onset, see the module docs.
+pub const ONSET_MAGIC: [u8; 4] = *b"TMON";
+pub const ONSET_PACKET_SIZE: usize = 20;
+
+/// Encodes an onset as described in the module docs.
+pub fn encode_onset(frame_index: i64, timestamp_ns: i64) -> [u8; ONSET_PACKET_SIZE] {
+    let mut packet = [0; ONSET_PACKET_SIZE];
+    packet[..4].copy_from_slice(&ONSET_MAGIC);
+    packet[4..12].copy_from_slice(&frame_index.to_le_bytes());
+    packet[12..].copy_from_slice(&timestamp_ns.to_le_bytes());
+    packet
+}
+
+pub struct UdpOnsetSender {
+    tx: Sender<[u8; ONSET_PACKET_SIZE]>,
+    last_lit: bool,
+}
+
+impl UdpOnsetSender {
+    /// Binds an ephemeral local socket and starts the sender thread for
+    /// datagrams to `target`, which may be a broadcast address.
+    pub fn new(target: SocketAddr) -> io::Result<Self> {
+        let local: SocketAddr = if target.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_broadcast(true)?;
+        socket.connect(target)?;
+
+        let (tx, rx) = mpsc::channel::<[u8; ONSET_PACKET_SIZE]>();
+        thread::Builder::new()
+            .name("udp onsets".to_string())
+            .spawn(move || {
+                // ends once the sender is dropped
+                for packet in rx {
+                    if let Err(e) = socket.send(&packet) {
+                        log::warn!("Failed to send UDP onset: {}", e);
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            tx,
+            last_lit: false,
+        })
+    }
+
+    /// Queues a datagram if `vblank` showed a stimulus onset.
+    /// `present_clock_start_ns` turns its relative timestamp back into present
+    /// clock time.
+    pub fn on_vblank(&mut self, vblank: &VBlankRecord, present_clock_start_ns: i64) {
+        let onset = vblank.lit && !self.last_lit;
+        self.last_lit = vblank.lit;
+        if onset {
+            let packet = encode_onset(
+                vblank.frame_index,
+                present_clock_start_ns + vblank.timestamp,
+            );
+            // only fails if the thread is gone, which it logged already
+            let _ = self.tx.send(packet);
+        }
+    }
+}
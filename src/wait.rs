@@ -0,0 +1,83 @@
+//! Strategies for waiting until a presented frame has reached the display.
+
+use std::time::Duration;
+
+use crate::present::{PresentStats, PresentTimingSource};
+
+/// How long before the expected vblank the hybrid strategy stops sleeping.
+const HYBRID_SPIN_MARGIN_NS: i64 = 1_500_000;
+/// Sleep between polls while far from the vblank.
+const SLEEP_SLICE: Duration = Duration::from_micros(250);
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaitMode {
+    /// Poll back-to-back; most precise, pins a CPU core
+    Spin,
+    /// Sleep until shortly before the expected vblank, then spin; as precise
+    /// as spin once the refresh interval is known, at a fraction of the CPU
+    #[default]
+    Hybrid,
+    /// Sleep briefly between every poll; lowest CPU use
+    Sleep,
+    /// Block on the swapchain's frame latency waitable object (DX12), then
+    /// poll with sleeps; like sleep on other backends
+    Waitable,
+}
+
+/// Polls `source` until it reports a present count other than `last_present_count`.
+///
+/// `expected_vblank` is the predicted time of the next vblank in the source's
+/// clock; the hybrid mode falls back to spinning when it is unknown. Returns
+/// the new statistics and how many times `source` was polled for them, or
+/// `None` if nothing new is reported within `timeout`.
+pub fn wait_for_present(
+    source: &mut dyn PresentTimingSource,
+    surface: &wgpu::Surface,
+    last_present_count: u32,
+    mode: WaitMode,
+    expected_vblank: Option<i64>,
+    timeout: Option<Duration>,
+) -> Option<(PresentStats, u32)> {
+    let deadline = timeout.map(|t| source.now() + t.as_nanos() as i64);
+
+    match (mode, expected_vblank) {
+        (WaitMode::Hybrid, Some(expected)) => sleep_until_near(source, expected),
+        (WaitMode::Waitable, _) => {
+            // the statistics may lag the signal slightly, which the loop below covers
+            if !source.wait_until_ready(surface, timeout) {
+                log::debug!("Frame latency wait unavailable or timed out, pacing like hybrid");
+                if let Some(expected) = expected_vblank {
+                    sleep_until_near(source, expected);
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut present_stats = source.latest(surface).unwrap_or_default();
+    let mut polls = 1;
+
+    // busy wait until the flip count changes
+    while present_stats.present_count == last_present_count {
+        match mode {
+            WaitMode::Spin => {}
+            WaitMode::Hybrid => std::hint::spin_loop(),
+            WaitMode::Sleep | WaitMode::Waitable => std::thread::sleep(SLEEP_SLICE),
+        }
+        present_stats = source.latest(surface).unwrap_or_default();
+        polls += 1;
+
+        if deadline.is_some_and(|deadline| source.now() > deadline) {
+            return None;
+        }
+    }
+
+    Some((present_stats, polls))
+}
+
+/// Sleeps until shortly before `expected`, in the clock of `source`.
+fn sleep_until_near(source: &dyn PresentTimingSource, expected: i64) {
+    while source.now() < expected - HYBRID_SPIN_MARGIN_NS {
+        std::thread::sleep(SLEEP_SLICE);
+    }
+}